@@ -13,13 +13,32 @@ extern "C" {
     // fn kv_set(kptr: *const u8, klen: i32, v: *const u8, vlen: i32);
     // fn kv_get_len(kptr: *const u8, klen: i32, vlen: &mut i32, id: &mut i32);
     // fn kv_get(id: i32, vptr: *const u8);
-    fn kv_batch_ope(ope_ptr: *const i32, ope_len: i32, ope_id: &mut i32);
-    fn kv_batch_res(ope_id: i32, args_ptr: *const i32, args_len: i32);
-    fn open_file(fname: *const u8, fnamelen: i32, fd: &mut i32);
-    fn read_file_at(fd: i32, buf: *const u8, buflen: i32, offset: i32, readlen: &mut i32);
-    pub fn write_result(res_ptr: *const u8, res_len: i32);
+    // host functions return HOST_OK or a negative error code
+    fn kv_batch_ope(ope_ptr: *const i32, ope_len: i32, ope_id: &mut i32) -> i32;
+    fn kv_batch_res(ope_id: i32, args_ptr: *const i32, args_len: i32) -> i32;
+    fn kv_event_kind(kind: &mut i32) -> i32;
+    fn kv_event_revision(revision: &mut u64) -> i32;
+    fn open_file(fname: *const u8, fnamelen: i32, fd: &mut i32) -> i32;
+    fn read_file_at(fd: i32, buf: *const u8, buflen: i32, offset: i32, readlen: &mut i32) -> i32;
+    pub fn write_result(res_ptr: *const u8, res_len: i32) -> i32;
+    fn write_http_response(
+        status: i32,
+        headers_ptr: *const u8,
+        headers_len: i32,
+        body_ptr: *const u8,
+        body_len: i32,
+    ) -> i32;
 }
 
+/// returned by host functions when succeeded
+pub const HOST_OK: i32 = 0;
+/// pointer or length out of the memory, the invocation is failed by host
+pub const HOST_ERR_MEMORY: i32 = -1;
+/// malformed arguments, the invocation is failed by host
+pub const HOST_ERR_ARG: i32 = -2;
+/// the operation failed on host, like kv request failure
+pub const HOST_ERR_FAILED: i32 = -3;
+
 #[cfg(not(feature = "test"))]
 pub trait KvResTrans {
     fn res_str(&self) -> Option<&str>;
@@ -28,7 +47,7 @@ pub trait KvResTrans {
 #[cfg(not(feature = "test"))]
 impl KvResTrans for Vec<KvResult> {
     fn res_str(&self) -> Option<&str> {
-        if let KvResult::Get(Some(s)) = &self[0] {
+        if let KvResult::Get { value: Some(s), .. } = &self[0] {
             return std::str::from_utf8(s).map_or_else(
                 |err| {
                     println!("err when get kv res str:{}", err);
@@ -57,26 +76,117 @@ const GET_ID: i32 = 2;
 const LOCK_ID: i32 = 3;
 #[cfg(not(feature = "test"))]
 const DELETE_ID: i32 = 4;
+#[cfg(not(feature = "test"))]
+const SCAN_ID: i32 = 5;
+#[cfg(not(feature = "test"))]
+const DELETE_RANGE_ID: i32 = 6;
+#[cfg(not(feature = "test"))]
+const CAS_ID: i32 = 7;
+#[cfg(not(feature = "test"))]
+const ADD_ID: i32 = 8;
+#[cfg(not(feature = "test"))]
+const SET_TTL_ID: i32 = 9;
+#[cfg(not(feature = "test"))]
+const GUARD_ID: i32 = 10;
+#[cfg(not(feature = "test"))]
+const GET_REVISION_ID: i32 = 11;
+#[cfg(not(feature = "test"))]
+const CAS_VERSION_ID: i32 = 12;
+/// set in the operation count if the batch is a transaction
+#[cfg(not(feature = "test"))]
+const TXN_FLAG: i32 = 1 << 30;
+
+// scan results are encoded as `[key_len: u32 le][key][value_len: u32 le][value]...`
+#[cfg(not(feature = "test"))]
+fn decode_kvs(buf: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut kvs = Vec::new();
+    let mut offset = 0;
+    let next_part = |offset: &mut usize| {
+        let len = u32::from_le_bytes(buf[*offset..*offset + 4].try_into().unwrap()) as usize;
+        *offset += 4;
+        let part = buf[*offset..*offset + len].to_vec();
+        *offset += len;
+        part
+    };
+    while offset < buf.len() {
+        let key = next_part(&mut offset);
+        let value = next_part(&mut offset);
+        kvs.push((key, value));
+    }
+    kvs
+}
 
 pub enum KvResult {
     Set,
-    GetLen(i32),
-    Get(Option<Vec<u8>>),
+    // len of value, -1 if not exist, and version
+    GetLen([i64; 2]),
+    /// `version` is increased by each write of the key from 1, 0 if not exist,
+    /// it restarts from 1 after the key's deleted, so it only orders the writes
+    /// since the key's created
+    Get {
+        value: Option<Vec<u8>>,
+        version: u64,
+    },
     Delete,
     Lock(u32),
     Unlock,
+    ScanLen(i32),
+    // ordered by key
+    Scan(Vec<(Vec<u8>, Vec<u8>)>),
+    DeleteRange,
+    // succ, len of current value
+    CasLen([i32; 2]),
+    // current value is the new value if succeeded
+    Cas {
+        succ: bool,
+        current: Option<Vec<u8>>,
+    },
+    // succ, new value
+    AddRes([i64; 2]),
+    // None if the value is not an integer
+    Add(Option<i64>),
+    GuardRes(i32),
+    // false if the guard failed, the transaction is aborted
+    Guard(bool),
 }
 
 impl KvResult {
+    /// result of the operation in a failed batch
+    #[cfg(not(feature = "test"))]
+    fn into_failed(self) -> Self {
+        match self {
+            KvResult::GetLen(_) => KvResult::Get {
+                value: None,
+                version: 0,
+            },
+            KvResult::ScanLen(_) => KvResult::Scan(Vec::new()),
+            KvResult::CasLen(_) => KvResult::Cas {
+                succ: false,
+                current: None,
+            },
+            KvResult::AddRes(_) => KvResult::Add(None),
+            KvResult::GuardRes(_) => KvResult::Guard(false),
+            res => res,
+        }
+    }
     #[cfg(not(feature = "test"))]
     fn one_ptr(&self) -> Option<i32> {
         match self {
             KvResult::Set => None,
-            KvResult::GetLen(len) => Some(len as *const i32 as i32),
-            KvResult::Get(vec) => vec.as_ref().map(|v| v.as_ptr() as i32),
+            KvResult::GetLen(res) => Some(res.as_ptr() as i32),
+            KvResult::Get { value, .. } => value.as_ref().map(|v| v.as_ptr() as i32),
             KvResult::Delete => None,
             KvResult::Lock(lockid) => Some(lockid as *const u32 as i32),
             KvResult::Unlock => None,
+            KvResult::ScanLen(len) => Some(len as *const i32 as i32),
+            KvResult::Scan(_) => None,
+            KvResult::DeleteRange => None,
+            KvResult::CasLen(res) => Some(res.as_ptr() as i32),
+            KvResult::Cas { current, .. } => current.as_ref().map(|v| v.as_ptr() as i32),
+            KvResult::AddRes(res) => Some(res.as_ptr() as i32),
+            KvResult::Add(_) => None,
+            KvResult::GuardRes(res) => Some(res as *const i32 as i32),
+            KvResult::Guard(_) => None,
         }
     }
 }
@@ -89,6 +199,8 @@ lazy_static::lazy_static! {
 #[cfg(feature = "test")]
 struct KvBatchInner {
     map: BTreeMap<Vec<u8>, Vec<u8>>,
+    // versions of the keys in `map`, old values are not kept
+    versions: BTreeMap<Vec<u8>, u64>,
 }
 
 #[cfg(feature = "test")]
@@ -96,42 +208,182 @@ impl KvBatchInner {
     pub fn new() -> Self {
         Self {
             map: BTreeMap::new(),
+            versions: BTreeMap::new(),
         }
     }
+    fn insert(&mut self, key: &[u8], value: Vec<u8>) {
+        self.map.insert(key.to_vec(), value);
+        *self.versions.entry(key.to_vec()).or_insert(0) += 1;
+    }
+    fn version(&self, key: &[u8]) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+    fn retain(&mut self, keep: impl Fn(&[u8]) -> bool) {
+        self.map.retain(|k, _| keep(k));
+        self.versions.retain(|k, _| keep(k));
+    }
 }
 
 #[cfg(feature = "test")]
 pub struct KvBatch {
     res: Vec<KvResult>,
+    // map and versions before the transaction, restored if any guard failed
+    txn_snapshot: Option<(BTreeMap<Vec<u8>, Vec<u8>>, BTreeMap<Vec<u8>, u64>)>,
 }
 
 // moke impl
 #[cfg(feature = "test")]
 impl KvBatch {
     pub fn new() -> Self {
-        Self { res: Vec::new() }
+        Self {
+            res: Vec::new(),
+            txn_snapshot: None,
+        }
     }
     pub fn reset(mut self) -> Self {
         self.res.clear();
+        self.txn_snapshot = None;
+        self
+    }
+    pub fn transaction(mut self) -> Self {
+        let k = KV_BATCH.lock();
+        self.txn_snapshot = Some((k.map.clone(), k.versions.clone()));
+        self
+    }
+    pub fn then_guard(mut self, key: &[u8], expected: Option<&[u8]>) -> Self {
+        let k = KV_BATCH.lock();
+        self.res
+            .push(KvResult::Guard(k.map.get(key).map(|v| v.as_slice()) == expected));
         self
     }
     pub fn then_set(mut self, key: &[u8], value: &[u8]) -> Self {
         let mut k = KV_BATCH.lock();
-        k.map.insert(key.to_vec(), value.to_vec());
+        k.insert(key, value.to_vec());
         self.res.push(KvResult::Set);
         self
     }
+    /// expiry is not simulated in test
+    pub fn then_set_ttl(self, key: &[u8], value: &[u8], _ttl_secs: u32) -> Self {
+        self.then_set(key, value)
+    }
     pub fn then_get(mut self, key: &[u8]) -> Self {
         let k = KV_BATCH.lock();
-        self.res.push(KvResult::Get(k.map.get(key).cloned()));
+        self.res.push(KvResult::Get {
+            value: k.map.get(key).cloned(),
+            version: k.version(key),
+        });
+        self
+    }
+    /// old values are not kept in test, only the latest one is got
+    pub fn then_get_at(mut self, key: &[u8], revision: u64) -> Self {
+        let k = KV_BATCH.lock();
+        let res = if k.version(key) == revision {
+            KvResult::Get {
+                value: k.map.get(key).cloned(),
+                version: revision,
+            }
+        } else {
+            tracing::warn!("test get of old version not implemented");
+            KvResult::Get {
+                value: None,
+                version: 0,
+            }
+        };
+        self.res.push(res);
         self
     }
     pub fn then_delete(mut self, key: &[u8]) -> Self {
         let mut k = KV_BATCH.lock();
-        k.map.remove(key);
+        k.retain(|k| k != key);
         self.res.push(KvResult::Delete);
         self
     }
+    /// keys in [start, end)
+    pub fn then_scan(mut self, start: &[u8], end: &[u8]) -> Self {
+        let k = KV_BATCH.lock();
+        let kvs = if start < end {
+            k.map
+                .range(start.to_vec()..end.to_vec())
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        self.res.push(KvResult::Scan(kvs));
+        self
+    }
+    pub fn then_scan_prefix(mut self, prefix: &[u8]) -> Self {
+        let k = KV_BATCH.lock();
+        let kvs = k
+            .map
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self.res.push(KvResult::Scan(kvs));
+        self
+    }
+    /// keys in [start, end)
+    pub fn then_delete_range(mut self, start: &[u8], end: &[u8]) -> Self {
+        let mut k = KV_BATCH.lock();
+        k.retain(|k| !(start <= k && k < end));
+        self.res.push(KvResult::DeleteRange);
+        self
+    }
+    pub fn then_delete_prefix(mut self, prefix: &[u8]) -> Self {
+        let mut k = KV_BATCH.lock();
+        k.retain(|k| !k.starts_with(prefix));
+        self.res.push(KvResult::DeleteRange);
+        self
+    }
+    pub fn then_cas(mut self, key: &[u8], expected: Option<&[u8]>, new: &[u8]) -> Self {
+        let mut k = KV_BATCH.lock();
+        let current = k.map.get(key).cloned();
+        if current.as_deref() == expected {
+            k.insert(key, new.to_vec());
+            self.res.push(KvResult::Cas {
+                succ: true,
+                current: Some(new.to_vec()),
+            });
+        } else {
+            self.res.push(KvResult::Cas {
+                succ: false,
+                current,
+            });
+        }
+        self
+    }
+    pub fn then_cas_version(mut self, key: &[u8], expected_version: u64, new: &[u8]) -> Self {
+        let mut k = KV_BATCH.lock();
+        if k.version(key) == expected_version {
+            k.insert(key, new.to_vec());
+            self.res.push(KvResult::Cas {
+                succ: true,
+                current: Some(new.to_vec()),
+            });
+        } else {
+            self.res.push(KvResult::Cas {
+                succ: false,
+                current: k.map.get(key).cloned(),
+            });
+        }
+        self
+    }
+    pub fn then_add(mut self, key: &[u8], delta: i64) -> Self {
+        let mut k = KV_BATCH.lock();
+        let new = match k.map.get(key) {
+            None => Some(delta),
+            Some(v) => std::str::from_utf8(v)
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .and_then(|v| v.checked_add(delta)),
+        };
+        if let Some(new) = new {
+            k.insert(key, new.to_string().into_bytes());
+        }
+        self.res.push(KvResult::Add(new));
+        self
+    }
     pub fn then_lock(mut self, _key: &[u8]) -> Self {
         tracing::warn!("mock lock not implemented");
 
@@ -142,7 +394,19 @@ impl KvBatch {
         tracing::warn!("mock unlock not implemented");
         self
     }
+    pub fn try_finally_call(self) -> Result<Vec<KvResult>, i32> {
+        Ok(self.finally_call())
+    }
     pub fn finally_call(self) -> Vec<KvResult> {
+        let aborted = self
+            .res
+            .iter()
+            .any(|res| matches!(res, KvResult::Guard(false)));
+        if let (true, Some((map, versions))) = (aborted, self.txn_snapshot) {
+            let mut k = KV_BATCH.lock();
+            k.map = map;
+            k.versions = versions;
+        }
         self.res
     }
 }
@@ -151,6 +415,7 @@ impl KvBatch {
 pub struct KvBatch {
     batch_args: Vec<i32>,
     results: Vec<KvResult>,
+    txn: bool,
 }
 
 #[cfg(not(feature = "test"))]
@@ -159,11 +424,33 @@ impl KvBatch {
         Self {
             batch_args: vec![0],
             results: Vec::new(),
+            txn: false,
         }
     }
     pub fn reset(mut self) -> Self {
         self.batch_args.clear();
         self.batch_args.push(0);
+        self.txn = false;
+        self
+    }
+    /// all operations are applied atomically, nothing is written if any guard fails,
+    /// only set, get, delete and guard of single key are allowed
+    pub fn transaction(mut self) -> Self {
+        self.txn = true;
+        self
+    }
+    /// check the current value, None for expecting the key not exist
+    pub fn then_guard(mut self, key: &[u8], expected: Option<&[u8]>) -> Self {
+        self.batch_args.push(GUARD_ID);
+        self.batch_args.push(key.as_ptr() as i32);
+        self.batch_args.push(key.len() as i32);
+        self.batch_args
+            .push(expected.map_or(0, |expected| expected.as_ptr() as i32));
+        self.batch_args
+            .push(expected.map_or(-1, |expected| expected.len() as i32));
+        self.results.push(KvResult::GuardRes(0));
+        self.batch_args
+            .push(self.results.iter().rev().next().unwrap().one_ptr().unwrap());
         self
     }
     pub fn then_set(mut self, key: &[u8], value: &[u8]) -> Self {
@@ -175,16 +462,40 @@ impl KvBatch {
         self.results.push(KvResult::Set);
         self
     }
+    /// the key is removed after `ttl_secs`
+    pub fn then_set_ttl(mut self, key: &[u8], value: &[u8], ttl_secs: u32) -> Self {
+        self.batch_args.push(SET_TTL_ID);
+        self.batch_args.push(key.as_ptr() as i32);
+        self.batch_args.push(key.len() as i32);
+        self.batch_args.push(value.as_ptr() as i32);
+        self.batch_args.push(value.len() as i32);
+        self.batch_args.push(ttl_secs as i32);
+        self.results.push(KvResult::Set);
+        self
+    }
     pub fn then_get(mut self, key: &[u8]) -> Self {
         self.batch_args.push(GET_ID as i32);
         self.batch_args.push(key.as_ptr() as i32);
         self.batch_args.push(key.len() as i32);
-        self.results.push(KvResult::GetLen(0));
+        self.results.push(KvResult::GetLen([0, 0]));
         self.batch_args
             .push(self.results.iter().rev().next().unwrap().one_ptr().unwrap());
 
         self
     }
+    /// value written at version `revision`, the last few versions of each key are kept,
+    /// not allowed in transaction
+    pub fn then_get_at(mut self, key: &[u8], revision: u64) -> Self {
+        self.batch_args.push(GET_REVISION_ID);
+        self.batch_args.push(key.as_ptr() as i32);
+        self.batch_args.push(key.len() as i32);
+        self.batch_args.push(revision as i32);
+        self.batch_args.push((revision >> 32) as i32);
+        self.results.push(KvResult::GetLen([0, 0]));
+        self.batch_args
+            .push(self.results.iter().rev().next().unwrap().one_ptr().unwrap());
+        self
+    }
     pub fn then_delete(mut self, key: &[u8]) -> Self {
         self.batch_args.push(DELETE_ID as i32);
         self.batch_args.push(key.as_ptr() as i32);
@@ -193,6 +504,83 @@ impl KvBatch {
 
         self
     }
+    fn push_range_args(&mut self, ope: i32, start: &[u8], end: &[u8], prefix: bool) {
+        self.batch_args.push(ope);
+        self.batch_args.push(start.as_ptr() as i32);
+        self.batch_args.push(start.len() as i32);
+        self.batch_args.push(end.as_ptr() as i32);
+        self.batch_args.push(end.len() as i32);
+        self.batch_args.push(prefix as i32);
+    }
+    fn then_scan_inner(mut self, start: &[u8], end: &[u8], prefix: bool) -> Self {
+        self.push_range_args(SCAN_ID, start, end, prefix);
+        self.results.push(KvResult::ScanLen(0));
+        self.batch_args
+            .push(self.results.iter().rev().next().unwrap().one_ptr().unwrap());
+        self
+    }
+    /// keys in [start, end)
+    pub fn then_scan(self, start: &[u8], end: &[u8]) -> Self {
+        self.then_scan_inner(start, end, false)
+    }
+    pub fn then_scan_prefix(self, prefix: &[u8]) -> Self {
+        self.then_scan_inner(prefix, &[], true)
+    }
+    /// keys in [start, end)
+    pub fn then_delete_range(mut self, start: &[u8], end: &[u8]) -> Self {
+        self.push_range_args(DELETE_RANGE_ID, start, end, false);
+        self.results.push(KvResult::DeleteRange);
+        self
+    }
+    pub fn then_delete_prefix(mut self, prefix: &[u8]) -> Self {
+        self.push_range_args(DELETE_RANGE_ID, prefix, &[], true);
+        self.results.push(KvResult::DeleteRange);
+        self
+    }
+    /// set to `new` only if current value equals `expected`, None for absent
+    pub fn then_cas(mut self, key: &[u8], expected: Option<&[u8]>, new: &[u8]) -> Self {
+        self.batch_args.push(CAS_ID);
+        self.batch_args.push(key.as_ptr() as i32);
+        self.batch_args.push(key.len() as i32);
+        self.batch_args
+            .push(expected.map_or(0, |expected| expected.as_ptr() as i32));
+        self.batch_args
+            .push(expected.map_or(-1, |expected| expected.len() as i32));
+        self.batch_args.push(new.as_ptr() as i32);
+        self.batch_args.push(new.len() as i32);
+        self.results.push(KvResult::CasLen([0, 0]));
+        self.batch_args
+            .push(self.results.iter().rev().next().unwrap().one_ptr().unwrap());
+        self
+    }
+    /// set to `new` only if the version got equals `expected_version`, 0 for absent,
+    /// as the version restarts from 1 after the key's deleted, compare values by `then_cas`
+    /// if the key may be deleted and created again meanwhile
+    pub fn then_cas_version(mut self, key: &[u8], expected_version: u64, new: &[u8]) -> Self {
+        self.batch_args.push(CAS_VERSION_ID);
+        self.batch_args.push(key.as_ptr() as i32);
+        self.batch_args.push(key.len() as i32);
+        self.batch_args.push(expected_version as i32);
+        self.batch_args.push((expected_version >> 32) as i32);
+        self.batch_args.push(new.as_ptr() as i32);
+        self.batch_args.push(new.len() as i32);
+        self.results.push(KvResult::CasLen([0, 0]));
+        self.batch_args
+            .push(self.results.iter().rev().next().unwrap().one_ptr().unwrap());
+        self
+    }
+    /// add `delta` to the integer value (decimal string), absent key is taken as 0
+    pub fn then_add(mut self, key: &[u8], delta: i64) -> Self {
+        self.batch_args.push(ADD_ID);
+        self.batch_args.push(key.as_ptr() as i32);
+        self.batch_args.push(key.len() as i32);
+        self.batch_args.push(delta as i32);
+        self.batch_args.push((delta >> 32) as i32);
+        self.results.push(KvResult::AddRes([0, 0]));
+        self.batch_args
+            .push(self.results.iter().rev().next().unwrap().one_ptr().unwrap());
+        self
+    }
     pub fn then_lock(mut self, key: &[u8]) -> Self {
         self.batch_args.push(LOCK_ID as i32);
         self.batch_args.push(key.as_ptr() as i32);
@@ -213,11 +601,31 @@ impl KvBatch {
 
         self
     }
-    pub fn finally_call(mut self) -> Vec<KvResult> {
+    /// results of the operations, or the code returned by host if the batch failed,
+    /// like `HOST_ERR_FAILED` when the node holding a key is unreachable
+    pub fn try_finally_call(self) -> Result<Vec<KvResult>, i32> {
+        match self.call() {
+            (HOST_OK, results) => Ok(results),
+            (code, _) => Err(code),
+        }
+    }
+    /// results of failed batch are empty, gets return None and atomic operations fail,
+    /// use `try_finally_call` to tell the failure from missing keys
+    pub fn finally_call(self) -> Vec<KvResult> {
+        let (code, results) = self.call();
+        if code != HOST_OK {
+            println!("kv batch failed with code {}", code);
+        }
+        results
+    }
+    fn call(mut self) -> (i32, Vec<KvResult>) {
         self.batch_args[0] = self.results.len() as i32;
+        if self.txn {
+            self.batch_args[0] |= TXN_FLAG;
+        }
         println!("batch args: {:?}", self.batch_args);
         let mut id = 0;
-        unsafe {
+        let code = unsafe {
             kv_batch_ope(
                 self.batch_args.as_ptr(),
                 self.batch_args.len() as i32,
@@ -225,27 +633,126 @@ impl KvBatch {
             )
         };
         self.batch_args.clear();
+        if code != HOST_OK {
+            return (
+                code,
+                self.results
+                    .into_iter()
+                    .map(KvResult::into_failed)
+                    .collect(),
+            );
+        }
+        // encoded scan results, decoded after fetched
+        let mut scan_bufs = Vec::new();
         for (ope_idx, res) in self.results.iter_mut().enumerate() {
             let mut is_get_len = None;
             match res {
-                KvResult::GetLen(len) => {
-                    is_get_len = Some(*len);
+                KvResult::GetLen([len, version]) => {
+                    is_get_len = Some((*len, *version as u64));
+                }
+                KvResult::ScanLen(len) => {
+                    let buf = vec![0u8; *len as usize];
+                    self.batch_args.push(ope_idx as i32);
+                    self.batch_args.push(buf.as_ptr() as i32);
+                    scan_bufs.push((ope_idx, buf));
+                }
+                KvResult::CasLen([succ, len]) => {
+                    let current = if *len >= 0 {
+                        Some(vec![0; *len as usize])
+                    } else {
+                        None
+                    };
+                    *res = KvResult::Cas {
+                        succ: *succ != 0,
+                        current,
+                    };
+                    if let Some(ptr) = res.one_ptr() {
+                        self.batch_args.push(ope_idx as i32);
+                        self.batch_args.push(ptr);
+                    }
+                }
+                KvResult::AddRes([succ, value]) => {
+                    *res = KvResult::Add(if *succ != 0 { Some(*value) } else { None });
+                }
+                KvResult::GuardRes(succ) => {
+                    *res = KvResult::Guard(*succ != 0);
                 }
                 _ => {}
             }
-            if let Some(len) = is_get_len {
+            if let Some((len, version)) = is_get_len {
                 if len >= 0 {
-                    let vec = vec![0; len as usize];
-                    *res = KvResult::Get(Some(vec));
+                    *res = KvResult::Get {
+                        value: Some(vec![0; len as usize]),
+                        version,
+                    };
                     self.batch_args.push(ope_idx as i32);
                     self.batch_args.push(res.one_ptr().unwrap());
                 } else {
-                    *res = KvResult::Get(None);
+                    *res = KvResult::Get {
+                        value: None,
+                        version: 0,
+                    };
                 }
             }
         }
-        unsafe { kv_batch_res(id, self.batch_args.as_ptr(), self.batch_args.len() as i32) };
-        self.results
+        let code =
+            unsafe { kv_batch_res(id, self.batch_args.as_ptr(), self.batch_args.len() as i32) };
+        for (ope_idx, buf) in scan_bufs {
+            self.results[ope_idx] = KvResult::Scan(decode_kvs(&buf));
+        }
+        (code, self.results)
+    }
+}
+
+/// kind of kv event firing current fn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvEvent {
+    Set,
+    /// set creating the key
+    New,
+    /// set overwriting an existing key
+    Change,
+    Delete,
+}
+
+#[cfg(feature = "test")]
+impl KvEvent {
+    /// None if not fired by kv event
+    pub fn current() -> Option<Self> {
+        tracing::warn!("test KvEvent current not implemented");
+        None
+    }
+    /// version written by the set firing current fn, None for delete or not fired by kv event
+    pub fn revision() -> Option<u64> {
+        tracing::warn!("test KvEvent revision not implemented");
+        None
+    }
+}
+
+#[cfg(not(feature = "test"))]
+impl KvEvent {
+    /// None if not fired by kv event
+    pub fn current() -> Option<Self> {
+        let mut kind = 0;
+        if unsafe { kv_event_kind(&mut kind) } != HOST_OK {
+            return None;
+        }
+        match kind {
+            1 => Some(Self::Set),
+            2 => Some(Self::New),
+            3 => Some(Self::Change),
+            4 => Some(Self::Delete),
+            _ => None,
+        }
+    }
+    /// version written by the set firing current fn, None for delete or not fired by kv event,
+    /// the value of the version can be got by `KvBatch::then_get_at`
+    pub fn revision() -> Option<u64> {
+        let mut revision = 0;
+        if unsafe { kv_event_revision(&mut revision) } != HOST_OK || revision == 0 {
+            return None;
+        }
+        Some(revision)
     }
 }
 
@@ -276,6 +783,64 @@ impl KvBatch {
 //     }
 // }
 
+/// response of http triggered fn, written back by `write`
+pub struct HttpResponse {
+    status: u16,
+    // lines of `name: value`
+    headers: String,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: String::new(),
+            body: Vec::new(),
+        }
+    }
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push_str(name);
+        self.headers.push_str(": ");
+        self.headers.push_str(value);
+        self.headers.push('\n');
+        self
+    }
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+    /// body with content type `application/json`
+    pub fn json(self, body: &str) -> Self {
+        self.header("content-type", "application/json")
+            .body(body.as_bytes())
+    }
+    #[cfg(feature = "test")]
+    pub fn write(self) {
+        tracing::info!(
+            "test HttpResponse status:{} headers:{:?} body len:{}",
+            self.status,
+            self.headers,
+            self.body.len()
+        );
+    }
+    #[cfg(not(feature = "test"))]
+    pub fn write(self) {
+        let code = unsafe {
+            write_http_response(
+                self.status as i32,
+                self.headers.as_ptr(),
+                self.headers.len() as i32,
+                self.body.as_ptr(),
+                self.body.len() as i32,
+            )
+        };
+        if code != HOST_OK {
+            println!("write http response failed with code {}", code);
+        }
+    }
+}
+
 #[cfg(feature = "test")]
 pub struct HostFile;
 
@@ -301,8 +866,10 @@ pub struct HostFile {
 impl HostFile {
     pub fn open(fname: &str) -> Self {
         let mut fd = 0;
-        unsafe {
-            open_file(fname.as_ptr(), fname.len() as i32, &mut fd);
+        let code = unsafe { open_file(fname.as_ptr(), fname.len() as i32, &mut fd) };
+        if code != HOST_OK {
+            println!("open file {} failed with code {}", fname, code);
+            fd = -1;
         }
         Self { fd }
     }
@@ -312,13 +879,17 @@ impl HostFile {
         let mut readlen = 0;
         let buf_old_len = buf.len();
         unsafe {
-            read_file_at(
+            let code = read_file_at(
                 self.fd,
                 (buf.as_ptr() as usize + buf.len()) as *const u8,
                 (buf.capacity() - buf.len()) as i32,
                 offset as i32,
                 &mut readlen,
             );
+            if code != HOST_OK {
+                println!("read file {} failed with code {}", self.fd, code);
+                return 0;
+            }
             buf.set_len(buf_old_len + readlen as usize);
         }
         readlen as usize
@@ -66,7 +66,7 @@ fn get_count() -> u32 {
         .then_get("chain_count".as_bytes())
         .finally_call();
     match &res[0] {
-        KvResult::Get(value) => {
+        KvResult::Get { value, .. } => {
             if let Some(value) = value.as_ref().map(|v| {
                 std::str::from_utf8(v.as_slice())
                     .map_err(|e| {
@@ -95,7 +95,7 @@ pub fn chain_loop(key: *mut u8, key_len: u32) {
         .then_get("chain_count".as_bytes())
         .finally_call();
     match &res[0] {
-        KvResult::Get(value) => {
+        KvResult::Get { value, .. } => {
             if let Some(value) = value.as_ref().map(|v| {
                 std::str::from_utf8(v.as_slice())
                     .map_err(|e| {
@@ -103,13 +103,20 @@ pub fn chain_loop(key: *mut u8, key_len: u32) {
                     })
                     .unwrap()
             }) {
-                let mut count = value.parse::<u32>().unwrap();
-                count += 1;
-                println!("chain_loop count: {}", count - 1);
-                if count - 1 < LOOP_TIME {
-                    KvBatch::new()
-                        .then_set("chain_count".as_bytes(), format!("{}", count).as_bytes())
+                let count = value.parse::<u32>().unwrap();
+                println!("chain_loop count: {}", count);
+                if count < LOOP_TIME {
+                    // only one of the concurrent instances will succeed and trigger the next loop
+                    let res = KvBatch::new()
+                        .then_cas(
+                            "chain_count".as_bytes(),
+                            Some(value.as_bytes()),
+                            format!("{}", count + 1).as_bytes(),
+                        )
                         .finally_call();
+                    if let KvResult::Cas { succ: false, .. } = &res[0] {
+                        println!("chain_loop count {} is updated by others", count);
+                    }
                 }
             }
         }
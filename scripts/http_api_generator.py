@@ -40,6 +40,8 @@ def gen_type_ts(type):
             return "string"
         elif type=="Int":
             return "number"
+        elif type=="Uint":
+            return "number"
         elif type=="Float":
             return "number"
         elif type=="Bool":
@@ -60,6 +62,8 @@ def gen_type_rs(type):
             return "String"
         elif type=="Int":
             return "i32"
+        elif type=="Uint":
+            return "u64"
         elif type=="Float":
             return "f64"
         elif type=="Bool":
@@ -33,6 +33,45 @@ pub struct ServiceBasic {
        pub actions:Vec<Action>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KvWatchEvent {
+       pub key:String,
+       pub delete:bool,
+       pub version:u64,
+       pub revision:u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+       pub id:String,
+       pub app:String,
+       pub func:String,
+       pub trigger:String,
+       pub key:String,
+       pub attempts:i32,
+       pub nodes:Vec<u64>,
+       pub err:String,
+       pub fail_time:u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkflowTask {
+       pub task:u64,
+       pub parent:u64,
+       pub target:String,
+       pub node:u64,
+       pub start_time:u64,
+       pub end_time:u64,
+       pub output:String,
+       pub error:String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppVersion {
+       pub version:u64,
+       pub deploy_time:u64,
+}
+
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -146,6 +185,9 @@ pub enum RunServiceActionResp{
     Fail{
        msg:String,
 },
+    Async{
+       invocation_id:String,
+},
 
 }
 
@@ -154,6 +196,7 @@ impl RunServiceActionResp {
         match self {
                 RunServiceActionResp::Succ{..}=>1,
     RunServiceActionResp::Fail{..}=>2,
+    RunServiceActionResp::Async{..}=>3,
 
         }
     }
@@ -174,6 +217,400 @@ pub struct RunServiceActionReq {
 }
 
 
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WatchKvResp{
+    Events{
+       events:Vec<KvWatchEvent>,
+       revision:u64,
+       compacted:bool,
+},
+
+}
+
+impl WatchKvResp {
+    fn id(&self)->u32 {
+        match self {
+                WatchKvResp::Events{..}=>1,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchKvReq {
+       pub key:String,
+       pub pattern:bool,
+       pub from_revision:u64,
+       pub timeout_ms:i32,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GetInvocationResp{
+    Running{
+       invocation_id:String,
+       target:String,
+       start_time:u64,
+},
+    Succ{
+       invocation_id:String,
+       target:String,
+       start_time:u64,
+       end_time:u64,
+       status:i32,
+       output:String,
+},
+    Fail{
+       invocation_id:String,
+       target:String,
+       start_time:u64,
+       end_time:u64,
+       msg:String,
+},
+    NotFound{
+
+},
+
+}
+
+impl GetInvocationResp {
+    fn id(&self)->u32 {
+        match self {
+                GetInvocationResp::Running{..}=>1,
+    GetInvocationResp::Succ{..}=>2,
+    GetInvocationResp::Fail{..}=>3,
+    GetInvocationResp::NotFound{..}=>4,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetInvocationReq {
+       pub invocation_id:String,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CancelInvocationResp{
+    Succ{
+
+},
+    Fail{
+       msg:String,
+},
+
+}
+
+impl CancelInvocationResp {
+    fn id(&self)->u32 {
+        match self {
+                CancelInvocationResp::Succ{..}=>1,
+    CancelInvocationResp::Fail{..}=>2,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelInvocationReq {
+       pub invocation_id:String,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ListDeadLettersResp{
+    Letters{
+       letters:Vec<DeadLetter>,
+},
+
+}
+
+impl ListDeadLettersResp {
+    fn id(&self)->u32 {
+        match self {
+                ListDeadLettersResp::Letters{..}=>1,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListDeadLettersReq {
+       pub app:String,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GetDeadLetterResp{
+    Found{
+       letter:DeadLetter,
+       detail:String,
+},
+    NotFound{
+
+},
+
+}
+
+impl GetDeadLetterResp {
+    fn id(&self)->u32 {
+        match self {
+                GetDeadLetterResp::Found{..}=>1,
+    GetDeadLetterResp::NotFound{..}=>2,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetDeadLetterReq {
+       pub id:String,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ReplayDeadLetterResp{
+    Succ{
+       node:u64,
+},
+    Fail{
+       msg:String,
+},
+
+}
+
+impl ReplayDeadLetterResp {
+    fn id(&self)->u32 {
+        match self {
+                ReplayDeadLetterResp::Succ{..}=>1,
+    ReplayDeadLetterResp::Fail{..}=>2,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplayDeadLetterReq {
+       pub id:String,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GetWorkflowResp{
+    Running{
+       workflow_id:u64,
+       invocation_id:String,
+       start_time:u64,
+       tasks:Vec<WorkflowTask>,
+},
+    Done{
+       workflow_id:u64,
+       invocation_id:String,
+       succ:bool,
+       start_time:u64,
+       end_time:u64,
+       tasks:Vec<WorkflowTask>,
+},
+    NotFound{
+
+},
+
+}
+
+impl GetWorkflowResp {
+    fn id(&self)->u32 {
+        match self {
+                GetWorkflowResp::Running{..}=>1,
+    GetWorkflowResp::Done{..}=>2,
+    GetWorkflowResp::NotFound{..}=>3,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetWorkflowReq {
+       pub workflow_id:u64,
+       pub wait_ms:i32,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DeployAppResp{
+    Succ{
+       version:u64,
+},
+    Fail{
+       msg:String,
+},
+
+}
+
+impl DeployAppResp {
+    fn id(&self)->u32 {
+        match self {
+                DeployAppResp::Succ{..}=>1,
+    DeployAppResp::Fail{..}=>2,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeployAppReq {
+       pub app:String,
+       pub wasm:String,
+       pub yaml:String,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RollbackAppResp{
+    Succ{
+       version:u64,
+},
+    Fail{
+       msg:String,
+},
+
+}
+
+impl RollbackAppResp {
+    fn id(&self)->u32 {
+        match self {
+                RollbackAppResp::Succ{..}=>1,
+    RollbackAppResp::Fail{..}=>2,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RollbackAppReq {
+       pub app:String,
+       pub version:u64,
+}
+
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ListAppVersionsResp{
+    Versions{
+       current:u64,
+       versions:Vec<AppVersion>,
+},
+    NotFound{
+
+},
+
+}
+
+impl ListAppVersionsResp {
+    fn id(&self)->u32 {
+        match self {
+                ListAppVersionsResp::Versions{..}=>1,
+    ListAppVersionsResp::NotFound{..}=>2,
+
+        }
+    }
+    pub fn serialize(&self)->Value {
+        json!({
+            "id": self.id(),
+            "kernel": serde_json::to_value(self).unwrap(),
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListAppVersionsReq {
+       pub app:String,
+}
+
+
 #[async_trait]
 pub trait ApiHandler {
     
@@ -185,6 +622,26 @@ pub trait ApiHandler {
             
     async fn handle_run_service_action(&self, req:RunServiceActionReq)->RunServiceActionResp;
             
+    async fn handle_watch_kv(&self, req:WatchKvReq)->WatchKvResp;
+            
+    async fn handle_get_invocation(&self, req:GetInvocationReq)->GetInvocationResp;
+            
+    async fn handle_cancel_invocation(&self, req:CancelInvocationReq)->CancelInvocationResp;
+            
+    async fn handle_list_dead_letters(&self, req:ListDeadLettersReq)->ListDeadLettersResp;
+            
+    async fn handle_get_dead_letter(&self, req:GetDeadLetterReq)->GetDeadLetterResp;
+            
+    async fn handle_replay_dead_letter(&self, req:ReplayDeadLetterReq)->ReplayDeadLetterResp;
+            
+    async fn handle_get_workflow(&self, req:GetWorkflowReq)->GetWorkflowResp;
+            
+    async fn handle_deploy_app(&self, req:DeployAppReq)->DeployAppResp;
+            
+    async fn handle_rollback_app(&self, req:RollbackAppReq)->RollbackAppResp;
+            
+    async fn handle_list_app_versions(&self, req:ListAppVersionsReq)->ListAppVersionsResp;
+            
 }
 
 
@@ -215,6 +672,66 @@ pub fn add_routers(mut router:Router)->Router
     router=router
         .route("/run_service_action", post(run_service_action));
                              
+    async fn watch_kv(Json(req):Json<WatchKvReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_watch_kv(req).await.serialize()))
+    }
+    router=router
+        .route("/watch_kv", post(watch_kv));
+                             
+    async fn get_invocation(Json(req):Json<GetInvocationReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_get_invocation(req).await.serialize()))
+    }
+    router=router
+        .route("/get_invocation", post(get_invocation));
+                             
+    async fn cancel_invocation(Json(req):Json<CancelInvocationReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_cancel_invocation(req).await.serialize()))
+    }
+    router=router
+        .route("/cancel_invocation", post(cancel_invocation));
+                             
+    async fn list_dead_letters(Json(req):Json<ListDeadLettersReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_list_dead_letters(req).await.serialize()))
+    }
+    router=router
+        .route("/list_dead_letters", post(list_dead_letters));
+                             
+    async fn get_dead_letter(Json(req):Json<GetDeadLetterReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_get_dead_letter(req).await.serialize()))
+    }
+    router=router
+        .route("/get_dead_letter", post(get_dead_letter));
+                             
+    async fn replay_dead_letter(Json(req):Json<ReplayDeadLetterReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_replay_dead_letter(req).await.serialize()))
+    }
+    router=router
+        .route("/replay_dead_letter", post(replay_dead_letter));
+                             
+    async fn get_workflow(Json(req):Json<GetWorkflowReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_get_workflow(req).await.serialize()))
+    }
+    router=router
+        .route("/get_workflow", post(get_workflow));
+                             
+    async fn deploy_app(Json(req):Json<DeployAppReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_deploy_app(req).await.serialize()))
+    }
+    router=router
+        .route("/deploy_app", post(deploy_app));
+                             
+    async fn rollback_app(Json(req):Json<RollbackAppReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_rollback_app(req).await.serialize()))
+    }
+    router=router
+        .route("/rollback_app", post(rollback_app));
+                             
+    async fn list_app_versions(Json(req):Json<ListAppVersionsReq>)-> (StatusCode, Json<Value>){
+        (StatusCode::OK, Json(ApiHandlerImpl.handle_list_app_versions(req).await.serialize()))
+    }
+    router=router
+        .route("/list_app_versions", post(list_app_versions));
+                             
     
     router
 }
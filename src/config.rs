@@ -12,6 +12,9 @@ pub struct NodesConfig {
     pub peers: HashMap<NodeID, NodeConfig>,
     pub this: (NodeID, NodeConfig),
     pub file_dir: PathBuf,
+    pub sche: ScheConfig,
+    pub gateway: GatewayConfig,
+    pub instance: InstanceConfig,
 }
 
 impl NodesConfig {
@@ -44,6 +47,74 @@ impl NodesConfig {
     pub fn node_exist(&self, id: NodeID) -> bool {
         self.peers.contains_key(&id) || self.this.0 == id
     }
+    pub fn is_worker_node(&self, id: NodeID) -> bool {
+        if self.this.0 == id {
+            return self.this.1.is_worker();
+        }
+        self.peers.get(&id).map_or(false, |config| config.is_worker())
+    }
+    /// all nodes that can run functions, sorted by id
+    pub fn get_worker_nodes(&self) -> Vec<NodeID> {
+        let mut workers: Vec<NodeID> = self
+            .peers
+            .iter()
+            .chain(std::iter::once((&self.this.0, &self.this.1)))
+            .filter(|(_, config)| config.is_worker())
+            .map(|(id, _)| *id)
+            .collect();
+        workers.sort();
+        workers
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeSelectorType {
+    Hash,
+    Straw,
+    LeastLoad,
+}
+
+impl Default for NodeSelectorType {
+    fn default() -> Self {
+        Self::LeastLoad
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheConfig {
+    #[serde(default)]
+    pub node_selector: NodeSelectorType,
+}
+
+/// how master passes the fn calls received by its http gateway to workers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GatewayMode {
+    /// answer with a 307 redirect to the http port of the worker
+    #[default]
+    Redirect,
+    /// forward the request to the worker over p2p and answer with its response,
+    /// which is buffered whole, so responses over `MAX_PROXIED_BODY` are refused with 502
+    Proxy,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    #[serde(default)]
+    pub mode: GatewayMode,
+    /// hosts, or host:port, the output of async calls may be posted to by `x-webhook-url`,
+    /// webhooks to other addresses are refused, so none is allowed if it's empty
+    #[serde(default)]
+    pub webhook_hosts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InstanceConfig {
+    /// compile app.wasm into native app.so next to it before instantiating,
+    /// only works when built with feature `aot`
+    #[serde(default)]
+    pub aot: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -88,6 +159,12 @@ impl NodeConfig {
 pub struct YamlConfig {
     pub nodes: HashMap<NodeID, NodeConfig>,
     // pub this: NodeID,
+    #[serde(default)]
+    pub sche: ScheConfig,
+    #[serde(default)]
+    pub gateway: GatewayConfig,
+    #[serde(default)]
+    pub instance: InstanceConfig,
 }
 
 fn read_yaml_config(file_path: impl AsRef<Path>) -> YamlConfig {
@@ -107,5 +184,8 @@ pub fn read_config(this_id: NodeID, file_path: impl AsRef<Path>) -> NodesConfig
         this: (this_id, yaml_config.nodes.remove(&this_id).unwrap()),
         peers: yaml_config.nodes,
         file_dir: file_path.as_ref().to_path_buf(),
+        sche: yaml_config.sche,
+        gateway: yaml_config.gateway,
+        instance: yaml_config.instance,
     }
 }
@@ -3,16 +3,21 @@ use crate::{
     sys::{LogicalModule, NodeID},
 };
 use async_trait::async_trait;
+use std::time::Duration;
 
 use super::network::proto;
 
 pub struct KvOptions {
     spec_node: Option<NodeID>,
+    timeout: Option<Duration>,
 }
 
 impl KvOptions {
     pub fn new() -> KvOptions {
-        KvOptions { spec_node: None }
+        KvOptions {
+            spec_node: None,
+            timeout: None,
+        }
     }
 
     pub fn spec_node(&self) -> Option<NodeID> {
@@ -23,6 +28,16 @@ impl KvOptions {
         self.spec_node = Some(node);
         self
     }
+
+    /// max time waiting for the operations, None for the default of the client
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> KvOptions {
+        self.timeout = Some(timeout);
+        self
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
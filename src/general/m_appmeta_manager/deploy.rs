@@ -0,0 +1,553 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{AppMetaFunction, AppMetaManager, AppMetaYaml};
+use crate::{
+    apis::{self, DeployAppResp, ListAppVersionsResp, RollbackAppResp},
+    general::{
+        m_kv_store_engine::KeyType,
+        network::{
+            m_p2p::RPCResponsor,
+            proto::remote_sys::{
+                ActivateAppReq, ActivateAppResp, DeployAppReq, DeployAppResp as StageResp,
+            },
+        },
+    },
+    sys::NodeID,
+    util::now_ms,
+};
+
+/// versions of each app kept by master for rollback
+const MAX_APP_VERSIONS: usize = 5;
+
+/// time for a node to stage or activate a version
+const DEPLOY_RPC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// versions of app deployed by api and the one the nodes run, read by rollback
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AppDeployment {
+    /// version all nodes run
+    pub current: u64,
+    /// in the order deployed, the last ones are kept
+    pub versions: Vec<AppVersion>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppVersion {
+    pub version: u64,
+    /// unix time in ms
+    pub deploy_time: u64,
+}
+
+/// files of app at a version, rolled out again by rollback to it,
+/// dropped with the version from the deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppArtifact {
+    pub wasm: Vec<u8>,
+    pub yaml: String,
+}
+
+/// versions of app deployed by api
+pub struct KeyTypeAppDeployment<'a>(pub &'a str);
+
+impl KeyType for KeyTypeAppDeployment<'_> {
+    type Value = AppDeployment;
+    fn id(&self) -> u8 {
+        13
+    }
+}
+
+impl Serialize for KeyTypeAppDeployment<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+/// app.wasm and app.yaml of app at the version
+pub struct KeyTypeAppArtifact<'a>(pub &'a str, pub u64);
+
+impl KeyType for KeyTypeAppArtifact<'_> {
+    type Value = AppArtifact;
+    fn id(&self) -> u8 {
+        14
+    }
+}
+
+impl Serialize for KeyTypeAppArtifact<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.0, self.1).serialize(serializer)
+    }
+}
+
+impl AppDeployment {
+    /// the one deployed before the current
+    fn previous(&self) -> Option<u64> {
+        let current = self
+            .versions
+            .iter()
+            .position(|v| v.version == self.current)?;
+        self.versions[..current].last().map(|v| v.version)
+    }
+    /// add the version and return the ones dropped for exceeding `MAX_APP_VERSIONS`
+    fn push(&mut self, version: u64, deploy_time: u64) -> Vec<u64> {
+        self.versions.push(AppVersion {
+            version,
+            deploy_time,
+        });
+        let drop_cnt = self.versions.len().saturating_sub(MAX_APP_VERSIONS);
+        self.versions.drain(..drop_cnt).map(|v| v.version).collect()
+    }
+}
+
+/// version failed to roll out, the `activated` nodes already switched to it
+struct RollOutFailure {
+    msg: String,
+    activated: Vec<NodeID>,
+}
+
+/// app name is used as dir name
+fn check_app_name(app: &str) -> Result<(), String> {
+    if app.is_empty()
+        || !app
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(format!("invalid app name {:?}", app));
+    }
+    Ok(())
+}
+
+/// write the file by renaming a written temp file, so that it's never seen half written
+fn replace_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)
+}
+
+impl AppMetaManager {
+    pub(super) fn regist_deploy_rpcs(&self) {
+        let p2p = self.view.p2p();
+        self.rpc_caller_deploy_app.regist(p2p);
+        self.rpc_caller_activate_app.regist(p2p);
+        let view = self.view.clone();
+        self.rpc_handler_deploy_app
+            .regist(p2p, move |responser, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    let res = view.appmeta_manager().stage_version(req).await;
+                    send_deploy_resp(
+                        responser,
+                        StageResp {
+                            error: res.err().unwrap_or_default(),
+                        },
+                    )
+                    .await;
+                });
+                Ok(())
+            });
+        let view = self.view.clone();
+        self.rpc_handler_activate_app
+            .regist(p2p, move |responser, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    let res = view
+                        .appmeta_manager()
+                        .activate_version(&req.app, req.version)
+                        .await;
+                    send_deploy_resp(
+                        responser,
+                        ActivateAppResp {
+                            error: res.err().unwrap_or_default(),
+                        },
+                    )
+                    .await;
+                });
+                Ok(())
+            });
+    }
+
+    /// keep the uploaded app as a new version and switch all nodes to it
+    pub async fn deploy_app(&self, req: apis::DeployAppReq) -> DeployAppResp {
+        let artifact = match Self::check_upload(&req) {
+            Ok(wasm) => AppArtifact {
+                wasm,
+                yaml: req.yaml,
+            },
+            Err(msg) => return DeployAppResp::Fail { msg },
+        };
+        let app = req.app;
+        let _guard = self.deploy_lock.lock().await;
+        let kv_store_engine = self.view.kv_store_engine();
+        let mut deployment = kv_store_engine
+            .get(KeyTypeAppDeployment(&app))
+            .unwrap_or_default();
+        let mut dropped = vec![];
+        if deployment.versions.is_empty() {
+            // the app loaded at boot is kept as the first version, so it can be rolled back to
+            if let Some(booted) = self.read_active_artifact(&app) {
+                kv_store_engine.set(KeyTypeAppArtifact(&app, 1), &booted);
+                dropped.extend(deployment.push(1, now_ms()));
+                deployment.current = 1;
+            }
+        }
+        let version = deployment.versions.last().map_or(1, |v| v.version + 1);
+        kv_store_engine.set(KeyTypeAppArtifact(&app, version), &artifact);
+        kv_store_engine.flush();
+        let mut split = None;
+        if let Err(failure) = self
+            .roll_out(&app, version, &artifact, &self.deploy_nodes())
+            .await
+        {
+            match self.revert(&app, &deployment, &failure).await {
+                Ok(()) => {
+                    kv_store_engine.del(KeyTypeAppArtifact(&app, version));
+                    return DeployAppResp::Fail { msg: failure.msg };
+                }
+                // the version is kept but not current, rolling back to any version joins the nodes
+                Err(err) => {
+                    split = Some(format!(
+                        "{}, nodes {:?} switched to it failed to switch back: {}, \
+                        roll back the app to join the nodes",
+                        failure.msg, failure.activated, err
+                    ))
+                }
+            }
+        }
+        dropped.extend(deployment.push(version, now_ms()));
+        if split.is_none() {
+            deployment.current = version;
+        }
+        for dropped in dropped {
+            kv_store_engine.del(KeyTypeAppArtifact(&app, dropped));
+        }
+        kv_store_engine.set(KeyTypeAppDeployment(&app), &deployment);
+        kv_store_engine.flush();
+        if let Some(msg) = split {
+            tracing::warn!("app {} is split by version {}: {}", app, version, msg);
+            return DeployAppResp::Fail { msg };
+        }
+        tracing::info!("app {} deployed as version {}", app, version);
+        DeployAppResp::Succ { version }
+    }
+
+    /// switch all nodes to a kept version of app, the previous one if `version` is 0
+    pub async fn rollback_app(&self, req: apis::RollbackAppReq) -> RollbackAppResp {
+        let _guard = self.deploy_lock.lock().await;
+        let kv_store_engine = self.view.kv_store_engine();
+        let Some(mut deployment) = kv_store_engine.get(KeyTypeAppDeployment(&req.app)) else {
+            return RollbackAppResp::Fail {
+                msg: format!("app {} is not deployed by api", req.app),
+            };
+        };
+        let version = match req.version {
+            0 => deployment.previous(),
+            version => deployment
+                .versions
+                .iter()
+                .any(|v| v.version == version)
+                .then_some(version),
+        };
+        let Some((version, artifact)) = version.and_then(|version| {
+            let artifact = kv_store_engine.get(KeyTypeAppArtifact(&req.app, version))?;
+            Some((version, artifact))
+        }) else {
+            return RollbackAppResp::Fail {
+                msg: format!("version to roll back of app {} is not kept", req.app),
+            };
+        };
+        if let Err(failure) = self
+            .roll_out(&req.app, version, &artifact, &self.deploy_nodes())
+            .await
+        {
+            let msg = match self.revert(&req.app, &deployment, &failure).await {
+                Ok(()) => failure.msg,
+                Err(err) => format!(
+                    "{}, nodes {:?} switched to it failed to switch back: {}",
+                    failure.msg, failure.activated, err
+                ),
+            };
+            return RollbackAppResp::Fail { msg };
+        }
+        deployment.current = version;
+        kv_store_engine.set(KeyTypeAppDeployment(&req.app), &deployment);
+        kv_store_engine.flush();
+        tracing::info!("app {} rolled back to version {}", req.app, version);
+        RollbackAppResp::Succ { version }
+    }
+
+    pub fn list_app_versions(&self, app: &str) -> ListAppVersionsResp {
+        let Some(deployment) = self.view.kv_store_engine().get(KeyTypeAppDeployment(app)) else {
+            return ListAppVersionsResp::NotFound {};
+        };
+        ListAppVersionsResp::Versions {
+            current: deployment.current,
+            versions: deployment
+                .versions
+                .into_iter()
+                .map(|v| apis::AppVersion {
+                    version: v.version,
+                    deploy_time: v.deploy_time,
+                })
+                .collect(),
+        }
+    }
+
+    /// the decoded wasm if the upload can be deployed
+    fn check_upload(req: &apis::DeployAppReq) -> Result<Vec<u8>, String> {
+        check_app_name(&req.app)?;
+        let wasm = base64::decode(&req.wasm).map_err(|err| format!("decode wasm: {}", err))?;
+        if !wasm.starts_with(b"\0asm") {
+            return Err("app.wasm is not a wasm module".to_owned());
+        }
+        let _ = AppMetaYaml::parse(&req.yaml)?;
+        Ok(wasm)
+    }
+
+    /// nodes the apps are deployed to, master included
+    fn deploy_nodes(&self) -> Vec<NodeID> {
+        let nodes_config = &self.view.p2p().nodes_config;
+        nodes_config
+            .peers
+            .keys()
+            .copied()
+            .chain([nodes_config.this_node()])
+            .collect()
+    }
+
+    /// switch the nodes that activated the failed version back to the current one
+    async fn revert(
+        &self,
+        app: &str,
+        deployment: &AppDeployment,
+        failure: &RollOutFailure,
+    ) -> Result<(), String> {
+        if failure.activated.is_empty() {
+            return Ok(());
+        }
+        let Some(artifact) = self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeAppArtifact(app, deployment.current))
+        else {
+            return Err(format!("no kept version of app {} to switch back to", app));
+        };
+        self.roll_out(app, deployment.current, &artifact, &failure.activated)
+            .await
+            .map_err(|failure| failure.msg)?;
+        tracing::info!(
+            "nodes {:?} switched back to version {} of app {}",
+            failure.activated,
+            deployment.current,
+            app
+        );
+        Ok(())
+    }
+
+    /// stage the version on the nodes first, so that a node failing to receive it leaves
+    /// all of them on the old version, then activate it on the nodes
+    async fn roll_out(
+        &self,
+        app: &str,
+        version: u64,
+        artifact: &AppArtifact,
+        nodes: &[NodeID],
+    ) -> Result<(), RollOutFailure> {
+        let mut staging = vec![];
+        for &node in nodes {
+            let view = self.view.clone();
+            let req = DeployAppReq {
+                app: app.to_owned(),
+                version,
+                wasm: artifact.wasm.clone(),
+                yaml: artifact.yaml.clone(),
+            };
+            staging.push((
+                node,
+                tokio::spawn(async move { view.appmeta_manager().stage_on(node, req).await }),
+            ));
+        }
+        for (node, res) in staging {
+            let res = res.await.map_err(|err| err.to_string()).and_then(|res| res);
+            if let Err(err) = res {
+                return Err(RollOutFailure {
+                    msg: format!("stage version {} on node {} failed: {}", version, node, err),
+                    activated: vec![],
+                });
+            }
+        }
+        let mut activating = vec![];
+        for &node in nodes {
+            let view = self.view.clone();
+            let app = app.to_owned();
+            activating.push((
+                node,
+                tokio::spawn(async move {
+                    view.appmeta_manager().activate_on(node, app, version).await
+                }),
+            ));
+        }
+        let (mut errs, mut activated) = (vec![], vec![]);
+        for (node, res) in activating {
+            let res = res.await.map_err(|err| err.to_string()).and_then(|res| res);
+            match res {
+                Ok(()) => activated.push(node),
+                Err(err) => errs.push(format!("node {}: {}", node, err)),
+            }
+        }
+        if !errs.is_empty() {
+            return Err(RollOutFailure {
+                msg: format!("activate version {} failed on {}", version, errs.join(", ")),
+                activated,
+            });
+        }
+        Ok(())
+    }
+
+    async fn stage_on(&self, node: NodeID, req: DeployAppReq) -> Result<(), String> {
+        if node == self.view.p2p().nodes_config.this_node() {
+            return self.stage_version(req).await;
+        }
+        let resp = self
+            .rpc_caller_deploy_app
+            .call(self.view.p2p(), node, req, Some(DEPLOY_RPC_TIMEOUT))
+            .await
+            .map_err(|err| err.to_string())?;
+        if !resp.error.is_empty() {
+            return Err(resp.error);
+        }
+        Ok(())
+    }
+
+    async fn activate_on(&self, node: NodeID, app: String, version: u64) -> Result<(), String> {
+        if node == self.view.p2p().nodes_config.this_node() {
+            return self.activate_version(&app, version).await;
+        }
+        let resp = self
+            .rpc_caller_activate_app
+            .call(
+                self.view.p2p(),
+                node,
+                ActivateAppReq { app, version },
+                Some(DEPLOY_RPC_TIMEOUT),
+            )
+            .await
+            .map_err(|err| err.to_string())?;
+        if !resp.error.is_empty() {
+            return Err(resp.error);
+        }
+        Ok(())
+    }
+
+    fn staged_dir(&self, app: &str, version: u64) -> PathBuf {
+        self.view
+            .os()
+            .file_path
+            .join(format!("staged_apps/{}/{}", app, version))
+    }
+
+    fn app_dir(&self, app: &str) -> PathBuf {
+        self.view.os().file_path.join(format!("apps/{}", app))
+    }
+
+    /// files of the app running on this node, None if it's not there
+    fn read_active_artifact(&self, app: &str) -> Option<AppArtifact> {
+        let dir = self.app_dir(app);
+        Some(AppArtifact {
+            wasm: fs::read(dir.join("app.wasm")).ok()?,
+            yaml: fs::read_to_string(dir.join("app.yaml")).ok()?,
+        })
+    }
+
+    /// keep the files of the version on this node until it's activated
+    async fn stage_version(&self, req: DeployAppReq) -> Result<(), String> {
+        check_app_name(&req.app)?;
+        let _ = AppMetaYaml::parse(&req.yaml)?;
+        let dir = self.staged_dir(&req.app, req.version);
+        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("app.wasm"), &req.wasm)?;
+            fs::write(dir.join("app.yaml"), &req.yaml)
+        })
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("write staged files: {}", err))
+    }
+
+    /// switch the app on this node to the staged version, the meta of the app is swapped at once,
+    /// instances of the old version are destroyed once their running calls finish,
+    /// the instances of the new one are warmed if its pool declares prewarm
+    async fn activate_version(&self, app: &str, version: u64) -> Result<(), String> {
+        check_app_name(app)?;
+        let staged = self.staged_dir(app, version);
+        let yaml = fs::read_to_string(staged.join("app.yaml"))
+            .map_err(|err| format!("version {} is not staged: {}", version, err))?;
+        let meta: AppMetaFunction = AppMetaYaml::parse(&yaml)?.into();
+        let prewarmed = meta.prewarmed_pages();
+
+        let mut metas = self.meta.write().await;
+        let app_dir = self.app_dir(app);
+        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            fs::create_dir_all(&app_dir)?;
+            replace_file(
+                &app_dir.join("app.wasm"),
+                &fs::read(staged.join("app.wasm"))?,
+            )?;
+            replace_file(&app_dir.join("app.yaml"), yaml.as_bytes())?;
+            fs::remove_dir_all(staged)
+        })
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("switch app files: {}", err))?;
+        let nodes_config = &self.view.p2p().nodes_config;
+        let is_worker = nodes_config.is_worker_node(nodes_config.this_node());
+        if is_worker {
+            self.view.instance_manager().drain_app(app);
+        }
+        metas.insert_app(app.to_owned(), meta);
+        if is_worker {
+            self.view.instance_manager().prewarm(app, prewarmed);
+        }
+        tracing::info!("app {} switched to version {}", app, version);
+        Ok(())
+    }
+}
+
+async fn send_deploy_resp<Req: crate::general::network::msg_pack::RPCReq>(
+    responser: RPCResponsor<Req>,
+    resp: Req::Resp,
+) {
+    if let Err(err) = responser.send_resp(resp).await {
+        tracing::error!("send deploy resp failed with err: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_app_deployment_versions() {
+        let mut deployment = AppDeployment::default();
+        assert_eq!(deployment.previous(), None);
+        for version in 1..=MAX_APP_VERSIONS as u64 {
+            assert!(deployment.push(version, version).is_empty());
+        }
+        deployment.current = MAX_APP_VERSIONS as u64;
+        assert_eq!(deployment.previous(), Some(MAX_APP_VERSIONS as u64 - 1));
+        // the oldest one is dropped
+        let next = MAX_APP_VERSIONS as u64 + 1;
+        assert_eq!(deployment.push(next, next), vec![1]);
+        // rolled back to the first kept one
+        deployment.current = 2;
+        assert_eq!(deployment.previous(), None);
+
+        assert!(check_app_name("word_count").is_ok());
+        assert!(check_app_name("").is_err());
+        assert!(check_app_name("../etc").is_err());
+    }
+}
@@ -1,32 +1,61 @@
 use crate::general::{
     kv_interface::KvOps,
-    network::proto::sche::distribute_task_req::{Trigger, TriggerKvSet},
+    network::proto::sche::distribute_task_req::{Trigger, TriggerKvDelete, TriggerKvSet},
 };
 
 use super::{
     super::network::proto::{self, kv::KvRequest},
-    AppMetas,
+    AppMetas, KeyPattern, KvEventKind,
 };
 
 pub struct EventTriggerInfo {
-    pub trigger_appfns: Vec<(String, String)>,
-    pub kvreq: KvRequest,
+    /// fns listening the key and the kind of event each listens
+    pub trigger_appfns: Vec<(String, String, KvEventKind)>,
+    pub key: Vec<u8>,
 }
 impl EventTriggerInfo {
-    pub fn to_trigger(&self, opeid: u32) -> Trigger {
-        match self.kvreq.op.as_ref().unwrap() {
-            proto::kv::kv_request::Op::Set(set) => {
-                let kv = set.kv.as_ref().unwrap();
-                Trigger::KvSet(TriggerKvSet {
-                    key: kv.key.clone(),
-                    opeid,
-                })
-            }
-            _ => unimplemented!(),
+    /// `revision` is the version of the written value, unused for delete
+    pub fn to_trigger(&self, kind: KvEventKind, opeid: u32, revision: u64) -> Trigger {
+        let set = || TriggerKvSet {
+            key: self.key.clone(),
+            opeid,
+            revision,
+        };
+        match kind {
+            KvEventKind::Set => Trigger::KvSet(set()),
+            KvEventKind::New => Trigger::KvNew(set()),
+            KvEventKind::Change => Trigger::KvChange(set()),
+            KvEventKind::Delete => Trigger::KvDelete(TriggerKvDelete {
+                key: self.key.clone(),
+                opeid,
+            }),
         }
     }
 }
 
+/// what happened to the key after a set writing version `revision`
+pub fn set_happened(revision: u64) -> KvEventKind {
+    // versions restart from 1 after the key's deleted
+    if revision == 1 {
+        KvEventKind::New
+    } else {
+        KvEventKind::Change
+    }
+}
+
+/// key of the operations changing value like set, cas and add
+pub fn kv_set_key(req: &KvRequest) -> Option<&[u8]> {
+    match req.op.as_ref().unwrap() {
+        proto::kv::kv_request::Op::Set(set) => Some(&set.kv.as_ref().unwrap().key),
+        proto::kv::kv_request::Op::Cas(cas) => Some(&cas.key),
+        proto::kv::kv_request::Op::Add(add) => Some(&add.key),
+        proto::kv::kv_request::Op::Get(_) => None,
+        proto::kv::kv_request::Op::Delete(_) => None,
+        proto::kv::kv_request::Op::Lock(_) => None,
+        proto::kv::kv_request::Op::Guard(_) => None,
+    }
+}
+
 // impl Into<Trigger> for EventTriggerInfo {
 //     fn into(self) -> Trigger {
 //         match self.kvreq.op.unwrap() {
@@ -44,35 +73,57 @@ pub fn try_match_kv_event(
     source_app: &str,
     source_fn: &str,
 ) -> Option<EventTriggerInfo> {
-    // find source app
-    let Some(appmeta) = app_metas.get_app_meta(source_app) else {
-        tracing::warn!("source app:{} not found", source_app);
-        return None;
+    // cas and add trigger set events like set when they succeed,
+    // delete events are matched when keys are really deleted
+    let key = kv_set_key(req)?;
+    match_kv_event(app_metas, key, KvOps::Set, Some((source_app, source_fn)))
+}
+
+/// whether the key matches the pattern of kv events, keys not in utf8 match no pattern
+pub fn key_match_pattern(pattern: &KeyPattern, key: &[u8]) -> bool {
+    std::str::from_utf8(key).map_or(false, |key| pattern.match_key(key))
+}
+
+/// fns listening `ope` on the key,
+/// `source` is the app fn operating the key, None for system operations like ttl expiry
+pub fn match_kv_event(
+    app_metas: &AppMetas,
+    key: &[u8],
+    ope: KvOps,
+    source: Option<(&str, &str)>,
+) -> Option<EventTriggerInfo> {
+    let listening = |triggers: &Vec<(String, String, KvEventKind)>| {
+        triggers
+            .iter()
+            .filter(|(_, _, kind)| kind.kv_ope() == ope)
+            .cloned()
+            .collect::<Vec<_>>()
     };
-    // find source func
-    let Some(fnmeta) = appmeta.get_fn_meta(source_fn) else {
-        tracing::warn!("app {} source func:{} not found", source_app, source_fn);
-        return None;
+    let trigger_appfns = if let Some((source_app, source_fn)) = source {
+        // find source app
+        let Some(appmeta) = app_metas.get_app_meta(source_app) else {
+            tracing::warn!("source app:{} not found", source_app);
+            return None;
+        };
+        // find source func
+        let Some(fnmeta) = appmeta.get_fn_meta(source_fn) else {
+            tracing::warn!("app {} source func:{} not found", source_app, source_fn);
+            return None;
+        };
+        // match kv pattern
+        let pattern = fnmeta.match_key(key, ope)?;
+        // find trigger func
+        listening(app_metas.pattern_2_app_fn.get(&pattern.0)?)
+    } else {
+        app_metas
+            .pattern_2_app_fn
+            .iter()
+            .filter(|(pattern, _)| key_match_pattern(&KeyPattern::new((*pattern).clone()), key))
+            .flat_map(|(_, triggers)| listening(triggers))
+            .collect()
     };
-
-    match req.op.as_ref().unwrap() {
-        proto::kv::kv_request::Op::Set(set) => {
-            let kv = set.kv.as_ref().unwrap();
-            // match kv pattern
-            let Some(pattern) = fnmeta.match_key(&kv.key, KvOps::Set) else {
-                return None;
-            };
-            // find trigger func
-            app_metas
-                .pattern_2_app_fn
-                .get(&pattern.0)
-                .map(|triggers| EventTriggerInfo {
-                    trigger_appfns: triggers.clone(),
-                    kvreq: req.clone(),
-                })
-        }
-        proto::kv::kv_request::Op::Get(_) => None,
-        proto::kv::kv_request::Op::Delete(_) => None,
-        proto::kv::kv_request::Op::Lock(_) => None,
-    }
+    (!trigger_appfns.is_empty()).then(|| EventTriggerInfo {
+        trigger_appfns,
+        key: key.to_owned(),
+    })
 }
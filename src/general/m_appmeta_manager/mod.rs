@@ -2,8 +2,8 @@ use super::{
     m_kv_store_engine::{KeyTypeServiceList, KeyTypeServiceMeta, KvStoreEngine},
     m_os::OperatingSystem,
     network::{
-        m_p2p::P2PModule,
-        proto::remote_sys::{GetDirContentReq, RunCmdReq},
+        m_p2p::{P2PModule, RPCCaller, RPCHandler},
+        proto::remote_sys::{ActivateAppReq, DeployAppReq, GetDirContentReq, RunCmdReq},
     },
 };
 use crate::{
@@ -13,9 +13,11 @@ use crate::{
     },
     general::kv_interface::KvOps,
     logical_module_view_impl,
+    master::m_async_invoker::{AsyncInvoker, InvocationOutput},
     result::{ErrCvt, WSResult},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
+    worker::m_instance_manager::InstanceManager,
 };
 use async_trait::async_trait;
 use parking_lot::Mutex;
@@ -25,39 +27,174 @@ use std::{
     collections::{BTreeMap, HashMap, HashSet},
     fs,
     path::Path,
+    sync::Arc,
     time::Duration,
 };
+use timer::{TimerMeta, TimerYaml};
 use tokio::sync::RwLock;
 use ws_derive::LogicalModule;
 
+pub mod deploy;
 pub mod fn_event;
+pub mod timer;
 
 logical_module_view_impl!(View);
 logical_module_view_impl!(View, os, OperatingSystem);
 logical_module_view_impl!(View, p2p, P2PModule);
 logical_module_view_impl!(View, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(View, appmeta_manager, AppMetaManager);
+logical_module_view_impl!(View, async_invoker, Option<AsyncInvoker>);
+logical_module_view_impl!(View, instance_manager, Option<InstanceManager>);
+
+/// time waiting for the output of service action run async
+const SERVICE_ACTION_ASYNC_TIMEOUT: Duration = Duration::from_secs(600);
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum FnEventYaml {
-    HttpFn { http_fn: () },
-    HttpApp { http_app: () },
+    HttpFn { http_fn: HttpEventYaml },
+    HttpApp { http_app: HttpEventYaml },
     KvSet { kv_set: usize },
+    KvDelete { kv_delete: usize },
+    KvNew { kv_new: usize },
+    KvChange { kv_change: usize },
+    Timer { timer: TimerYaml },
+}
+
+/// allowed methods and sub-path of http event, POST without sub-path if not declared
+/// ```yaml
+/// event:
+/// - http_fn:
+///     methods: [get, put]
+///     path: users/{id}
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HttpEventYaml {
+    Default(()),
+    Route {
+        #[serde(default)]
+        methods: Vec<String>,
+        path: Option<String>,
+    },
+}
+
+/// `http_fn` is routed by `/app/fn/sub-path`, `http_app` by `/app/sub-path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRoute {
+    /// uppercase
+    pub methods: Vec<String>,
+    /// segments of sub-path, `{name}` matches one segment as path param
+    pub path: Vec<String>,
 }
 
 #[derive(PartialEq, Eq)]
 pub enum FnEvent {
-    HttpFn,
-    HttpApp,
+    HttpFn(HttpRoute),
+    HttpApp(HttpRoute),
     KvSet(usize),
+    KvDelete(usize),
+    /// set creating the key
+    KvNew(usize),
+    /// set overwriting an existing key
+    KvChange(usize),
+    /// fired periodically by master
+    Timer(TimerMeta),
+}
+
+/// kind of the kv event a fn listens or is triggered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvEventKind {
+    Set,
+    New,
+    Change,
+    Delete,
 }
 
 impl From<FnEventYaml> for FnEvent {
     fn from(yaml: FnEventYaml) -> Self {
         match yaml {
-            FnEventYaml::HttpFn { http_fn: _ } => Self::HttpFn,
-            FnEventYaml::HttpApp { http_app: _ } => Self::HttpApp,
+            FnEventYaml::HttpFn { http_fn } => Self::HttpFn(http_fn.into()),
+            FnEventYaml::HttpApp { http_app } => Self::HttpApp(http_app.into()),
             FnEventYaml::KvSet { kv_set } => Self::KvSet(kv_set),
+            FnEventYaml::KvDelete { kv_delete } => Self::KvDelete(kv_delete),
+            FnEventYaml::KvNew { kv_new } => Self::KvNew(kv_new),
+            FnEventYaml::KvChange { kv_change } => Self::KvChange(kv_change),
+            FnEventYaml::Timer { timer } => Self::Timer(
+                TimerMeta::new(timer).unwrap_or_else(|err| panic!("invalid timer event: {}", err)),
+            ),
+        }
+    }
+}
+
+impl FnEvent {
+    /// kind and listened key index of kv event
+    pub fn kv_event(&self) -> Option<(KvEventKind, usize)> {
+        match self {
+            FnEvent::HttpFn(_) | FnEvent::HttpApp(_) | FnEvent::Timer(_) => None,
+            FnEvent::KvSet(key_index) => Some((KvEventKind::Set, *key_index)),
+            FnEvent::KvDelete(key_index) => Some((KvEventKind::Delete, *key_index)),
+            FnEvent::KvNew(key_index) => Some((KvEventKind::New, *key_index)),
+            FnEvent::KvChange(key_index) => Some((KvEventKind::Change, *key_index)),
+        }
+    }
+}
+
+impl From<HttpEventYaml> for HttpRoute {
+    fn from(yaml: HttpEventYaml) -> Self {
+        let (methods, path) = match yaml {
+            HttpEventYaml::Default(()) => (vec![], None),
+            HttpEventYaml::Route { methods, path } => (methods, path),
+        };
+        let methods = if methods.is_empty() {
+            vec!["POST".to_owned()]
+        } else {
+            methods.iter().map(|m| m.to_uppercase()).collect()
+        };
+        let path = path.map_or(vec![], |path| {
+            path.split('/')
+                .filter(|seg| !seg.is_empty())
+                .map(|seg| seg.to_owned())
+                .collect()
+        });
+        Self { methods, path }
+    }
+}
+
+impl HttpRoute {
+    pub fn allow_method(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m == method)
+    }
+    /// path params if the sub-path segments match
+    pub fn match_path(&self, segs: &[&str]) -> Option<BTreeMap<String, String>> {
+        if segs.len() != self.path.len() {
+            return None;
+        }
+        let mut params = BTreeMap::new();
+        for (pattern, seg) in self.path.iter().zip(segs) {
+            if let Some(name) = pattern.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+                let _ = params.insert(name.to_owned(), (*seg).to_owned());
+            } else if pattern != seg {
+                return None;
+            }
+        }
+        Some(params)
+    }
+}
+
+impl KvEventKind {
+    /// operation on the key firing the event
+    pub fn kv_ope(&self) -> KvOps {
+        match self {
+            KvEventKind::Delete => KvOps::Delete,
+            KvEventKind::Set | KvEventKind::New | KvEventKind::Change => KvOps::Set,
+        }
+    }
+    /// whether the listener fires when `happened` (new, change or delete) happens to the key
+    pub fn fires_on(&self, happened: KvEventKind) -> bool {
+        match self {
+            KvEventKind::Set => matches!(happened, KvEventKind::New | KvEventKind::Change),
+            _ => *self == happened,
         }
     }
 }
@@ -67,12 +204,21 @@ impl From<FnEventYaml> for FnEvent {
 pub enum FnArgYaml {
     KvKey { kv_key: usize },
     HttpText { http_text: () },
+    HttpJson { http_json: () },
+    HttpHeaders { http_headers: () },
+    TimerTime { timer_time: () },
 }
 
 #[derive(Debug)]
 pub enum FnArg {
     KvKey(usize),
     HttpText,
+    /// method, params, query, headers and the json body of the request in one json object
+    HttpJson,
+    /// headers of the request in json object
+    HttpHeaders,
+    /// scheduled time of timer event in unix ms, passed as i64
+    TimerTime,
 }
 
 impl From<FnArgYaml> for FnArg {
@@ -80,6 +226,9 @@ impl From<FnArgYaml> for FnArg {
         match yaml {
             FnArgYaml::KvKey { kv_key } => Self::KvKey(kv_key),
             FnArgYaml::HttpText { http_text: _ } => Self::HttpText,
+            FnArgYaml::HttpJson { http_json: _ } => Self::HttpJson,
+            FnArgYaml::HttpHeaders { http_headers: _ } => Self::HttpHeaders,
+            FnArgYaml::TimerTime { timer_time: _ } => Self::TimerTime,
         }
     }
 }
@@ -90,7 +239,84 @@ pub struct FnMetaYaml {
     // pub input: Option<Vec<FnInputYaml>>,
     pub args: Vec<FnArgYaml>,
     /// key to operations
-    pub kvs: Option<BTreeMap<String, Vec<String>>>,
+    pub kvs: Option<BTreeMap<String, KvMetaYaml>>,
+    /// seconds the fn may run before it's interrupted
+    pub timeout: Option<u64>,
+    /// pages of 64KiB the linear memory of the fn may grow to
+    pub max_memory_pages: Option<u32>,
+    /// cost of instructions a call may take, 1 for each instruction,
+    /// `DEFAULT_FUEL_PER_SEC` for each second of the timeout if not declared
+    pub fuel: Option<u64>,
+    /// calls of the fn running at the same time on a worker, http calls beyond it fail with 429,
+    /// events beyond it are rejected by the worker and retried by `retry`
+    pub max_concurrency: Option<u32>,
+    /// how the events failed to be scheduled to a worker are retried
+    pub retry: Option<RetryYaml>,
+}
+
+impl FnMetaYaml {
+    /// the checks panicking in turning it into fn meta
+    fn check(&self) -> Result<(), String> {
+        let kvs_len = self.kvs.as_ref().map_or(0, |kvs| kvs.len());
+        for (key, meta) in self.kvs.iter().flatten() {
+            let ops = match meta {
+                KvMetaYaml::Ops(ops) => ops,
+                KvMetaYaml::Detail { ops, .. } => ops,
+            };
+            if let Some(op) = ops
+                .iter()
+                .find(|op| !["set", "get", "delete"].contains(&op.as_str()))
+            {
+                return Err(format!("invalid operation {} of key {}", op, key));
+            }
+            let _ = regex::Regex::new(&key.replace("{}", "[a-zA-Z0-9]+"))
+                .map_err(|err| format!("invalid key pattern {}: {}", key, err))?;
+        }
+        for event in &self.event {
+            let key_index = match event {
+                FnEventYaml::KvSet { kv_set: index }
+                | FnEventYaml::KvDelete { kv_delete: index }
+                | FnEventYaml::KvNew { kv_new: index }
+                | FnEventYaml::KvChange { kv_change: index } => *index,
+                FnEventYaml::Timer { timer } => {
+                    let _ = TimerMeta::new(timer.clone())
+                        .map_err(|err| format!("invalid timer event: {}", err))?;
+                    continue;
+                }
+                FnEventYaml::HttpFn { .. } | FnEventYaml::HttpApp { .. } => continue,
+            };
+            if key_index >= kvs_len {
+                return Err(format!("kv event of key {} not in kvs", key_index));
+            }
+        }
+        for arg in &self.args {
+            if let FnArgYaml::KvKey { kv_key } = arg {
+                if *kv_key >= kvs_len {
+                    return Err(format!("kv_key arg {} not in kvs", kv_key));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetryYaml {
+    /// attempts including the first one
+    pub max_attempts: u32,
+    /// ms to wait before the first retry, doubled for each later one
+    pub backoff_ms: Option<u64>,
+    /// ms the backoff grows to at most
+    pub max_backoff_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KvMetaYaml {
+    /// `[set]`
+    Ops(Vec<String>),
+    /// `{ops: [set], ttl: 60}`, keys set by the fn expire after ttl seconds by default
+    Detail { ops: Vec<String>, ttl: Option<u32> },
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -102,21 +328,174 @@ pub struct KvMeta {
     get: bool,
     delete: bool,
     pub pattern: KeyPattern,
+    /// default ttl in seconds of keys set
+    pub ttl: Option<u32>,
 }
 
 pub struct FnMeta {
     pub event: Vec<FnEvent>,
     pub args: Vec<FnArg>,
     pub kvs: Option<Vec<KvMeta>>,
+    /// the fn is interrupted after running this long
+    pub timeout: Duration,
+    pub limits: FnLimits,
+    pub retry: RetryPolicy,
+}
+
+/// timeout of fns not declaring it in app.yaml
+pub const DEFAULT_FN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// time the worker may take beyond the fn timeout to finish a fn call,
+/// for scheduling, loading the instance and transferring the request and response
+pub const FN_CALL_TIMEOUT_MARGIN: Duration = Duration::from_secs(30);
+
+/// fuel for each second of the timeout of fns not declaring it in app.yaml,
+/// far beyond what the interpreter runs in a second, it only stops a runaway fn
+/// spinning without calling any host function, which can't be interrupted otherwise
+pub const DEFAULT_FUEL_PER_SEC: u64 = 1_000_000_000;
+
+/// max concurrency of fns not declaring it in app.yaml
+pub const DEFAULT_FN_MAX_CONCURRENCY: u32 = 100;
+
+/// resources a call of the fn may take, enforced by the instance manager of worker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FnLimits {
+    /// none for the wasm max 65536
+    pub max_memory_pages: Option<u32>,
+    /// none for bounded by the timeout, see `DEFAULT_FUEL_PER_SEC`
+    pub fuel: Option<u64>,
+    pub max_concurrency: u32,
+}
+
+impl FnLimits {
+    /// fuel of a call of the fn running at most `timeout`
+    pub fn fuel_within(&self, timeout: Duration) -> u64 {
+        self.fuel
+            .unwrap_or_else(|| DEFAULT_FUEL_PER_SEC.saturating_mul(timeout.as_secs().max(1)))
+    }
+}
+
+/// backoff before the first retry if not declared in app.yaml
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// max backoff of retries if not declared in app.yaml
+pub const DEFAULT_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// retry of the events failed to be scheduled, each retry goes to another worker if any,
+/// the event is kept as a dead letter after all attempts failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// at least 1, events are not retried by default
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: DEFAULT_RETRY_BACKOFF,
+            max_backoff: DEFAULT_RETRY_MAX_BACKOFF,
+        }
+    }
+}
+
+impl From<RetryYaml> for RetryPolicy {
+    fn from(yaml: RetryYaml) -> Self {
+        let backoff = yaml
+            .backoff_ms
+            .map_or(DEFAULT_RETRY_BACKOFF, Duration::from_millis);
+        Self {
+            max_attempts: yaml.max_attempts.max(1),
+            backoff,
+            max_backoff: yaml
+                .max_backoff_ms
+                .map_or(DEFAULT_RETRY_MAX_BACKOFF, Duration::from_millis)
+                .max(backoff),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// time to wait before the `retry`th retry, starts from 1
+    pub fn backoff_of(&self, retry: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PoolYaml {
+    /// idle instances kept on each worker even if no call needs them
+    pub min_warm: Option<u32>,
+    /// instances pooled or running on each worker at most, for each memory limit of the fns,
+    /// calls beyond it wait for a running one to finish within their timeout, or fail with 429
+    pub max_instances: Option<u32>,
+    /// seconds the instances beyond `min_warm` are kept after calls stopped needing them
+    pub idle_ttl: Option<u64>,
+    /// create the warm instances once the app is deployed, instead of after its first call
+    #[serde(default)]
+    pub prewarm: bool,
+}
+
+/// instances kept by a worker if not declared in app.yaml
+pub const DEFAULT_POOL_MAX_INSTANCES: u32 = 100;
+
+/// idle ttl of instances if not declared in app.yaml
+pub const DEFAULT_POOL_IDLE_TTL: Duration = Duration::from_secs(60);
+
+/// instances of the app pooled by each worker, scaled by the calls in flight lately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolPolicy {
+    pub min_warm: u32,
+    pub max_instances: u32,
+    pub idle_ttl: Duration,
+    pub prewarm: bool,
+}
+
+impl Default for PoolPolicy {
+    fn default() -> Self {
+        Self {
+            min_warm: 0,
+            max_instances: DEFAULT_POOL_MAX_INSTANCES,
+            idle_ttl: DEFAULT_POOL_IDLE_TTL,
+            prewarm: false,
+        }
+    }
+}
+
+impl From<PoolYaml> for PoolPolicy {
+    fn from(yaml: PoolYaml) -> Self {
+        let max_instances = yaml
+            .max_instances
+            .map_or(DEFAULT_POOL_MAX_INSTANCES, |max| max.max(1));
+        Self {
+            min_warm: yaml.min_warm.unwrap_or(0).min(max_instances),
+            max_instances,
+            idle_ttl: yaml.idle_ttl.map_or(DEFAULT_POOL_IDLE_TTL, |secs| {
+                Duration::from_secs(secs.max(1))
+            }),
+            prewarm: yaml.prewarm,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppMetaYaml {
     pub fns: HashMap<String, FnMetaYaml>,
+    /// instance pool of the app on workers
+    pub pool: Option<PoolYaml>,
 }
 
 pub struct AppMetaFunction {
-    fns: HashMap<String, FnMeta>,
+    /// shared with the running calls of the fns
+    fns: HashMap<String, Arc<FnMeta>>,
+    pool: PoolPolicy,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -128,7 +507,8 @@ pub struct AppMetaService {
 
 pub struct AppMetas {
     app_metas: HashMap<String, AppMetaFunction>,
-    pattern_2_app_fn: HashMap<String, Vec<(String, String)>>,
+    // key pattern to the app fns listening it and the kind of event
+    pattern_2_app_fn: HashMap<String, Vec<(String, String, KvEventKind)>>,
 }
 
 #[derive(LogicalModule)]
@@ -136,6 +516,12 @@ pub struct AppMetaManager {
     pub meta: RwLock<AppMetas>,
     view: View,
     app_meta_list_lock: Mutex<()>,
+    rpc_caller_deploy_app: RPCCaller<DeployAppReq>,
+    rpc_handler_deploy_app: RPCHandler<DeployAppReq>,
+    rpc_caller_activate_app: RPCCaller<ActivateAppReq>,
+    rpc_handler_activate_app: RPCHandler<ActivateAppReq>,
+    /// deploys and rollbacks run one by one
+    deploy_lock: tokio::sync::Mutex<()>,
 }
 
 // impl FnEvent {
@@ -157,6 +543,17 @@ impl AppMetaYaml {
             panic!("parse yaml config file failed, err: {:?}", e);
         })
     }
+    /// app.yaml uploaded by deploy, checked so that it can be turned into app meta
+    pub fn parse(yaml: &str) -> Result<AppMetaYaml, String> {
+        let meta: AppMetaYaml =
+            serde_yaml::from_str(yaml).map_err(|err| format!("parse app.yaml: {}", err))?;
+        for (fnname, fnmeta) in &meta.fns {
+            fnmeta
+                .check()
+                .map_err(|err| format!("fn {}: {}", fnname, err))?;
+        }
+        Ok(meta)
+    }
     // // return true if key set is valid
     // pub fn check_key_set(&self, key: &str) -> bool {
     //     self.fns
@@ -206,6 +603,23 @@ impl FnMeta {
         None
     }
 
+    /// the fn reads or removes the key, so it's better to run where the key is placed
+    pub fn consume_key(&self, key: &[u8]) -> bool {
+        self.match_key(key, KvOps::Get).is_some() || self.match_key(key, KvOps::Delete).is_some()
+    }
+
+    /// default ttl of the key when set by the fn
+    pub fn key_ttl(&self, key: &[u8]) -> Option<u32> {
+        let key = std::str::from_utf8(key).ok()?;
+        self.kvs.as_ref()?.iter().find_map(|kv| {
+            if kv.set && kv.pattern.match_key(key) {
+                kv.ttl
+            } else {
+                None
+            }
+        })
+    }
+
     pub fn try_get_kv_meta_by_index(&self, index: usize) -> Option<&KvMeta> {
         if let Some(kvs) = &self.kvs {
             return kvs.get(index);
@@ -233,17 +647,12 @@ impl FnMeta {
     /// find kv event trigger with match the `pattern` and `ope`
     pub fn find_will_trigger_kv_event(&self, pattern: &KeyPattern, ope: KvOps) -> Option<&KvMeta> {
         self.event.iter().find_map(|event| {
-            match event {
-                FnEvent::HttpApp => {}
-                FnEvent::KvSet(key_index) => {
-                    if ope == KvOps::Set {
-                        let res = self.get_kv_meta_by_index_unwrap(*key_index);
-                        if res.pattern == *pattern {
-                            return Some(res);
-                        }
-                    }
+            let (kind, key_index) = event.kv_event()?;
+            if kind.kv_ope() == ope {
+                let res = self.get_kv_meta_by_index_unwrap(key_index);
+                if res.pattern == *pattern {
+                    return Some(res);
                 }
-                FnEvent::HttpFn => {}
             }
             None
         })
@@ -297,7 +706,11 @@ impl From<FnMetaYaml> for FnMeta {
         let kvs = if let Some(kvs) = yaml.kvs {
             Some(
                 kvs.into_iter()
-                    .map(|(key, ops)| {
+                    .map(|(key, meta)| {
+                        let (ops, ttl) = match meta {
+                            KvMetaYaml::Ops(ops) => (ops, None),
+                            KvMetaYaml::Detail { ops, ttl } => (ops, ttl),
+                        };
                         let mut set = false;
                         let mut get = false;
                         let mut delete = false;
@@ -318,6 +731,7 @@ impl From<FnMetaYaml> for FnMeta {
                             set,
                             get,
                             pattern: KeyPattern::new(key),
+                            ttl,
                         }
                     })
                     .collect(),
@@ -329,6 +743,17 @@ impl From<FnMetaYaml> for FnMeta {
             event: yaml.event.into_iter().map(|e| e.into()).collect(),
             args: yaml.args.into_iter().map(|a| a.into()).collect(),
             kvs,
+            timeout: yaml
+                .timeout
+                .map_or(DEFAULT_FN_TIMEOUT, |secs| Duration::from_secs(secs.max(1))),
+            limits: FnLimits {
+                max_memory_pages: yaml.max_memory_pages.map(|pages| pages.clamp(1, 65536)),
+                fuel: yaml.fuel,
+                max_concurrency: yaml
+                    .max_concurrency
+                    .map_or(DEFAULT_FN_MAX_CONCURRENCY, |max| max.max(1)),
+            },
+            retry: yaml.retry.map(RetryPolicy::from).unwrap_or_default(),
         };
         // assert!(res.check_kv_valid());
         res
@@ -340,28 +765,87 @@ impl From<AppMetaYaml> for AppMetaFunction {
         let fns = yaml
             .fns
             .into_iter()
-            .map(|(fnname, fnmeta)| (fnname, fnmeta.into()))
+            .map(|(fnname, fnmeta)| (fnname, Arc::new(fnmeta.into())))
             .collect();
-        Self { fns }
+        Self {
+            fns,
+            pool: yaml.pool.map(PoolPolicy::from).unwrap_or_default(),
+        }
     }
 }
 
 impl AppMetaFunction {
+    pub fn pool(&self) -> PoolPolicy {
+        self.pool
+    }
     pub fn fns(&self) -> Vec<String> {
         self.fns.iter().map(|(fnname, _)| fnname.clone()).collect()
     }
-    pub fn get_fn_meta(&self, fnname: &str) -> Option<&FnMeta> {
+    /// memory pages of the fns whose instance caches are warmed once the app is deployed,
+    /// empty if the app doesn't prewarm
+    pub fn prewarmed_pages(&self) -> Vec<Option<u32>> {
+        if !self.pool.prewarm || self.pool.min_warm == 0 {
+            return vec![];
+        }
+        let mut pages: Vec<_> = self
+            .fns
+            .values()
+            .map(|fnmeta| fnmeta.limits.max_memory_pages)
+            .collect();
+        pages.sort();
+        pages.dedup();
+        pages
+    }
+    pub fn get_fn_meta(&self, fnname: &str) -> Option<&Arc<FnMeta>> {
         self.fns.get(fnname)
     }
-    pub fn http_trigger_fn(&self) -> Option<&str> {
+    pub fn http_trigger_fn(&self) -> Option<(&str, &HttpRoute)> {
         self.fns.iter().find_map(|(fnname, fnmeta)| {
-            if fnmeta.event.iter().any(|e| e == &FnEvent::HttpApp) {
-                Some(fnname.as_str())
-            } else {
-                None
-            }
+            fnmeta.event.iter().find_map(|e| match e {
+                FnEvent::HttpApp(route) => Some((fnname.as_str(), route)),
+                _ => None,
+            })
         })
     }
+    /// route `segs` after app name to the fn and its path params,
+    /// Err with status 404 if no fn matches the path, 405 if the method is not allowed
+    pub fn route_http(
+        &self,
+        segs: &[&str],
+        method: &str,
+    ) -> Result<(&str, BTreeMap<String, String>), u16> {
+        let mut candidates = vec![];
+        if let Some((&fnname, sub)) = segs.split_first() {
+            if let Some((fnname, fnmeta)) = self.fns.get_key_value(fnname) {
+                let routes: Vec<&HttpRoute> = fnmeta
+                    .event
+                    .iter()
+                    .filter_map(|e| match e {
+                        FnEvent::HttpFn(route) => Some(route),
+                        _ => None,
+                    })
+                    .collect();
+                // fns without http event are called by `POST /app/fn` as before
+                if routes.is_empty() && sub.is_empty() && method == "POST" {
+                    return Ok((fnname.as_str(), BTreeMap::new()));
+                }
+                candidates.extend(routes.into_iter().map(|route| (fnname.as_str(), route, sub)));
+            }
+        }
+        if let Some((fnname, route)) = self.http_trigger_fn() {
+            candidates.push((fnname, route, segs));
+        }
+        let mut status = 404;
+        for (fnname, route, sub) in candidates {
+            if let Some(params) = route.match_path(sub) {
+                if route.allow_method(method) {
+                    return Ok((fnname, params));
+                }
+                status = 405;
+            }
+        }
+        Err(status)
+    }
 }
 
 #[async_trait]
@@ -377,6 +861,11 @@ impl LogicalModule for AppMetaManager {
             }),
             view: View::new(args.logical_modules_ref.clone()),
             app_meta_list_lock: Mutex::new(()),
+            rpc_caller_deploy_app: RPCCaller::default(),
+            rpc_handler_deploy_app: RPCHandler::default(),
+            rpc_caller_activate_app: RPCCaller::default(),
+            rpc_handler_activate_app: RPCHandler::default(),
+            deploy_lock: tokio::sync::Mutex::new(()),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
@@ -385,6 +874,7 @@ impl LogicalModule for AppMetaManager {
             .await
             .load_all_app_meta(&self.view.os().file_path)
             .await?;
+        self.regist_deploy_rpcs();
         Ok(vec![])
     }
 }
@@ -402,9 +892,56 @@ impl AppMetas {
     pub fn get_pattern_triggers(
         &self,
         pattern: impl Borrow<str>,
-    ) -> Option<&Vec<(String, String)>> {
+    ) -> Option<&Vec<(String, String, KvEventKind)>> {
         self.pattern_2_app_fn.get(pattern.borrow())
     }
+    /// whether the `kvs` of app fn declares consumption of the key
+    pub fn fn_consume_key(&self, app: &str, func: &str, key: &[u8]) -> bool {
+        self.get_app_meta(app)
+            .and_then(|appmeta| appmeta.get_fn_meta(func))
+            .map_or(false, |fnmeta| fnmeta.consume_key(key))
+    }
+    /// default ttl of the key set by the app, declared in `kvs` of app.yaml
+    pub fn kv_ttl(&self, app: &str, key: &[u8]) -> Option<u32> {
+        self.get_app_meta(app)?
+            .fns
+            .values()
+            .find_map(|fnmeta| fnmeta.key_ttl(key))
+    }
+    /// timeout of the fn, the default one if the fn is gone
+    pub fn fn_timeout(&self, app: &str, func: &str) -> Duration {
+        self.get_app_meta(app)
+            .and_then(|appmeta| appmeta.get_fn_meta(func))
+            .map_or(DEFAULT_FN_TIMEOUT, |fnmeta| fnmeta.timeout)
+    }
+    /// retry policy of scheduling the events of fn, the default one if the fn is gone
+    pub fn fn_retry_policy(&self, app: &str, func: &str) -> RetryPolicy {
+        self.get_app_meta(app)
+            .and_then(|appmeta| appmeta.get_fn_meta(func))
+            .map_or_else(RetryPolicy::default, |fnmeta| fnmeta.retry)
+    }
+    /// instance caches to be warmed once the apps are deployed, (app, memory pages of its fns)
+    pub fn prewarmed_caches(&self) -> Vec<(String, Vec<Option<u32>>)> {
+        self.app_metas
+            .iter()
+            .map(|(app, appmeta)| (app.clone(), appmeta.prewarmed_pages()))
+            .filter(|(_, pages)| !pages.is_empty())
+            .collect()
+    }
+    /// timer events of all app fns, (app, fn, timer)
+    pub fn timers(&self) -> Vec<(String, String, TimerMeta)> {
+        let mut timers = vec![];
+        for (app, appmeta) in &self.app_metas {
+            for (func, fnmeta) in &appmeta.fns {
+                for event in &fnmeta.event {
+                    if let FnEvent::Timer(timer) = event {
+                        timers.push((app.clone(), func.clone(), timer.clone()));
+                    }
+                }
+            }
+        }
+        timers
+    }
     async fn load_all_app_meta(&mut self, file_dir: impl AsRef<Path>) -> WSResult<()> {
         let entries =
             fs::read_dir(file_dir.as_ref().join("apps")).map_err(|e| ErrCvt(e).to_ws_io_err())?;
@@ -429,30 +966,34 @@ impl AppMetas {
             };
 
             // transform
-            let meta: AppMetaFunction = res.into();
-
-            // build and checks
-            // - build up key pattern to app fn
-            for (fnname, fnmeta) in &meta.fns {
-                for event in &fnmeta.event {
-                    match event {
-                        // not kv event, no key pattern
-                        FnEvent::HttpFn => {}
-                        FnEvent::HttpApp => {}
-                        FnEvent::KvSet(key_index) => {
-                            let kvmeta = fnmeta.try_get_kv_meta_by_index(*key_index).unwrap();
-                            self.pattern_2_app_fn
-                                .entry(kvmeta.pattern.0.clone())
-                                .or_insert_with(Vec::new)
-                                .push((app_name.clone(), fnname.clone()));
-                        }
-                    }
-                }
-            }
-            let _ = self.app_metas.insert(app_name, meta);
+            self.insert_app(app_name, res.into());
         }
         Ok(())
     }
+    /// add or replace the app, key patterns listened by its fns are replaced along
+    fn insert_app(&mut self, app: String, meta: AppMetaFunction) {
+        for listeners in self.pattern_2_app_fn.values_mut() {
+            listeners.retain(|(listener, _, _)| *listener != app);
+        }
+        self.pattern_2_app_fn
+            .retain(|_, listeners| !listeners.is_empty());
+        // build and checks
+        // - build up key pattern to app fn
+        for (fnname, fnmeta) in &meta.fns {
+            for event in &fnmeta.event {
+                // not kv event, no key pattern
+                let Some((kind, key_index)) = event.kv_event() else {
+                    continue;
+                };
+                let kvmeta = fnmeta.try_get_kv_meta_by_index(key_index).unwrap();
+                self.pattern_2_app_fn
+                    .entry(kvmeta.pattern.0.clone())
+                    .or_insert_with(Vec::new)
+                    .push((app.clone(), fnname.clone(), kind));
+            }
+        }
+        let _ = self.app_metas.insert(app, meta);
+    }
 }
 
 impl AppMetaManager {
@@ -590,13 +1131,6 @@ impl AppMetaManager {
         AddServiceResp::Succ {}
     }
     pub async fn run_service_action(&self, req: RunServiceActionReq) -> RunServiceActionResp {
-        if !req.sync {
-            return RunServiceActionResp::Fail {
-                msg: "unsuppot async mode".to_owned(),
-            };
-        }
-
-        // sync logic
         // check service and action
         let service = match self.get_app_meta_service(&req.service) {
             Some(service) => service,
@@ -613,42 +1147,71 @@ impl AppMetaManager {
                 msg: format!("action {} not exist", req.action_cmd),
             };
         };
+        let cmd = RunCmdReq {
+            cmd: action.cmd.clone(),
+            workdir: service.app_dir,
+        };
 
+        if !req.sync {
+            let view = self.view.clone();
+            let invocation_id = self.view.async_invoker().invoke(
+                format!("{}/{}", req.service, action.name),
+                None,
+                None,
+                |_| async move {
+                    match view
+                        .appmeta_manager()
+                        .run_cmd(service.node, cmd, SERVICE_ACTION_ASYNC_TIMEOUT)
+                        .await
+                    {
+                        Ok(output) => InvocationOutput::Succ {
+                            status: 200,
+                            body: output.into_bytes(),
+                        },
+                        Err(msg) => InvocationOutput::Fail(msg),
+                    }
+                },
+            );
+            return RunServiceActionResp::Async { invocation_id };
+        }
+
+        match self
+            .run_cmd(service.node, cmd, Duration::from_secs(10))
+            .await
+        {
+            Ok(output) => RunServiceActionResp::Succ { output },
+            Err(msg) => RunServiceActionResp::Fail { msg },
+        }
+    }
+
+    /// run the cmd of service action on `node`, returns the output or error message
+    async fn run_cmd(
+        &self,
+        node: NodeID,
+        cmd: RunCmdReq,
+        timeout: Duration,
+    ) -> Result<String, String> {
         // handle rpc fail
         let res = match self
             .view
             .os()
             .remote_run_cmd_caller
-            .call(
-                self.view.p2p(),
-                service.node,
-                RunCmdReq {
-                    cmd: action.cmd.clone(),
-                    workdir: service.app_dir,
-                },
-                Some(Duration::from_secs(10)),
-            )
+            .call(self.view.p2p(), node, cmd, Some(timeout))
             .await
         {
             Ok(res) => res,
             Err(err) => {
-                return RunServiceActionResp::Fail {
-                    msg: format!("call remote_run_cmd_caller failed, err: {:?}", err),
-                };
+                return Err(format!("call remote_run_cmd_caller failed, err: {:?}", err));
             }
         };
 
         // handle cmd fail
-        let res = match res.dispatch.unwrap() {
-            super::network::proto::remote_sys::run_cmd_resp::Dispatch::Ok(res) => res,
+        match res.dispatch.unwrap() {
+            super::network::proto::remote_sys::run_cmd_resp::Dispatch::Ok(res) => Ok(res.output),
             super::network::proto::remote_sys::run_cmd_resp::Dispatch::Err(err) => {
-                return RunServiceActionResp::Fail {
-                    msg: format!("remote run cmd failed: {}", err.error),
-                }
+                Err(format!("remote run cmd failed: {}", err.error))
             }
-        };
-
-        RunServiceActionResp::Succ { output: res.output }
+        }
     }
 }
 
@@ -663,4 +1226,243 @@ mod test {
         let pattern = KeyPattern::new("xxxx_{}_{}".to_owned());
         assert!(pattern.match_key("xxxx_abc_123"));
     }
+
+    #[test]
+    fn test_consume_key() {
+        util::test_tracing_start();
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- kv_set: 0
+args:
+- kv_key: 0
+kvs:
+  wordcount_slice_{}: [delete]
+  wordcount_{}: [set]
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        assert!(fnmeta.consume_key(b"wordcount_slice_1"));
+        assert!(!fnmeta.consume_key(b"wordcount_1"));
+        assert!(!fnmeta.consume_key(b"other_1"));
+    }
+
+    #[test]
+    fn test_key_ttl() {
+        util::test_tracing_start();
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- http_app:
+args: []
+kvs:
+  wordcount_slice_{}: {ops: [set], ttl: 60}
+  wordcount_{}: [set]
+  other_{}: {ops: [get], ttl: 10}
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        assert_eq!(fnmeta.key_ttl(b"wordcount_slice_1"), Some(60));
+        assert_eq!(fnmeta.key_ttl(b"wordcount_1"), None);
+        // not set by the fn
+        assert_eq!(fnmeta.key_ttl(b"other_1"), None);
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        util::test_tracing_start();
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- http_app:
+args: []
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        assert_eq!(fnmeta.retry, RetryPolicy::default());
+
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- http_app:
+args: []
+retry:
+  max_attempts: 4
+  backoff_ms: 100
+  max_backoff_ms: 300
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        assert_eq!(fnmeta.retry.max_attempts, 4);
+        let backoffs: Vec<_> = (1..4).map(|retry| fnmeta.retry.backoff_of(retry)).collect();
+        assert_eq!(
+            backoffs,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(300)
+            ]
+        );
+        assert_eq!(fnmeta.retry.backoff_of(100), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_fn_limits() {
+        util::test_tracing_start();
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- http_app:
+args: []
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        assert_eq!(
+            fnmeta.limits,
+            FnLimits {
+                max_memory_pages: None,
+                fuel: None,
+                max_concurrency: DEFAULT_FN_MAX_CONCURRENCY
+            }
+        );
+
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- http_app:
+args: []
+max_memory_pages: 100000
+fuel: 1000000
+max_concurrency: 0
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        assert_eq!(
+            fnmeta.limits,
+            FnLimits {
+                max_memory_pages: Some(65536),
+                fuel: Some(1000000),
+                max_concurrency: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_fn_timeout() {
+        util::test_tracing_start();
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- http_app:
+args: []
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        assert_eq!(fnmeta.timeout, DEFAULT_FN_TIMEOUT);
+        // a runaway fn is still stopped by the fuel bounded by its timeout
+        assert_eq!(
+            fnmeta.limits.fuel_within(fnmeta.timeout),
+            DEFAULT_FUEL_PER_SEC * 60
+        );
+
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- http_app:
+args: []
+timeout: 5
+fuel: 1000000
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        assert_eq!(fnmeta.timeout, Duration::from_secs(5));
+        assert_eq!(fnmeta.limits.fuel_within(fnmeta.timeout), 1000000);
+    }
+
+    #[test]
+    fn test_kv_events() {
+        util::test_tracing_start();
+        let yaml: FnMetaYaml = serde_yaml::from_str(
+            r#"
+event:
+- kv_delete: 0
+- kv_new: 1
+- kv_change: 1
+args:
+- kv_key: 0
+kvs:
+  wordcount_slice_{}: [get]
+  wordcount_{}: [get]
+"#,
+        )
+        .unwrap();
+        let fnmeta: FnMeta = yaml.into();
+        let kinds: Vec<_> = fnmeta.event.iter().filter_map(|e| e.kv_event()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (KvEventKind::Delete, 0),
+                (KvEventKind::New, 1),
+                (KvEventKind::Change, 1)
+            ]
+        );
+        assert!(KvEventKind::Set.fires_on(KvEventKind::New));
+        assert!(KvEventKind::Set.fires_on(KvEventKind::Change));
+        assert!(!KvEventKind::Set.fires_on(KvEventKind::Delete));
+        assert!(!KvEventKind::New.fires_on(KvEventKind::Change));
+    }
+
+    #[test]
+    fn test_route_http() {
+        util::test_tracing_start();
+        let yaml: AppMetaYaml = serde_yaml::from_str(
+            r#"
+fns:
+  index:
+    event:
+    - http_app:
+    args: []
+  user:
+    event:
+    - http_fn:
+        methods: [get, delete]
+        path: /{id}
+    - http_fn:
+        methods: [put]
+        path: "{id}/name"
+    args: []
+  inner:
+    event:
+    - kv_set: 0
+    args: []
+    kvs:
+      k_{}: [get]
+"#,
+        )
+        .unwrap();
+        let app: AppMetaFunction = yaml.into();
+        let route = |path: &str, method| {
+            let segs: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+            app.route_http(&segs, method)
+                .map(|(fnname, params)| (fnname.to_owned(), params))
+        };
+        let params = |id: &str| BTreeMap::from([("id".to_owned(), id.to_owned())]);
+        assert_eq!(route("", "POST"), Ok(("index".to_owned(), BTreeMap::new())));
+        assert_eq!(route("", "GET"), Err(405));
+        assert_eq!(route("user/1", "GET"), Ok(("user".to_owned(), params("1"))));
+        assert_eq!(route("user/1", "DELETE"), Ok(("user".to_owned(), params("1"))));
+        assert_eq!(route("user/1/name", "PUT"), Ok(("user".to_owned(), params("1"))));
+        assert_eq!(route("user/1", "POST"), Err(405));
+        assert_eq!(route("user", "GET"), Err(404));
+        assert_eq!(route("inner", "POST"), Ok(("inner".to_owned(), BTreeMap::new())));
+        assert_eq!(route("other/1", "POST"), Err(404));
+    }
 }
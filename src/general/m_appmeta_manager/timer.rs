@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+
+/// `timer` event in app.yaml, either `cron` or `interval` in seconds
+/// ```yaml
+/// event:
+/// - timer:
+///     cron: "*/5 * * * *"
+///     missed: catch_up
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimerYaml {
+    pub cron: Option<String>,
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub missed: MissedFirePolicy,
+}
+
+/// what to do with the fires missed when master is down or busy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissedFirePolicy {
+    /// drop the missed fires, wait for the next one
+    #[default]
+    Skip,
+    /// run each missed fire with its own scheduled time
+    CatchUp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimerSchedule {
+    Cron(CronSchedule),
+    /// in ms
+    Interval(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimerMeta {
+    pub schedule: TimerSchedule,
+    pub missed: MissedFirePolicy,
+    /// schedule written in app.yaml, the stored fire time is reset when it changes
+    pub spec: String,
+}
+
+impl TimerMeta {
+    pub fn new(yaml: TimerYaml) -> Result<Self, String> {
+        let (schedule, spec) = match (yaml.cron, yaml.interval) {
+            (Some(cron), None) => (TimerSchedule::Cron(CronSchedule::parse(&cron)?), cron),
+            (None, Some(interval)) if interval > 0 => (
+                TimerSchedule::Interval(interval * 1000),
+                format!("interval {}", interval),
+            ),
+            _ => return Err("timer needs either cron or a positive interval".to_owned()),
+        };
+        Ok(Self {
+            schedule,
+            missed: yaml.missed,
+            spec,
+        })
+    }
+    /// first fire time strictly after `after_ms`, unix time in ms
+    pub fn next_fire(&self, after_ms: u64) -> Option<u64> {
+        match &self.schedule {
+            TimerSchedule::Cron(cron) => cron.next_after(after_ms),
+            TimerSchedule::Interval(interval) => Some((after_ms / interval + 1) * interval),
+        }
+    }
+}
+
+/// standard 5 fields cron `minute hour day-of-month month day-of-week` in utc,
+/// each field supports `*`, `a`, `a-b`, `*/n`, `a-b/n` and lists of them separated by `,`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // day-of-month and day-of-week are or'ed when both are restricted
+    days_any: bool,
+    weekdays_any: bool,
+}
+
+const MINUTE_MS: u64 = 60 * 1000;
+const DAY_MINUTES: u64 = 24 * 60;
+/// give up searching the next fire after years, for dates like Feb 30
+const CRON_SEARCH_DAYS: u64 = 366 * 5;
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("cron '{}' should have 5 fields", expr));
+        }
+        let mut weekdays = parse_cron_field(fields[4], 0, 7)?;
+        // both 0 and 7 are sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            weekdays,
+            days_any: fields[2] == "*",
+            weekdays_any: fields[4] == "*",
+        })
+    }
+    fn match_day(&self, days_since_epoch: u64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        // 1970-01-01 is thursday
+        let weekday = (days_since_epoch + 4) % 7;
+        let day_matched = self.days & (1 << day) != 0;
+        let weekday_matched = self.weekdays & (1 << weekday) != 0;
+        match (self.days_any, self.weekdays_any) {
+            (false, false) => day_matched || weekday_matched,
+            _ => day_matched && weekday_matched,
+        }
+    }
+    /// first matched minute strictly after `after_ms`, unix time in ms
+    pub fn next_after(&self, after_ms: u64) -> Option<u64> {
+        let start = after_ms / MINUTE_MS + 1;
+        let first_day = start / DAY_MINUTES;
+        for day in first_day..first_day + CRON_SEARCH_DAYS {
+            if !self.match_day(day) {
+                continue;
+            }
+            let first_minute = if day == first_day {
+                start % DAY_MINUTES
+            } else {
+                0
+            };
+            for minute in first_minute..DAY_MINUTES {
+                if self.hours & (1 << (minute / 60)) != 0
+                    && self.minutes & (1 << (minute % 60)) != 0
+                {
+                    return Some((day * DAY_MINUTES + minute) * MINUTE_MS);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// bit set of the values in field
+fn parse_cron_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let invalid = || format!("invalid cron field '{}'", field);
+    let parse = |v: &str| v.parse::<u64>().map_err(|_| invalid());
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, parse(step)?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse(start)?, parse(end)?)
+        } else {
+            let v = parse(range)?;
+            // `a/n` means from a to max
+            (v, if part.contains('/') { max } else { v })
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for v in (start..=end).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+/// (year, month, day) of days since 1970-01-01
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 2024-01-01 00:00:00 utc, monday
+    const BASE: u64 = 1704067200 * 1000;
+
+    #[test]
+    fn test_cron_next() {
+        let cron = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(cron.next_after(BASE), Some(BASE + 15 * MINUTE_MS));
+        assert_eq!(cron.next_after(BASE - 1), Some(BASE));
+
+        let cron = CronSchedule::parse("30 9 * * 1-5").unwrap();
+        assert_eq!(
+            cron.next_after(BASE),
+            Some(BASE + (9 * 60 + 30) * MINUTE_MS)
+        );
+        // saturday 2024-01-06 skips to monday 2024-01-08
+        let sat = BASE + 5 * DAY_MINUTES * MINUTE_MS;
+        assert_eq!(
+            cron.next_after(sat),
+            Some(sat + (2 * DAY_MINUTES + 9 * 60 + 30) * MINUTE_MS)
+        );
+
+        // 2024-02-29
+        let cron = CronSchedule::parse("0 0 29 2 *").unwrap();
+        assert_eq!(
+            cron.next_after(BASE),
+            Some(BASE + 59 * DAY_MINUTES * MINUTE_MS)
+        );
+        assert_eq!(
+            CronSchedule::parse("0 0 30 2 *").unwrap().next_after(BASE),
+            None
+        );
+
+        assert!(CronSchedule::parse("* * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_timer_yaml() {
+        let yaml: TimerYaml = serde_yaml::from_str("interval: 10").unwrap();
+        let timer = TimerMeta::new(yaml).unwrap();
+        assert_eq!(timer.missed, MissedFirePolicy::Skip);
+        assert_eq!(timer.next_fire(BASE + 1), Some(BASE + 10000));
+
+        let yaml: TimerYaml =
+            serde_yaml::from_str("{cron: '0 * * * *', missed: catch_up}").unwrap();
+        let timer = TimerMeta::new(yaml).unwrap();
+        assert_eq!(timer.missed, MissedFirePolicy::CatchUp);
+        assert_eq!(timer.next_fire(BASE), Some(BASE + 60 * MINUTE_MS));
+
+        let yaml: TimerYaml = serde_yaml::from_str("{cron: '0 * * * *', interval: 1}").unwrap();
+        assert!(TimerMeta::new(yaml).is_err());
+    }
+}
@@ -3,28 +3,45 @@
 //     pub view: KvStorageView,
 // }
 
-use super::{m_os::OperatingSystem, network::m_p2p::P2PModule};
+use super::{
+    m_os::OperatingSystem,
+    network::{
+        m_p2p::P2PModule,
+        msg_pack::{KeyRangeExt, KvResponseExt},
+        proto::{
+            self,
+            kv::{KeyRange, KvRequest, KvResponse},
+        },
+    },
+};
 use crate::{
     logical_module_view_impl,
-    result::WSResult,
+    result::{WSResult, WsSerialErr},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
-    util::JoinHandleWrapper,
+    util::{now_ms, JoinHandleWrapper},
 };
 use axum::async_trait;
 use bincode::serialize;
 use bincode::serialize_into;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
-use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{
+    ConflictableTransactionError, TransactionError, TransactionalTree, UnabortableTransactionError,
+};
+use std::{collections::HashMap, sync::OnceLock, time::Duration};
+use tokio::sync::broadcast;
 use ws_derive::LogicalModule;
 
 logical_module_view_impl!(View);
 logical_module_view_impl!(View, os, OperatingSystem);
 logical_module_view_impl!(View, p2p, P2PModule);
+logical_module_view_impl!(View, kv_store_engine, KvStoreEngine);
 
 #[derive(LogicalModule)]
 pub struct KvStoreEngine {
     db: OnceLock<sled::Db>,
+    // keys evicted by ttl
+    expired_tx: broadcast::Sender<Vec<u8>>,
     view: View,
 }
 
@@ -36,6 +53,7 @@ impl LogicalModule for KvStoreEngine {
     {
         Self {
             db: OnceLock::new(),
+            expired_tx: broadcast::channel(1024).0,
             view: View::new(args.logical_modules_ref.clone()),
         }
     }
@@ -55,7 +73,23 @@ impl LogicalModule for KvStoreEngine {
                 );
             db
         });
-        Ok(vec![])
+        migrate(self.db.get().unwrap())?;
+        let view = self.view.clone();
+        // evict expired keys in background
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(KV_REAP_INTERVAL).await;
+                let kv_store_engine = view.kv_store_engine();
+                let expired = kv_store_engine.reap_expired();
+                if !expired.is_empty() {
+                    tracing::debug!("{} expired keys evicted", expired.len());
+                }
+                for key in expired {
+                    // no subscriber on worker
+                    let _ = kv_store_engine.expired_tx.send(key);
+                }
+            }
+        }))])
     }
 }
 
@@ -92,11 +126,642 @@ impl KvStoreEngine {
         let key = key.make_key();
         let _ = self.db.get().unwrap().remove(key).unwrap();
     }
+    /// atomically update the value by `f`, `f` may be called multiple times,
+    /// returns the old and new value
+    pub fn update<K>(
+        &self,
+        key: K,
+        mut f: impl FnMut(Option<K::Value>) -> Option<K::Value>,
+    ) -> (Option<K::Value>, Option<K::Value>)
+    where
+        K: KeyType,
+    {
+        let key = key.make_key();
+        let mut new = None;
+        let old = self
+            .db
+            .get()
+            .unwrap()
+            .fetch_and_update(key, |old| {
+                new = f(old.map(|v| bincode::deserialize_from(v).unwrap()));
+                new.as_ref().map(|v| serialize(v).unwrap())
+            })
+            .unwrap()
+            .map(|v| bincode::deserialize_from(v.as_ref()).unwrap());
+        (old, new)
+    }
     pub fn flush(&self) {
         let _ = self.db.get().unwrap().flush().unwrap();
     }
+    pub fn apply_batch(&self, batch: KvBatch) {
+        self.db.get().unwrap().apply_batch(batch.0).unwrap();
+    }
+    /// scan the keys in `range`, `make` builds the key type from raw key,
+    /// the key type should keep the order of raw key, returns raw keys and values
+    pub fn scan<'a, K>(
+        &self,
+        range: &'a KeyRange,
+        make: impl Fn(&'a [u8]) -> K,
+    ) -> Vec<(Vec<u8>, K::Value)>
+    where
+        K: KeyType,
+    {
+        let db = self.db.get().unwrap();
+        let start = make(&range.start).make_key();
+        let iter = if range.prefix {
+            db.scan_prefix(start)
+        } else {
+            let end = make(&range.end).make_key();
+            if end <= start {
+                return vec![];
+            }
+            db.range(start..end)
+        };
+        iter.filter_map(|res| match res {
+            Ok((k, v)) => Some((
+                k[1..].to_vec(),
+                bincode::deserialize_from(v.as_ref()).unwrap(),
+            )),
+            Err(e) => {
+                tracing::error!("scan kv error: {:?}", e);
+                None
+            }
+        })
+        .collect()
+    }
+    /// all the keys of the type of `key`, returns raw keys and values in the order of raw key
+    pub fn scan_type<K>(&self, key: K) -> Vec<(Vec<u8>, K::Value)>
+    where
+        K: KeyType,
+    {
+        let db = self.db.get().unwrap();
+        db.scan_prefix([key.id()])
+            .filter_map(|kv| kv.ok())
+            .map(|(k, v)| {
+                (
+                    k[1..].to_vec(),
+                    bincode::deserialize_from(v.as_ref()).unwrap(),
+                )
+            })
+            .collect()
+    }
+    /// delete the values of the type of `key` matching `expired`, returns how many are deleted
+    pub fn evict<K>(&self, key: K, expired: impl Fn(&K::Value) -> bool) -> usize
+    where
+        K: KeyType,
+    {
+        let db = self.db.get().unwrap();
+        let mut batch = sled::Batch::default();
+        let mut count = 0;
+        for (k, v) in db.scan_prefix([key.id()]).filter_map(|kv| kv.ok()) {
+            if expired(&bincode::deserialize_from(v.as_ref()).unwrap()) {
+                batch.remove(k);
+                count += 1;
+            }
+        }
+        db.apply_batch(batch).unwrap();
+        count
+    }
+}
+
+/// writes applied atomically by `KvStoreEngine::apply_batch`
+#[derive(Default)]
+pub struct KvBatch(sled::Batch);
+
+impl KvBatch {
+    pub fn set<K>(&mut self, key: K, value: &K::Value)
+    where
+        K: KeyType,
+    {
+        self.0.insert(key.make_key(), serialize(value).unwrap());
+    }
+    pub fn del<K>(&mut self, key: K)
+    where
+        K: KeyType,
+    {
+        self.0.remove(key.make_key());
+    }
+    /// remove the directory record of the key
+    pub fn del_kv_position(&mut self, key: &[u8]) {
+        self.del(KeyTypeKvPosition(key));
+        self.del(KeyTypeKvExpire(key));
+    }
+    /// the key held here expires after `ttl` seconds, 0 for never
+    pub fn set_kv_expire(&mut self, key: &[u8], ttl: u32) {
+        if ttl == 0 {
+            self.del(KeyTypeKvExpire(key));
+            return;
+        }
+        let expire_at = now_ms() + ttl as u64 * 1000;
+        self.set(KeyTypeKvExpire(key), &expire_at);
+        self.set(KeyTypeKvExpireIndex(expire_at, key), &());
+    }
+}
+
+/// number of old versions kept for each key
+const KV_HISTORY_LEN: usize = 8;
+
+/// number of changes kept in the feed for watchers to resume
+const KV_CHANGE_LOG_LEN: u64 = 10000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum KvChangeKind {
+    Set,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KvChange {
+    pub key: Vec<u8>,
+    pub kind: KvChangeKind,
+    /// version of the value set, 0 if unknown
+    pub version: u64,
+}
+
+/// interval of evicting expired keys
+const KV_REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// layout of keys and values in db, bumped with a step in `migrate`
+const FORMAT_VERSION: u32 = 1;
+
+/// upgrade the db written by older versions in place,
+/// a db written by a newer version is refused
+fn migrate(db: &sled::Db) -> WSResult<()> {
+    let version = db
+        .get(KeyTypeFormatVersion.make_key())
+        .unwrap()
+        .map_or(0, |v| bincode::deserialize::<u32>(&v).unwrap());
+    if version > FORMAT_VERSION {
+        return Err(WsSerialErr::DbFormatUnsupported {
+            version,
+            supported: FORMAT_VERSION,
+        }
+        .into());
+    }
+    if version == FORMAT_VERSION {
+        return Ok(());
+    }
+    tracing::info!("migrate kv store engine db from format {}", version);
+    // all steps are applied at once, the db is left as it was if interrupted
+    let mut batch = sled::Batch::default();
+    if version < 1 {
+        migrate_raw_keys(db, &mut batch, KeyTypeKvPosition(&[]).id(), |value| {
+            Some(value.to_vec())
+        });
+        // values of kv are versioned since format 1, the existing ones are the first version
+        migrate_raw_keys(db, &mut batch, KeyTypeKv(&[]).id(), |value| {
+            let value = bincode::deserialize::<Vec<u8>>(value).ok()?;
+            Some(serialize(&KvValue { value, version: 1 }).unwrap())
+        });
+    }
+    batch.insert(
+        KeyTypeFormatVersion.make_key(),
+        serialize(&FORMAT_VERSION).unwrap(),
+    );
+    db.apply_batch(batch).unwrap();
+    let _ = db.flush().unwrap();
+    Ok(())
+}
+
+/// format 1 drops the length prefix of the raw keys of kv and positions, see `make_raw_key`,
+/// the values are converted by `convert`
+fn migrate_raw_keys(
+    db: &sled::Db,
+    batch: &mut sled::Batch,
+    id: u8,
+    convert: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) {
+    for (key, value) in db.scan_prefix([id]).filter_map(Result::ok) {
+        let raw = bincode::deserialize::<Vec<u8>>(&key[1..]).ok();
+        let (Some(raw), Some(value)) = (raw, convert(&value)) else {
+            tracing::warn!("skip malformed kv {:?} in migration", key);
+            continue;
+        };
+        batch.remove(key);
+        batch.insert(make_raw_key(id, &raw), value);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KvValue {
+    pub value: Vec<u8>,
+    /// increased by each write of the key
+    pub version: u64,
+}
+
+impl KvStoreEngine {
+    /// write the value returned by `f` with a larger version than current and `base_version`,
+    /// returns the new value, or the current value if `f` returns None
+    fn write_kv(
+        &self,
+        key: &[u8],
+        base_version: u64,
+        mut f: impl FnMut(Option<&KvValue>) -> Option<Vec<u8>>,
+    ) -> Result<KvValue, Option<KvValue>> {
+        let mut written = false;
+        let (old, new) = self.update(KeyTypeKv(key), |old| {
+            written = false;
+            let Some(value) = f(old.as_ref()) else {
+                return old;
+            };
+            written = true;
+            let version = old.as_ref().map_or(0, |old| old.version).max(base_version) + 1;
+            Some(KvValue { value, version })
+        });
+        if !written {
+            return Err(new);
+        }
+        if let Some(old) = old {
+            self.save_kv_history(key, old);
+        }
+        Ok(new.unwrap())
+    }
+    fn save_kv_history(&self, key: &[u8], old: KvValue) {
+        self.set(KeyTypeKvHistory(key, old.version), &old.value);
+        let db = self.db.get().unwrap();
+        let versions = db
+            .scan_prefix(KeyTypeKvHistory(key, 0).key_prefix())
+            .keys()
+            .filter_map(|k| k.ok())
+            .collect::<Vec<_>>();
+        if versions.len() > KV_HISTORY_LEN {
+            for k in &versions[..versions.len() - KV_HISTORY_LEN] {
+                let _ = db.remove(k).unwrap();
+            }
+        }
+    }
+    fn clear_kv_history(&self, key: &[u8]) {
+        let db = self.db.get().unwrap();
+        for k in db
+            .scan_prefix(KeyTypeKvHistory(key, 0).key_prefix())
+            .keys()
+            .filter_map(|k| k.ok())
+        {
+            let _ = db.remove(k).unwrap();
+        }
+    }
+    /// remove the data of the key held here
+    pub fn del_kv(&self, key: &[u8]) {
+        self.del(KeyTypeKv(key));
+        self.del(KeyTypeKvExpire(key));
+        self.clear_kv_history(key);
+    }
+    /// the key held here expires after `ttl` seconds, 0 for never
+    pub fn set_kv_expire(&self, key: &[u8], ttl: u32) {
+        let mut batch = KvBatch::default();
+        batch.set_kv_expire(key, ttl);
+        self.apply_batch(batch);
+    }
+    /// seconds left before the key held here expires, 0 for never
+    pub fn kv_ttl_left(&self, key: &[u8]) -> u32 {
+        self.get(KeyTypeKvExpire(key)).map_or(0, |expire_at| {
+            // expires soon but not never
+            (expire_at.saturating_sub(now_ms()).div_ceil(1000) as u32).max(1)
+        })
+    }
+    /// keys evicted by ttl from now on
+    pub fn subscribe_expired(&self) -> broadcast::Receiver<Vec<u8>> {
+        self.expired_tx.subscribe()
+    }
+    /// append the change at `revision` to the feed, old changes are dropped
+    pub fn append_kv_change(&self, revision: u64, change: &KvChange) {
+        self.set(KeyTypeKvChange(revision), change);
+        if revision > KV_CHANGE_LOG_LEN {
+            self.del(KeyTypeKvChange(revision - KV_CHANGE_LOG_LEN));
+        }
+    }
+    /// changes after `after` until `until` in order
+    pub fn kv_changes_between(&self, after: u64, until: u64) -> Vec<(u64, KvChange)> {
+        let db = self.db.get().unwrap();
+        let start = KeyTypeKvChange(after + 1).make_key();
+        let end = KeyTypeKvChange(until).make_key();
+        db.range(start..=end)
+            .filter_map(|kv| kv.ok())
+            .map(|(k, v)| {
+                (
+                    u64::from_be_bytes(k[1..].try_into().unwrap()),
+                    bincode::deserialize_from(v.as_ref()).unwrap(),
+                )
+            })
+            .collect()
+    }
+    /// revision range (first, last) of the changes kept, None if no change
+    pub fn kv_change_revisions(&self) -> Option<(u64, u64)> {
+        let db = self.db.get().unwrap();
+        let prefix = vec![KeyTypeKvChange(0).id()];
+        let revision = |k: sled::IVec| u64::from_be_bytes(k[1..].try_into().unwrap());
+        let first = db.scan_prefix(&prefix).keys().next()?.ok()?;
+        let last = db.scan_prefix(&prefix).keys().next_back()?.ok()?;
+        Some((revision(first), revision(last)))
+    }
+    /// evict the expired data and directory records, returns the evicted keys
+    pub fn reap_expired(&self) -> Vec<Vec<u8>> {
+        let db = self.db.get().unwrap();
+        let start = vec![KeyTypeKvExpireIndex(0, &[]).id()];
+        let end = KeyTypeKvExpireIndex(now_ms() + 1, &[]).make_key();
+        let mut expired = vec![];
+        for index in db.range(start..end).keys().filter_map(|k| k.ok()) {
+            let _ = db.remove(&index).unwrap();
+            let expire_at = u64::from_be_bytes(index[1..9].try_into().unwrap());
+            let key = &index[9..];
+            // the expiry is renewed or removed after indexed
+            if self.get(KeyTypeKvExpire(key)) != Some(expire_at) {
+                continue;
+            }
+            self.del_kv(key);
+            self.del(KeyTypeKvPosition(key));
+            expired.push(key.to_owned());
+        }
+        if !expired.is_empty() {
+            self.flush();
+        }
+        expired
+    }
+    /// `version` 0 for the latest, old versions are kept in bounded history
+    fn get_kv(&self, key: &[u8], version: u64) -> Option<KvValue> {
+        let cur = self.get(KeyTypeKv(key))?;
+        if version == 0 || cur.version == version {
+            return Some(cur);
+        }
+        self.get(KeyTypeKvHistory(key, version))
+            .map(|value| KvValue { value, version })
+    }
+    /// apply the data operation on local shard, flush is left to the caller
+    pub fn apply_kv_request(&self, req: KvRequest) -> KvResponse {
+        match req.op.unwrap() {
+            proto::kv::kv_request::Op::Set(set) => {
+                let Some(kv) = set.kv else {
+                    return KvResponse::new_common(vec![]);
+                };
+                // `kv.version` is the version before the key moved here
+                let new = self
+                    .write_kv(&kv.key, kv.version, |_| Some(kv.value.clone()))
+                    .unwrap();
+                self.set_kv_expire(&kv.key, set.ttl);
+                // only return the version of the written value
+                KvResponse::new_common(vec![proto::kv::KvPair {
+                    key: kv.key,
+                    value: vec![],
+                    version: new.version,
+                }])
+            }
+            proto::kv::kv_request::Op::Get(get) => {
+                let range = get.range.unwrap();
+                if !range.is_single_key() {
+                    let kvs = self
+                        .scan(&range, KeyTypeKv)
+                        .into_iter()
+                        .map(|(key, value)| kv_pair(key, value))
+                        .collect();
+                    return KvResponse::new_common(kvs);
+                }
+                let key = range.start;
+                let kvs = self
+                    .get_kv(&key, get.revision)
+                    .map(|value| kv_pair(key, value))
+                    .into_iter()
+                    .collect();
+                KvResponse::new_common(kvs)
+            }
+            proto::kv::kv_request::Op::Delete(delete) => {
+                let range = delete.range.unwrap();
+                if !range.is_single_key() {
+                    for (key, _) in self.scan(&range, KeyTypeKv) {
+                        self.del_kv(&key);
+                    }
+                    return KvResponse::new_common(vec![]);
+                }
+                self.del_kv(&range.start);
+                KvResponse::new_common(vec![])
+            }
+            proto::kv::kv_request::Op::Lock(lock) => {
+                tracing::warn!("lock should be handled by master, lock:{:?}", lock);
+                KvResponse::new_common(vec![])
+            }
+            proto::kv::kv_request::Op::Cas(cas) => {
+                let mut created = false;
+                let res = self.write_kv(&cas.key, 0, |cur| {
+                    created = cur.is_none();
+                    let matched = if cas.expected_version != 0 {
+                        cur.map(|cur| cur.version) == Some(cas.expected_version)
+                    } else if cas.expect_absent {
+                        cur.is_none()
+                    } else {
+                        cur.map(|cur| &cur.value) == Some(&cas.expected)
+                    };
+                    matched.then(|| cas.new_value.clone())
+                });
+                let (succ, current) = match res {
+                    Ok(new) => (true, Some(new)),
+                    Err(current) => (false, current),
+                };
+                // an existing key keeps its expiry
+                if succ && created {
+                    self.set_kv_expire(&cas.key, cas.ttl);
+                }
+                let kvs = current
+                    .map(|value| kv_pair(cas.key, value))
+                    .into_iter()
+                    .collect();
+                KvResponse::new_atomic(succ, kvs)
+            }
+            proto::kv::kv_request::Op::Add(add) => {
+                let mut created = false;
+                let res = self.write_kv(&add.key, 0, |cur| {
+                    created = cur.is_none();
+                    let cur = match cur {
+                        None => Some(0),
+                        Some(cur) => std::str::from_utf8(&cur.value)
+                            .ok()
+                            .and_then(|v| v.parse::<i64>().ok()),
+                    };
+                    // not an integer or overflow, keep the old value
+                    cur.and_then(|cur| cur.checked_add(add.delta))
+                        .map(|new| new.to_string().into_bytes())
+                });
+                let (succ, current) = match res {
+                    Ok(new) => (true, Some(new)),
+                    Err(current) => (false, current),
+                };
+                if succ && created {
+                    self.set_kv_expire(&add.key, add.ttl);
+                }
+                let kvs = current
+                    .map(|value| kv_pair(add.key, value))
+                    .into_iter()
+                    .collect();
+                KvResponse::new_atomic(succ, kvs)
+            }
+            proto::kv::kv_request::Op::Guard(guard) => {
+                let cur = self.get(KeyTypeKv(&guard.key));
+                let succ = guard_matched(&guard, cur.as_ref());
+                let kvs = cur
+                    .map(|value| kv_pair(guard.key, value))
+                    .into_iter()
+                    .collect();
+                KvResponse::new_atomic(succ, kvs)
+            }
+        }
+    }
+    /// apply the requests in one sled transaction, nothing is written if any guard fails
+    /// or any operation is not allowed in transaction.
+    /// - `moved_in` holds current values of keys moved from other nodes, None for not exist
+    /// - keys written are recorded as held by `hold_on` if it's set, on master
+    /// - `with_writes` builds the writes committed with the responses, like the triggers queued,
+    ///   it may be called again when the transaction is retried
+    pub fn apply_kv_txn(
+        &self,
+        reqs: &[KvRequest],
+        moved_in: &HashMap<Vec<u8>, Option<KvValue>>,
+        hold_on: Option<NodeID>,
+        with_writes: impl Fn(&[KvResponse]) -> KvBatch,
+    ) -> Option<Vec<KvResponse>> {
+        let db = self.db.get().unwrap();
+        let res = db.transaction(|tx| {
+            for (key, value) in moved_in {
+                match value {
+                    Some(value) => tx_set(tx, KeyTypeKv(key), value)?,
+                    None => tx_del(tx, KeyTypeKv(key))?,
+                }
+            }
+            let mut resps = Vec::with_capacity(reqs.len());
+            for req in reqs {
+                let resp = match req.op.as_ref().unwrap() {
+                    proto::kv::kv_request::Op::Guard(guard) => {
+                        let cur = tx_get(tx, KeyTypeKv(&guard.key))?;
+                        if !guard_matched(guard, cur.as_ref()) {
+                            return Err(ConflictableTransactionError::Abort(()));
+                        }
+                        let kvs = cur
+                            .map(|value| kv_pair(guard.key.clone(), value))
+                            .into_iter()
+                            .collect();
+                        KvResponse::new_atomic(true, kvs)
+                    }
+                    proto::kv::kv_request::Op::Get(get) => {
+                        let range = get.range.as_ref().unwrap();
+                        if !range.is_single_key() || get.revision != 0 {
+                            return Err(ConflictableTransactionError::Abort(()));
+                        }
+                        let kvs = tx_get(tx, KeyTypeKv(&range.start))?
+                            .map(|value| kv_pair(range.start.clone(), value))
+                            .into_iter()
+                            .collect();
+                        KvResponse::new_common(kvs)
+                    }
+                    proto::kv::kv_request::Op::Set(set) => {
+                        let Some(kv) = set.kv.as_ref() else {
+                            return Err(ConflictableTransactionError::Abort(()));
+                        };
+                        let old = tx_get(tx, KeyTypeKv(&kv.key))?;
+                        let version =
+                            old.as_ref().map_or(0, |old| old.version).max(kv.version) + 1;
+                        if let Some(old) = old {
+                            // trimmed by the next write out of transaction
+                            tx_set(tx, KeyTypeKvHistory(&kv.key, old.version), &old.value)?;
+                        }
+                        let new = KvValue {
+                            value: kv.value.clone(),
+                            version,
+                        };
+                        tx_set(tx, KeyTypeKv(&kv.key), &new)?;
+                        if let Some(node) = hold_on {
+                            tx_set(tx, KeyTypeKvPosition(&kv.key), &node)?;
+                        }
+                        if set.ttl == 0 {
+                            tx_del(tx, KeyTypeKvExpire(&kv.key))?;
+                        } else {
+                            let expire_at = now_ms() + set.ttl as u64 * 1000;
+                            tx_set(tx, KeyTypeKvExpire(&kv.key), &expire_at)?;
+                            tx_set(tx, KeyTypeKvExpireIndex(expire_at, &kv.key), &())?;
+                        }
+                        KvResponse::new_common(vec![proto::kv::KvPair {
+                            key: kv.key.clone(),
+                            value: vec![],
+                            version,
+                        }])
+                    }
+                    proto::kv::kv_request::Op::Delete(delete) => {
+                        let range = delete.range.as_ref().unwrap();
+                        if !range.is_single_key() {
+                            return Err(ConflictableTransactionError::Abort(()));
+                        }
+                        tx_del(tx, KeyTypeKv(&range.start))?;
+                        tx_del(tx, KeyTypeKvExpire(&range.start))?;
+                        if hold_on.is_some() {
+                            tx_del(tx, KeyTypeKvPosition(&range.start))?;
+                        }
+                        KvResponse::new_common(vec![])
+                    }
+                    _ => {
+                        tracing::warn!("operation not allowed in transaction: {:?}", req);
+                        return Err(ConflictableTransactionError::Abort(()));
+                    }
+                };
+                resps.push(resp);
+            }
+            tx.apply_batch(&with_writes(&resps).0)?;
+            Ok(resps)
+        });
+        match res {
+            Ok(resps) => {
+                for req in reqs {
+                    if let Some(proto::kv::kv_request::Op::Delete(delete)) = req.op.as_ref() {
+                        self.clear_kv_history(&delete.range.as_ref().unwrap().start);
+                    }
+                }
+                Some(resps)
+            }
+            Err(TransactionError::Abort(())) => None,
+            Err(TransactionError::Storage(err)) => {
+                panic!("kv transaction failed: {:?}", err);
+            }
+        }
+    }
+}
+
+fn kv_pair(key: Vec<u8>, value: KvValue) -> proto::kv::KvPair {
+    proto::kv::KvPair {
+        key,
+        value: value.value,
+        version: value.version,
+    }
 }
 
+fn guard_matched(guard: &proto::kv::kv_request::KvGuardRequest, cur: Option<&KvValue>) -> bool {
+    if guard.expect_absent {
+        cur.is_none()
+    } else {
+        cur.map(|cur| &cur.value) == Some(&guard.expected)
+    }
+}
+
+fn tx_get<K: KeyType>(
+    tx: &TransactionalTree,
+    key: K,
+) -> Result<Option<K::Value>, UnabortableTransactionError> {
+    Ok(tx
+        .get(key.make_key())?
+        .map(|v| bincode::deserialize_from(v.as_ref()).unwrap()))
+}
+
+fn tx_set<K: KeyType>(
+    tx: &TransactionalTree,
+    key: K,
+    value: &K::Value,
+) -> Result<(), UnabortableTransactionError> {
+    let _ = tx.insert(key.make_key(), serialize(value).unwrap())?;
+    Ok(())
+}
+
+fn tx_del<K: KeyType>(tx: &TransactionalTree, key: K) -> Result<(), UnabortableTransactionError> {
+    let _ = tx.remove(key.make_key())?;
+    Ok(())
+}
+
+/// key of the data kept in kv store engine, prefixed by the id of its type,
+/// the ids 0-8 are taken by the key types here, 9-14 by the key types kept by master modules:
+/// invocation 9, dead letter 10, trigger queue 11, app deployment 13, app artifact 14
 pub trait KeyType: Serialize {
     type Value: Serialize + DeserializeOwned;
     fn id(&self) -> u8;
@@ -108,25 +773,57 @@ pub trait KeyType: Serialize {
     }
 }
 
+/// id + raw key without length prefix, so that the order of raw key is kept for scan
+pub fn make_raw_key(id: u8, raw: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + raw.len());
+    key.push(id);
+    key.extend_from_slice(raw);
+    key
+}
+
 pub struct KeyTypeKv<'a>(pub &'a [u8]);
 
 pub struct KeyTypeKvPosition<'a>(pub &'a [u8]);
 
 pub struct KeyTypeServiceMeta<'a>(pub &'a [u8]);
 
+/// old value of key at the version
+pub struct KeyTypeKvHistory<'a>(pub &'a [u8], pub u64);
+
+/// change of kv at the revision of feed
+pub struct KeyTypeKvChange(pub u64);
+
+/// unix time in ms when the key expires
+pub struct KeyTypeKvExpire<'a>(pub &'a [u8]);
+
+/// keys ordered by expiry time
+pub struct KeyTypeKvExpireIndex<'a>(pub u64, pub &'a [u8]);
+
 pub struct KeyTypeServiceList;
 
+/// version of the db format, kept apart from the ids of data
+pub struct KeyTypeFormatVersion;
+
+/// unix time in ms of the next fire of timer (app, fn, schedule spec)
+pub struct KeyTypeTimerNextFire<'a>(pub &'a str, pub &'a str, pub &'a str);
+
 impl KeyType for KeyTypeKvPosition<'_> {
     type Value = NodeID;
     fn id(&self) -> u8 {
         0
     }
+    fn make_key(&self) -> Vec<u8> {
+        make_raw_key(self.id(), self.0)
+    }
 }
 impl KeyType for KeyTypeKv<'_> {
-    type Value = Vec<u8>;
+    type Value = KvValue;
     fn id(&self) -> u8 {
         1
     }
+    fn make_key(&self) -> Vec<u8> {
+        make_raw_key(self.id(), self.0)
+    }
 }
 impl KeyType for KeyTypeServiceMeta<'_> {
     type Value = Vec<u8>;
@@ -134,12 +831,79 @@ impl KeyType for KeyTypeServiceMeta<'_> {
         2
     }
 }
+impl KeyType for KeyTypeKvHistory<'_> {
+    type Value = Vec<u8>;
+    fn id(&self) -> u8 {
+        4
+    }
+    // versions of one key are ordered
+    fn make_key(&self) -> Vec<u8> {
+        let mut key = self.key_prefix();
+        key.extend_from_slice(&self.1.to_be_bytes());
+        key
+    }
+}
+impl KeyTypeKvHistory<'_> {
+    /// prefix of all versions of the key
+    fn key_prefix(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(1 + 8 + self.0.len() + 8);
+        key.push(self.id());
+        key.extend_from_slice(&(self.0.len() as u64).to_be_bytes());
+        key.extend_from_slice(self.0);
+        key
+    }
+}
+impl KeyType for KeyTypeKvChange {
+    type Value = KvChange;
+    fn id(&self) -> u8 {
+        7
+    }
+    // ordered by revision
+    fn make_key(&self) -> Vec<u8> {
+        make_raw_key(self.id(), &self.0.to_be_bytes())
+    }
+}
+impl KeyType for KeyTypeKvExpire<'_> {
+    type Value = u64;
+    fn id(&self) -> u8 {
+        5
+    }
+    fn make_key(&self) -> Vec<u8> {
+        make_raw_key(self.id(), self.0)
+    }
+}
+impl KeyType for KeyTypeKvExpireIndex<'_> {
+    type Value = ();
+    fn id(&self) -> u8 {
+        6
+    }
+    // ordered by expiry time
+    fn make_key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(1 + 8 + self.1.len());
+        key.push(self.id());
+        key.extend_from_slice(&self.0.to_be_bytes());
+        key.extend_from_slice(self.1);
+        key
+    }
+}
+impl KeyType for KeyTypeFormatVersion {
+    type Value = u32;
+    fn id(&self) -> u8 {
+        u8::MAX
+    }
+}
 impl KeyType for KeyTypeServiceList {
     type Value = Vec<u8>;
     fn id(&self) -> u8 {
         3
     }
 }
+impl KeyType for KeyTypeTimerNextFire<'_> {
+    type Value = u64;
+    fn id(&self) -> u8 {
+        8
+    }
+}
 
 impl Serialize for KeyTypeKvPosition<'_> {
     fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
@@ -159,8 +923,86 @@ impl Serialize for KeyTypeServiceMeta<'_> {
     }
 }
 
+impl Serialize for KeyTypeKvHistory<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.0, self.1).serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeKvChange {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeKvExpire<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeKvExpireIndex<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.0, self.1).serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeTimerNextFire<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.0, self.1, self.2).serialize(serializer)
+    }
+}
+
 impl Serialize for KeyTypeServiceList {
     fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         serializer.serialize_unit()
     }
 }
+
+impl Serialize for KeyTypeFormatVersion {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_migrate_from_format_0() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        // format 0 keys are id + bincode of the raw key
+        let mut old_key = vec![KeyTypeKvPosition(&[]).id()];
+        serialize_into(&mut old_key, b"k1".as_slice()).unwrap();
+        let _ = db.insert(&old_key, serialize(&3u32).unwrap()).unwrap();
+        // and values of kv are not versioned
+        let mut old_kv_key = vec![KeyTypeKv(&[]).id()];
+        serialize_into(&mut old_kv_key, b"k1".as_slice()).unwrap();
+        let _ = db
+            .insert(&old_kv_key, serialize(&b"v1".to_vec()).unwrap())
+            .unwrap();
+        migrate(&db).unwrap();
+
+        let pos = db
+            .get(KeyTypeKvPosition(b"k1").make_key())
+            .unwrap()
+            .unwrap();
+        assert_eq!(bincode::deserialize::<NodeID>(&pos).unwrap(), 3);
+        assert!(db.get(&old_key).unwrap().is_none());
+        let kv = db.get(KeyTypeKv(b"k1").make_key()).unwrap().unwrap();
+        let kv: KvValue = bincode::deserialize(&kv).unwrap();
+        assert_eq!((kv.value.as_slice(), kv.version), (b"v1".as_slice(), 1));
+        // migrated only once
+        migrate(&db).unwrap();
+        assert_eq!(db.len(), 3);
+
+        let _ = db
+            .insert(
+                KeyTypeFormatVersion.make_key(),
+                serialize(&(FORMAT_VERSION + 1)).unwrap(),
+            )
+            .unwrap();
+        assert!(migrate(&db).is_err());
+    }
+}
@@ -7,6 +7,7 @@ use crate::{
     result::WSResult,
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
     util::JoinHandleWrapper,
+    worker::m_instance_manager::InstanceManager,
 };
 
 use super::network::{
@@ -18,6 +19,11 @@ logical_module_view_impl!(MetricPublisherView);
 logical_module_view_impl!(MetricPublisherView, p2p, P2PModule);
 // logical_module_view_impl!(MetricPublisherView, metric_observor, Option<MetricObservor>);
 logical_module_view_impl!(MetricPublisherView, metric_publisher, MetricPublisher);
+logical_module_view_impl!(
+    MetricPublisherView,
+    instance_manager,
+    Option<InstanceManager>
+);
 
 #[derive(LogicalModule)]
 pub struct MetricPublisher {
@@ -58,6 +64,8 @@ async fn report_metric_task(view: MetricPublisherView) {
     );
     // First we update all information of our `System` struct.
     sys.refresh_all();
+    let nodes_config = &view.p2p().nodes_config;
+    let is_worker = nodes_config.is_worker_node(nodes_config.this_node());
     loop {
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         sys.refresh_all();
@@ -72,6 +80,16 @@ async fn report_metric_task(view: MetricPublisherView) {
             mem_used: sys.used_memory() as f32,
             cpu_all: cpu_all as f32,
             mem_all: sys.total_memory() as f32,
+            cold_starts: if is_worker {
+                view.instance_manager().take_cold_starts()
+            } else {
+                vec![]
+            },
+            pools: if is_worker {
+                view.instance_manager().pool_occupancy()
+            } else {
+                vec![]
+            },
         };
         // println!("send metrics to master");
         // let node_config = view.p2p().nodes_config;
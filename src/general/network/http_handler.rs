@@ -1,25 +1,48 @@
-use super::m_p2p::P2PModule;
+use super::{m_p2p::P2PModule, proto};
 use crate::{
     apis::{
-        self, AddServiceReq, AddServiceResp, ApiHandler, DeleteServiceReq, DeleteServiceResp,
-        GetServiceListResp, RunServiceActionReq, RunServiceActionResp,
+        self, AddServiceReq, AddServiceResp, ApiHandler, CancelInvocationReq, CancelInvocationResp,
+        DeleteServiceReq, DeleteServiceResp, DeployAppReq, DeployAppResp, GetDeadLetterReq,
+        GetDeadLetterResp, GetInvocationReq, GetInvocationResp, GetServiceListResp, GetWorkflowReq,
+        GetWorkflowResp, KvWatchEvent, ListAppVersionsReq, ListAppVersionsResp, ListDeadLettersReq,
+        ListDeadLettersResp, ReplayDeadLetterReq, ReplayDeadLetterResp, RollbackAppReq,
+        RollbackAppResp, RunServiceActionReq, RunServiceActionResp, WatchKvReq, WatchKvResp,
     },
     general::m_appmeta_manager::AppMetaManager,
     logical_module_view_impl,
+    master::{
+        m_async_invoker::AsyncInvoker, m_dead_letters::DeadLetters, m_kv_watch::KvWatch,
+        m_workflow::Workflows,
+    },
     sys::{LogicalModule, LogicalModulesRef},
 };
 use async_trait::async_trait;
 use axum::{
-    extract::Path,
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, RawQuery},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::any,
     Router,
 };
+use serde::Serialize;
 use std::sync::atomic::AtomicUsize;
-use std::{net::SocketAddr, sync::OnceLock};
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    sync::OnceLock,
+    time::Duration,
+};
 use tower_http::cors::CorsLayer;
 pub type ReqId = usize;
 
+/// apps are uploaded to deploy api as base64 in json
+const MAX_API_BODY: usize = 64 * 1024 * 1024;
+
+/// fn responses proxied by master are passed over p2p as a whole, the larger ones are refused,
+/// while fns called in redirect mode or on workers directly answer any size
+pub const MAX_PROXIED_BODY: usize = 16 * 1024 * 1024;
+
 pub struct LocalReqIdAllocator {
     id: AtomicUsize,
 }
@@ -34,10 +57,110 @@ impl LocalReqIdAllocator {
     }
 }
 
+/// http request triggering fn
+#[derive(Debug, Clone, Serialize)]
+pub struct HttpReq {
+    pub method: String,
+    /// path params matched by the route
+    pub params: BTreeMap<String, String>,
+    pub query: BTreeMap<String, String>,
+    /// lowercase names, values of the same name are joined by `,`
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip)]
+    pub body: Vec<u8>,
+    /// query string as received, kept when the request is forwarded
+    #[serde(skip)]
+    pub raw_query: Option<String>,
+}
+
+impl HttpReq {
+    fn new(
+        method: Method,
+        (query, raw_query): (BTreeMap<String, String>, Option<String>),
+        header_map: HeaderMap,
+        body: Bytes,
+    ) -> Self {
+        let mut headers: BTreeMap<String, String> = BTreeMap::new();
+        for (name, value) in header_map.iter() {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let _ = headers
+                .entry(name.as_str().to_owned())
+                .and_modify(|v| {
+                    v.push(',');
+                    v.push_str(&value)
+                })
+                .or_insert_with(|| value.into_owned());
+        }
+        Self {
+            method: method.as_str().to_owned(),
+            params: BTreeMap::new(),
+            query,
+            headers,
+            body: body.to_vec(),
+            raw_query,
+        }
+    }
+    /// request forwarded by the gateway of master to the worker running `route`
+    pub fn into_invoke_req(self, route: &str) -> proto::sche::HttpInvokeReq {
+        proto::sche::HttpInvokeReq {
+            route: route.to_owned(),
+            method: self.method,
+            query: self.query.into_iter().collect(),
+            headers: self.headers.into_iter().collect(),
+            body: self.body,
+            invocation_id: String::new(),
+            workflow_id: 0,
+        }
+    }
+}
+
+impl From<proto::sche::HttpInvokeReq> for HttpReq {
+    fn from(req: proto::sche::HttpInvokeReq) -> Self {
+        Self {
+            method: req.method,
+            params: BTreeMap::new(),
+            query: req.query.into_iter().collect(),
+            headers: req.headers.into_iter().collect(),
+            body: req.body,
+            raw_query: None,
+        }
+    }
+}
+
+/// http response of fn, either answered by the worker or proxied by master
+pub fn invoke_resp_response(resp: proto::sche::HttpInvokeResp) -> Response {
+    let status = u16::try_from(resp.status)
+        .ok()
+        .and_then(|status| StatusCode::from_u16(status).ok());
+    let Some(status) = status else {
+        tracing::warn!("fn responds invalid status {}", resp.status);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let mut response = (status, resp.body).into_response();
+    // headers set by fn replace the default ones like content-type
+    let mut set_names = HashSet::new();
+    for proto::sche::HttpHeader { name, value } in resp.headers {
+        match (
+            HeaderName::try_from(name.as_str()),
+            HeaderValue::try_from(value.as_str()),
+        ) {
+            (Ok(name), Ok(value)) => {
+                if set_names.insert(name.clone()) {
+                    let _ = response.headers_mut().insert(name, value);
+                } else {
+                    let _ = response.headers_mut().append(name, value);
+                }
+            }
+            _ => tracing::warn!("fn responds invalid header {}: {}", name, value),
+        }
+    }
+    response
+}
+
 #[async_trait]
 pub trait HttpHandler: LogicalModule {
     // fn alloc_local_req_id(&self) -> ReqId;
-    async fn handle_request(&self, req_fn: &str, http_text: String) -> Response;
+    async fn handle_request(&self, req_fn: &str, req: HttpReq) -> Response;
     // async fn select_node(
     //     &self,
     //     req: proto::sche::FnEventScheRequest,
@@ -48,6 +171,10 @@ logical_module_view_impl!(HttpHandlerView);
 logical_module_view_impl!(HttpHandlerView, p2p, P2PModule);
 logical_module_view_impl!(HttpHandlerView, http_handler, Box<dyn HttpHandler>);
 logical_module_view_impl!(HttpHandlerView, appmeta_manager, AppMetaManager);
+logical_module_view_impl!(HttpHandlerView, kv_watch, Option<KvWatch>);
+logical_module_view_impl!(HttpHandlerView, async_invoker, Option<AsyncInvoker>);
+logical_module_view_impl!(HttpHandlerView, dead_letters, Option<DeadLetters>);
+logical_module_view_impl!(HttpHandlerView, workflows, Option<Workflows>);
 
 pub struct ApiHandlerImpl;
 
@@ -94,6 +221,82 @@ impl ApiHandler for ApiHandlerImpl {
             .run_service_action(req)
             .await
     }
+
+    async fn handle_watch_kv(&self, req: WatchKvReq) -> WatchKvResp {
+        let resp = http_handler_view()
+            .kv_watch()
+            .watch(proto::kv::KvWatchReq {
+                key: req.key.into_bytes(),
+                pattern: req.pattern,
+                from_revision: req.from_revision,
+                timeout_ms: req.timeout_ms.max(0) as u32,
+            })
+            .await;
+        WatchKvResp::Events {
+            events: resp
+                .events
+                .into_iter()
+                .map(|event| KvWatchEvent {
+                    key: String::from_utf8_lossy(&event.key).into_owned(),
+                    delete: event.delete,
+                    version: event.version,
+                    revision: event.revision,
+                })
+                .collect(),
+            revision: resp.revision,
+            compacted: resp.compacted,
+        }
+    }
+
+    async fn handle_get_invocation(&self, req: GetInvocationReq) -> GetInvocationResp {
+        http_handler_view().async_invoker().get(&req.invocation_id)
+    }
+
+    async fn handle_cancel_invocation(&self, req: CancelInvocationReq) -> CancelInvocationResp {
+        http_handler_view()
+            .async_invoker()
+            .cancel(&req.invocation_id)
+            .await
+    }
+
+    async fn handle_list_dead_letters(&self, req: ListDeadLettersReq) -> ListDeadLettersResp {
+        http_handler_view().dead_letters().list(&req.app)
+    }
+
+    async fn handle_get_dead_letter(&self, req: GetDeadLetterReq) -> GetDeadLetterResp {
+        http_handler_view().dead_letters().get(&req.id)
+    }
+
+    async fn handle_replay_dead_letter(&self, req: ReplayDeadLetterReq) -> ReplayDeadLetterResp {
+        http_handler_view().dead_letters().replay(&req.id).await
+    }
+
+    async fn handle_get_workflow(&self, req: GetWorkflowReq) -> GetWorkflowResp {
+        http_handler_view()
+            .workflows()
+            .get(
+                req.workflow_id,
+                Duration::from_millis(req.wait_ms.max(0) as u64),
+            )
+            .await
+    }
+
+    async fn handle_deploy_app(&self, req: DeployAppReq) -> DeployAppResp {
+        http_handler_view().appmeta_manager().deploy_app(req).await
+    }
+
+    async fn handle_rollback_app(&self, req: RollbackAppReq) -> RollbackAppResp {
+        http_handler_view()
+            .appmeta_manager()
+            .rollback_app(req)
+            .await
+    }
+
+    async fn handle_list_app_versions(&self, req: ListAppVersionsReq) -> ListAppVersionsResp {
+        http_handler_view()
+            .appmeta_manager()
+            .list_app_versions(&req.app)
+    }
 }
 
 lazy_static::lazy_static!(
@@ -119,14 +322,15 @@ pub async fn start_http_handler(modsref: LogicalModulesRef) {
     // .route("metrics")
     //
     let app = if view.p2p().nodes_config.this_node() == view.p2p().nodes_config.get_master_node() {
-        apis::add_routers(app)
+        apis::add_routers(app).layer(DefaultBodyLimit::max(MAX_API_BODY))
     } else {
         app
     };
 
+    // fns and sub-paths are routed by method in `HttpHandler`
     let app = app
-        .route("/:app/:fn", post(handler2))
-        .route("/:route", post(handler))
+        .route("/:app/*path", any(handler2))
+        .route("/:route", any(handler))
         .layer(CorsLayer::permissive());
 
     axum::Server::bind(&addr)
@@ -137,16 +341,36 @@ pub async fn start_http_handler(modsref: LogicalModulesRef) {
     tracing::info!("http end on {}", addr);
 }
 
-async fn handler2(Path((app, func)): Path<(String, String)>, body: String) -> impl IntoResponse {
+async fn handler2(
+    method: Method,
+    Path((app, path)): Path<(String, String)>,
+    Query(query): Query<BTreeMap<String, String>>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
     http_handler_view()
         .http_handler()
-        .handle_request(&format!("{app}/{func}"), body)
+        .handle_request(
+            &format!("{app}/{}", path.trim_start_matches('/')),
+            HttpReq::new(method, (query, raw_query), headers, body),
+        )
         .await
 }
 
-async fn handler(route: Path<String>, body: String) -> impl IntoResponse {
+async fn handler(
+    method: Method,
+    route: Path<String>,
+    Query(query): Query<BTreeMap<String, String>>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
     http_handler_view()
         .http_handler()
-        .handle_request(route.as_str(), body)
+        .handle_request(
+            route.as_str(),
+            HttpReq::new(method, (query, raw_query), headers, body),
+        )
         .await
 }
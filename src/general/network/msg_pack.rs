@@ -2,7 +2,10 @@ use downcast_rs::{impl_downcast, Downcast};
 
 use super::{
     m_p2p::MsgId,
-    proto::{self, kv::KvResponse},
+    proto::{
+        self,
+        kv::{KvResponse, KvResponses},
+    },
 };
 
 macro_rules! count_modules {
@@ -60,7 +63,21 @@ define_msg_ids!(
     proto::remote_sys::GetDirContentReq,
     proto::remote_sys::GetDirContentResp,
     proto::remote_sys::RunCmdReq,
-    proto::remote_sys::RunCmdResp
+    proto::remote_sys::RunCmdResp,
+    proto::kv::KvPositionReq,
+    proto::kv::KvPositionResp,
+    proto::kv::KvWatchReq,
+    proto::kv::KvWatchResp,
+    proto::sche::HttpInvokeReq,
+    proto::sche::HttpInvokeResp,
+    proto::sche::CancelInvocationReq,
+    proto::sche::CancelInvocationResp,
+    proto::sche::TriggerAckReq,
+    proto::sche::TriggerAckResp,
+    proto::remote_sys::DeployAppReq,
+    proto::remote_sys::DeployAppResp,
+    proto::remote_sys::ActivateAppReq,
+    proto::remote_sys::ActivateAppResp
 );
 
 pub trait RPCReq: MsgPack + Default {
@@ -79,10 +96,30 @@ impl RPCReq for proto::sche::DistributeTaskReq {
     type Resp = proto::sche::DistributeTaskResp;
 }
 
+impl RPCReq for proto::sche::HttpInvokeReq {
+    type Resp = proto::sche::HttpInvokeResp;
+}
+
+impl RPCReq for proto::sche::CancelInvocationReq {
+    type Resp = proto::sche::CancelInvocationResp;
+}
+
+impl RPCReq for proto::sche::TriggerAckReq {
+    type Resp = proto::sche::TriggerAckResp;
+}
+
 impl RPCReq for proto::kv::KvRequests {
     type Resp = proto::kv::KvResponses;
 }
 
+impl RPCReq for proto::kv::KvPositionReq {
+    type Resp = proto::kv::KvPositionResp;
+}
+
+impl RPCReq for proto::kv::KvWatchReq {
+    type Resp = proto::kv::KvWatchResp;
+}
+
 impl RPCReq for proto::remote_sys::GetDirContentReq {
     type Resp = proto::remote_sys::GetDirContentResp;
 }
@@ -91,11 +128,33 @@ impl RPCReq for proto::remote_sys::RunCmdReq {
     type Resp = proto::remote_sys::RunCmdResp;
 }
 
+impl RPCReq for proto::remote_sys::DeployAppReq {
+    type Resp = proto::remote_sys::DeployAppResp;
+}
+
+impl RPCReq for proto::remote_sys::ActivateAppReq {
+    type Resp = proto::remote_sys::ActivateAppResp;
+}
+
+pub trait KeyRangeExt {
+    fn is_single_key(&self) -> bool;
+}
+
+impl KeyRangeExt for proto::kv::KeyRange {
+    fn is_single_key(&self) -> bool {
+        !self.prefix && self.end.is_empty()
+    }
+}
+
 pub trait KvResponseExt {
     fn new_lock(lock_id: u32) -> KvResponse;
     fn new_common(kvs: Vec<proto::kv::KvPair>) -> KvResponse;
+    fn new_atomic(succ: bool, kvs: Vec<proto::kv::KvPair>) -> KvResponse;
     fn lock_id(&self) -> Option<u32>;
     fn common_kvs(&self) -> Option<&Vec<proto::kv::KvPair>>;
+    fn atomic_res(&self) -> Option<(bool, &Vec<proto::kv::KvPair>)>;
+    fn with_err(self, err: String) -> KvResponse;
+    fn err(&self) -> Option<&str>;
 }
 
 impl KvResponseExt for KvResponse {
@@ -104,25 +163,91 @@ impl KvResponseExt for KvResponse {
             resp: Some(proto::kv::kv_response::Resp::CommonResp(
                 proto::kv::kv_response::KvResponse { kvs },
             )),
+            error: String::new(),
+        }
+    }
+    fn new_atomic(succ: bool, kvs: Vec<proto::kv::KvPair>) -> KvResponse {
+        KvResponse {
+            resp: Some(proto::kv::kv_response::Resp::AtomicResp(
+                proto::kv::kv_response::KvAtomicResponse { succ, kvs },
+            )),
+            error: String::new(),
         }
     }
     fn new_lock(lock_id: u32) -> KvResponse {
         KvResponse {
             resp: Some(proto::kv::kv_response::Resp::LockId(lock_id)),
+            error: String::new(),
         }
     }
     fn lock_id(&self) -> Option<u32> {
         match self.resp.as_ref().unwrap() {
-            proto::kv::kv_response::Resp::CommonResp(_) => None,
             proto::kv::kv_response::Resp::LockId(id) => Some(*id),
+            _ => None,
         }
     }
     fn common_kvs(&self) -> Option<&Vec<proto::kv::KvPair>> {
         match self.resp.as_ref().unwrap() {
             proto::kv::kv_response::Resp::CommonResp(resp) => Some(&resp.kvs),
-            proto::kv::kv_response::Resp::LockId(_) => None,
+            _ => None,
+        }
+    }
+    fn atomic_res(&self) -> Option<(bool, &Vec<proto::kv::KvPair>)> {
+        match self.resp.as_ref().unwrap() {
+            proto::kv::kv_response::Resp::AtomicResp(resp) => Some((resp.succ, &resp.kvs)),
+            _ => None,
+        }
+    }
+    /// mark the operation failed, the empty result mustn't be taken as the value
+    fn with_err(mut self, err: String) -> KvResponse {
+        self.error = err;
+        self
+    }
+    fn err(&self) -> Option<&str> {
+        (!self.error.is_empty()).then_some(self.error.as_str())
+    }
+}
+
+pub trait KvResponsesExt {
+    fn new_aborted(reqs: &[proto::kv::KvRequest]) -> KvResponses;
+    fn new_err(reqs: &[proto::kv::KvRequest], err: String) -> KvResponses;
+    fn err(&self) -> Option<&str>;
+}
+
+impl KvResponsesExt for KvResponses {
+    /// guards fail and other operations get nothing
+    fn new_aborted(reqs: &[proto::kv::KvRequest]) -> KvResponses {
+        let responses = reqs
+            .iter()
+            .map(|req| match req.op.as_ref().unwrap() {
+                proto::kv::kv_request::Op::Guard(_) => KvResponse::new_atomic(false, vec![]),
+                _ => KvResponse::new_common(vec![]),
+            })
+            .collect();
+        KvResponses {
+            responses,
+            aborted: true,
+            error: String::new(),
         }
     }
+    /// nothing is written and every operation fails with the error
+    fn new_err(reqs: &[proto::kv::KvRequest], err: String) -> KvResponses {
+        let mut resps = Self::new_aborted(reqs);
+        resps.aborted = false;
+        resps.responses = resps
+            .responses
+            .into_iter()
+            .map(|resp| resp.with_err(err.clone()))
+            .collect();
+        resps.error = err;
+        resps
+    }
+    /// the batch failed as a whole or any operation of it failed
+    fn err(&self) -> Option<&str> {
+        (!self.error.is_empty())
+            .then_some(self.error.as_str())
+            .or_else(|| self.responses.iter().find_map(|resp| resp.err()))
+    }
 }
 
 // impl MsgId for raft::prelude::Message {
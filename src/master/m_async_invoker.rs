@@ -0,0 +1,352 @@
+use std::{future::Future, time::Duration};
+
+use async_trait::async_trait;
+use hyper::{header, Body, Client, Method, Request, Uri};
+use serde::{Deserialize, Serialize};
+use ws_derive::LogicalModule;
+
+use crate::{
+    apis::{CancelInvocationResp, GetInvocationResp},
+    general::{
+        m_kv_store_engine::{make_raw_key, KeyType, KvStoreEngine},
+        network::{
+            m_p2p::{P2PModule, RPCCaller},
+            proto,
+        },
+    },
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
+    util::{now_ms, JoinHandleWrapper, Retention},
+};
+
+logical_module_view_impl!(AsyncInvokerView);
+logical_module_view_impl!(AsyncInvokerView, p2p, P2PModule);
+logical_module_view_impl!(AsyncInvokerView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(AsyncInvokerView, async_invoker, Option<AsyncInvoker>);
+
+/// running invocations are never lost, they're failed when master restarts
+const INVOCATION_RETENTION: Retention = Retention {
+    finished_ms: 24 * 3600 * 1000,
+    unfinished_ms: u64::MAX,
+};
+
+/// time of connecting and posting to the webhook
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum InvocationOutput {
+    /// http response of fn, or output of service action with status 200
+    Succ {
+        status: u16,
+        body: Vec<u8>,
+    },
+    Fail(String),
+}
+
+/// target and status of an async invocation answered by get_invocation api,
+/// the output is filled in when the task returns
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvocationRecord {
+    /// fn route or service action
+    pub target: String,
+    /// worker running the fn, none for service actions which can't be cancelled
+    pub node: Option<NodeID>,
+    /// unix time in ms
+    pub start_time: u64,
+    /// 0 while running
+    pub end_time: u64,
+    /// none while running
+    pub output: Option<InvocationOutput>,
+}
+
+/// async invocation by id
+pub struct KeyTypeInvocation<'a>(pub &'a str);
+
+impl KeyType for KeyTypeInvocation<'_> {
+    type Value = InvocationRecord;
+    fn id(&self) -> u8 {
+        9
+    }
+    fn make_key(&self) -> Vec<u8> {
+        make_raw_key(self.id(), self.0.as_bytes())
+    }
+}
+
+impl Serialize for KeyTypeInvocation<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl InvocationRecord {
+    fn into_resp(self, invocation_id: String) -> GetInvocationResp {
+        let Self {
+            target,
+            node: _,
+            start_time,
+            end_time,
+            output,
+        } = self;
+        match output {
+            None => GetInvocationResp::Running {
+                invocation_id,
+                target,
+                start_time,
+            },
+            Some(InvocationOutput::Succ { status, body }) => GetInvocationResp::Succ {
+                invocation_id,
+                target,
+                start_time,
+                end_time,
+                status: status as i32,
+                output: String::from_utf8_lossy(&body).into_owned(),
+            },
+            Some(InvocationOutput::Fail(msg)) => GetInvocationResp::Fail {
+                invocation_id,
+                target,
+                start_time,
+                end_time,
+                msg,
+            },
+        }
+    }
+}
+
+/// runs invocations in background and keeps their output for polling,
+/// shared by the async fn calls and service actions
+#[derive(LogicalModule)]
+pub struct AsyncInvoker {
+    rpc_caller_cancel_invocation: RPCCaller<proto::sche::CancelInvocationReq>,
+    view: AsyncInvokerView,
+}
+
+#[async_trait]
+impl LogicalModule for AsyncInvoker {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            rpc_caller_cancel_invocation: RPCCaller::default(),
+            view: AsyncInvokerView::new(args.logical_modules_ref.clone()),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        self.rpc_caller_cancel_invocation.regist(self.view.p2p());
+        self.fail_interrupted(now_ms());
+        let view = self.view.clone();
+        Ok(vec![Retention::spawn_gc(move |now| {
+            view.async_invoker().evict_finished(now)
+        })])
+    }
+}
+
+impl AsyncInvoker {
+    /// run the task built with the invocation id in background, returns the id to poll the output,
+    /// the output is posted to `webhook` checked by `check_webhook` in the body of get_invocation
+    /// when finished
+    pub fn invoke<F>(
+        &self,
+        target: String,
+        node: Option<NodeID>,
+        webhook: Option<Uri>,
+        task: impl FnOnce(String) -> F,
+    ) -> String
+    where
+        F: Future<Output = InvocationOutput> + Send + 'static,
+    {
+        let invocation_id = uuid::Uuid::new_v4().to_string();
+        let record = InvocationRecord {
+            target,
+            node,
+            start_time: now_ms(),
+            end_time: 0,
+            output: None,
+        };
+        let kv_store_engine = self.view.kv_store_engine();
+        kv_store_engine.set(KeyTypeInvocation(&invocation_id), &record);
+        kv_store_engine.flush();
+
+        let view = self.view.clone();
+        let id = invocation_id.clone();
+        let task = task(invocation_id.clone());
+        let _ = tokio::spawn(async move {
+            let output = task.await;
+            let record = InvocationRecord {
+                end_time: now_ms(),
+                output: Some(output),
+                ..record
+            };
+            let kv_store_engine = view.kv_store_engine();
+            kv_store_engine.set(KeyTypeInvocation(&id), &record);
+            kv_store_engine.flush();
+
+            if let Some(webhook) = webhook {
+                let body = record.into_resp(id.clone()).serialize().to_string();
+                match post_webhook(webhook.clone(), body.into_bytes()).await {
+                    Ok(status) if (200..300).contains(&status) => {}
+                    Ok(status) => {
+                        tracing::warn!(
+                            "webhook {} of invocation {} answers {}",
+                            webhook,
+                            id,
+                            status
+                        )
+                    }
+                    Err(err) => {
+                        tracing::warn!("post invocation {} to {} failed: {}", id, webhook, err)
+                    }
+                }
+            }
+        });
+        invocation_id
+    }
+    pub fn get(&self, invocation_id: &str) -> GetInvocationResp {
+        match self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeInvocation(invocation_id))
+        {
+            Some(record) => record.into_resp(invocation_id.to_owned()),
+            None => GetInvocationResp::NotFound {},
+        }
+    }
+    /// interrupt the fn of running invocation, its output is recorded as failed
+    pub async fn cancel(&self, invocation_id: &str) -> CancelInvocationResp {
+        let fail = |msg: &str| CancelInvocationResp::Fail {
+            msg: format!("invocation {} {}", invocation_id, msg),
+        };
+        let Some(record) = self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeInvocation(invocation_id))
+        else {
+            return fail("not found");
+        };
+        if record.output.is_some() {
+            return fail("already finished");
+        }
+        let Some(node) = record.node else {
+            return fail("can't be cancelled");
+        };
+        match self
+            .rpc_caller_cancel_invocation
+            .call(
+                self.view.p2p(),
+                node,
+                proto::sche::CancelInvocationReq {
+                    invocation_id: invocation_id.to_owned(),
+                },
+                Some(Duration::from_secs(10)),
+            )
+            .await
+        {
+            Ok(resp) if resp.found => CancelInvocationResp::Succ {},
+            // not started or just finished
+            Ok(_) => fail("not running on the worker"),
+            Err(err) => fail(&format!("cancel on node {} failed: {:?}", node, err)),
+        }
+    }
+    fn all_records(&self) -> Vec<(String, InvocationRecord)> {
+        self.view
+            .kv_store_engine()
+            .scan_type(KeyTypeInvocation(""))
+            .into_iter()
+            .map(|(id, record)| (String::from_utf8_lossy(&id).into_owned(), record))
+            .collect()
+    }
+    /// invocations running when master went down will never finish
+    fn fail_interrupted(&self, started_before: u64) {
+        let kv_store_engine = self.view.kv_store_engine();
+        for (id, record) in self.all_records() {
+            if record.output.is_some() || record.start_time >= started_before {
+                continue;
+            }
+            tracing::warn!("invocation {} interrupted by master restart", id);
+            kv_store_engine.set(
+                KeyTypeInvocation(&id),
+                &InvocationRecord {
+                    end_time: started_before,
+                    output: Some(InvocationOutput::Fail(
+                        "interrupted by master restart".to_owned(),
+                    )),
+                    ..record
+                },
+            );
+        }
+        kv_store_engine.flush();
+    }
+    fn evict_finished(&self, now: u64) {
+        let _ = self
+            .view
+            .kv_store_engine()
+            .evict(KeyTypeInvocation(""), |record| {
+                INVOCATION_RETENTION.expired(record.start_time, record.end_time, now)
+            });
+    }
+}
+
+/// the webhook url if it's a plain http url to one of the `allowed` hosts,
+/// an allowed host matches the url on any port, an allowed host:port only on the port
+pub fn check_webhook(url: &str, allowed: &[String]) -> Result<Uri, String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|err| format!("webhook {} is invalid: {}", url, err))?;
+    if uri.scheme_str() != Some("http") {
+        return Err(format!("webhook {} should start with http://", url));
+    }
+    let Some(authority) = uri.authority() else {
+        return Err(format!("webhook {} has no host", url));
+    };
+    let (host, addr) = (authority.host(), authority.as_str());
+    if !allowed
+        .iter()
+        .any(|allowed| allowed == host || allowed == addr)
+    {
+        return Err(format!("webhook host {} is not allowed", addr));
+    }
+    Ok(uri)
+}
+
+/// post json body to the webhook, returns the response status
+async fn post_webhook(uri: Uri, body: Vec<u8>) -> Result<u16, String> {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .map_err(|err| err.to_string())?;
+    let resp = tokio::time::timeout(WEBHOOK_TIMEOUT, Client::new().request(req))
+        .await
+        .map_err(|_| "timeout".to_owned())?
+        .map_err(|err| err.to_string())?;
+    Ok(resp.status().as_u16())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_webhook() {
+        let allowed = vec!["example.com".to_owned(), "[::1]:8080".to_owned()];
+        let host =
+            |url: &str| check_webhook(url, &allowed).map(|uri| uri.host().unwrap().to_owned());
+        assert_eq!(
+            host("http://example.com/hooks/done?a=1"),
+            Ok("example.com".to_owned())
+        );
+        assert_eq!(
+            host("http://example.com:8080"),
+            Ok("example.com".to_owned())
+        );
+        assert_eq!(host("http://[::1]:8080/hooks"), Ok("[::1]".to_owned()));
+        assert!(check_webhook("http://[::1]:9090/hooks", &allowed).is_err());
+        assert!(check_webhook("http://127.0.0.1/", &allowed).is_err());
+        assert!(check_webhook("http://example.com@127.0.0.1/", &allowed).is_err());
+        assert!(check_webhook("https://example.com/", &allowed).is_err());
+        assert!(check_webhook("http:///path", &allowed).is_err());
+        assert!(check_webhook("http://example.com/", &[]).is_err());
+    }
+}
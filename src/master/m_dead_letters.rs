@@ -0,0 +1,256 @@
+use async_trait::async_trait;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use ws_derive::LogicalModule;
+
+use crate::{
+    apis::{self, GetDeadLetterResp, ListDeadLettersResp, ReplayDeadLetterResp},
+    general::{
+        m_kv_store_engine::{make_raw_key, KeyType, KvStoreEngine},
+        network::proto::{
+            kv::KeyRange,
+            sche::{distribute_task_req::Trigger, DistributeTaskReq},
+        },
+    },
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
+    util::{now_ms, JoinHandleWrapper},
+};
+
+use super::{
+    m_master::Master,
+    m_trigger_queue::TriggerQueue,
+    m_workflow::{WorkflowCtx, Workflows},
+};
+
+logical_module_view_impl!(DeadLettersView);
+logical_module_view_impl!(DeadLettersView, master, Option<Master>);
+logical_module_view_impl!(DeadLettersView, trigger_queue, Option<TriggerQueue>);
+logical_module_view_impl!(DeadLettersView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(DeadLettersView, workflows, Option<Workflows>);
+
+/// fn event no worker accepted after all attempts, kept until it's replayed successfully
+/// so that the event is not lost
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// DistributeTaskReq encoded by prost
+    pub task: Vec<u8>,
+    /// workers tried in order
+    pub nodes: Vec<NodeID>,
+    /// error of the last attempt
+    pub err: String,
+    /// unix time in ms of the last attempt
+    pub fail_time: u64,
+}
+
+/// fn event failed to be scheduled, by letter id
+pub struct KeyTypeDeadLetter<'a>(pub &'a str);
+
+impl KeyType for KeyTypeDeadLetter<'_> {
+    type Value = DeadLetter;
+    fn id(&self) -> u8 {
+        10
+    }
+    fn make_key(&self) -> Vec<u8> {
+        make_raw_key(self.id(), self.0.as_bytes())
+    }
+}
+
+impl Serialize for KeyTypeDeadLetter<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl DeadLetter {
+    fn decode_task(&self, id: &str) -> Option<DistributeTaskReq> {
+        match DistributeTaskReq::decode(self.task.as_slice()) {
+            Ok(task) => Some(task),
+            Err(err) => {
+                tracing::warn!("dead letter {} is broken: {}", id, err);
+                None
+            }
+        }
+    }
+    fn to_api(&self, id: String, task: &DistributeTaskReq) -> apis::DeadLetter {
+        let (trigger, key) = match task.trigger.as_ref() {
+            Some(Trigger::KvSet(set)) => ("kv_set", set.key.as_slice()),
+            Some(Trigger::KvNew(set)) => ("kv_new", set.key.as_slice()),
+            Some(Trigger::KvChange(set)) => ("kv_change", set.key.as_slice()),
+            Some(Trigger::KvDelete(delete)) => ("kv_delete", delete.key.as_slice()),
+            Some(Trigger::Timer(_)) => ("timer", [].as_slice()),
+            None => ("", [].as_slice()),
+        };
+        apis::DeadLetter {
+            id,
+            app: task.app.clone(),
+            func: task.func.clone(),
+            trigger: trigger.to_owned(),
+            key: String::from_utf8_lossy(key).into_owned(),
+            attempts: self.nodes.len() as i32,
+            nodes: self.nodes.iter().map(|&node| node as u64).collect(),
+            err: self.err.clone(),
+            fail_time: self.fail_time,
+        }
+    }
+}
+
+/// dead letters of the fn events whose schedule failed, can be listed and replayed by api
+#[derive(LogicalModule)]
+pub struct DeadLetters {
+    view: DeadLettersView,
+}
+
+#[async_trait]
+impl LogicalModule for DeadLetters {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: DeadLettersView::new(args.logical_modules_ref.clone()),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        Ok(vec![])
+    }
+}
+
+impl DeadLetters {
+    /// keep the task tried on `nodes` and failed with `err` lastly, returns the letter id,
+    /// the task is failed in its workflow
+    pub fn put(&self, task: &DistributeTaskReq, nodes: Vec<NodeID>, err: String) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        tracing::error!(
+            "event of {}/{} failed after {} attempts, kept as dead letter {}: {}",
+            task.app,
+            task.func,
+            nodes.len(),
+            id,
+            err
+        );
+        let error = format!("kept as dead letter {}: {}", id, err);
+        let kv_store_engine = self.view.kv_store_engine();
+        kv_store_engine.set(
+            KeyTypeDeadLetter(&id),
+            &DeadLetter {
+                task: task.encode_to_vec(),
+                nodes,
+                err,
+                fail_time: now_ms(),
+            },
+        );
+        kv_store_engine.flush();
+        self.view.workflows().finish_task(
+            WorkflowCtx {
+                workflow_id: task.task_id,
+                task: task.queue_id,
+            },
+            vec![],
+            error,
+        );
+        id
+    }
+    /// letters of `app`, or all apps if it's empty
+    pub fn list(&self, app: &str) -> ListDeadLettersResp {
+        let all = KeyRange {
+            start: vec![],
+            end: vec![],
+            prefix: true,
+        };
+        let mut letters: Vec<_> = self
+            .view
+            .kv_store_engine()
+            .scan(&all, |_| KeyTypeDeadLetter(""))
+            .into_iter()
+            .filter_map(|(id, letter)| {
+                let id = String::from_utf8_lossy(&id).into_owned();
+                let task = letter.decode_task(&id)?;
+                (app.is_empty() || task.app == app).then(|| letter.to_api(id, &task))
+            })
+            .collect();
+        letters.sort_by_key(|letter| letter.fail_time);
+        ListDeadLettersResp::Letters { letters }
+    }
+    pub fn get(&self, id: &str) -> GetDeadLetterResp {
+        let Some((letter, task)) = self.get_with_task(id) else {
+            return GetDeadLetterResp::NotFound {};
+        };
+        GetDeadLetterResp::Found {
+            letter: letter.to_api(id.to_owned(), &task),
+            detail: format!("{:?}", task.trigger),
+        }
+    }
+    /// try the task once more on a worker not tried yet if any,
+    /// the letter is removed if accepted, otherwise the attempt is recorded in it
+    pub async fn replay(&self, id: &str) -> ReplayDeadLetterResp {
+        let Some((mut letter, mut task)) = self.get_with_task(id) else {
+            return ReplayDeadLetterResp::Fail {
+                msg: format!("dead letter {} not found", id),
+            };
+        };
+        let master = self.view.master();
+        let node =
+            match master.select_retry_node(&format!("{}/{}", task.app, task.func), &letter.nodes) {
+                Ok(node) => node,
+                Err(err) => {
+                    return ReplayDeadLetterResp::Fail {
+                        msg: format!("replay dead letter {} failed: {}", id, err),
+                    }
+                }
+            };
+        // accepted one is tracked by the trigger queue until its fn finished
+        let trigger_queue = self.view.trigger_queue();
+        let failed = task.queue_id;
+        trigger_queue.enqueue_now(&mut task, node);
+        let queue_id = task.queue_id;
+        // and reopens its workflow as a task fired by the failed one
+        let workflow = WorkflowCtx {
+            workflow_id: task.task_id,
+            task: failed,
+        };
+        let workflows = self.view.workflows();
+        if workflow.workflow_id != 0 {
+            workflows.add_task(
+                workflow,
+                queue_id,
+                format!("{}/{}", task.app, task.func),
+                node,
+            );
+        }
+        let res = master.distribute_task(node, task).await;
+        let kv_store_engine = self.view.kv_store_engine();
+        let resp = match res {
+            Ok(()) => {
+                kv_store_engine.del(KeyTypeDeadLetter(id));
+                ReplayDeadLetterResp::Succ { node: node as u64 }
+            }
+            Err(err) => {
+                trigger_queue.remove(queue_id);
+                workflows.finish_task(
+                    WorkflowCtx {
+                        task: queue_id,
+                        ..workflow
+                    },
+                    vec![],
+                    format!("replay on node {} failed: {}", node, err),
+                );
+                letter.nodes.push(node);
+                letter.err = err.to_string();
+                letter.fail_time = now_ms();
+                kv_store_engine.set(KeyTypeDeadLetter(id), &letter);
+                ReplayDeadLetterResp::Fail {
+                    msg: format!("replay on node {} failed: {}", node, letter.err),
+                }
+            }
+        };
+        kv_store_engine.flush();
+        resp
+    }
+    fn get_with_task(&self, id: &str) -> Option<(DeadLetter, DistributeTaskReq)> {
+        let letter = self.view.kv_store_engine().get(KeyTypeDeadLetter(id))?;
+        let task = letter.decode_task(id)?;
+        Some((letter, task))
+    }
+}
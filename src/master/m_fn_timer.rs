@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ws_derive::LogicalModule;
+
+use crate::{
+    general::{
+        m_appmeta_manager::{
+            timer::{MissedFirePolicy, TimerMeta},
+            AppMetaManager,
+        },
+        m_kv_store_engine::{KeyTypeTimerNextFire, KvBatch, KvStoreEngine},
+        network::proto::sche::{
+            distribute_task_req::{Trigger, TriggerTimer},
+            DistributeTaskReq,
+        },
+    },
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
+    util::{now_ms, JoinHandleWrapper},
+};
+
+use super::{m_master::Master, m_trigger_queue::TriggerQueue};
+
+logical_module_view_impl!(FnTimerView);
+logical_module_view_impl!(FnTimerView, master, Option<Master>);
+logical_module_view_impl!(FnTimerView, appmeta_manager, AppMetaManager);
+logical_module_view_impl!(FnTimerView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(FnTimerView, fn_timer, Option<FnTimer>);
+logical_module_view_impl!(FnTimerView, trigger_queue, Option<TriggerQueue>);
+
+/// interval of checking the due timers
+const TIMER_TICK: Duration = Duration::from_millis(500);
+
+/// a fire later than this is missed
+const MISFIRE_THRESHOLD_MS: u64 = 5000;
+
+/// max fires of one timer in a tick, the rest missed fires are caught up in later ticks
+const MAX_FIRES_PER_TICK: usize = 100;
+
+/// fires the timer events of fns declared in app.yaml,
+/// next fire times are kept in kv store engine so that the schedule survives master restart
+#[derive(LogicalModule)]
+pub struct FnTimer {
+    view: FnTimerView,
+}
+
+#[async_trait]
+impl LogicalModule for FnTimer {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: FnTimerView::new(args.logical_modules_ref.clone()),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let view = self.view.clone();
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TIMER_TICK);
+            loop {
+                let _ = interval.tick().await;
+                view.fn_timer().tick().await;
+            }
+        }))])
+    }
+}
+
+impl FnTimer {
+    async fn tick(&self) {
+        let timers = self.view.appmeta_manager().meta.read().await.timers();
+        let kv_store_engine = self.view.kv_store_engine();
+        let now = now_ms();
+        let mut changed = false;
+        let mut tasks = vec![];
+        for (app, func, timer) in timers {
+            let key = || KeyTypeTimerNextFire(&app, &func, &timer.spec);
+            let Some(next) = kv_store_engine.get(key()) else {
+                // newly deployed timer starts from now
+                if let Some(next) = timer.next_fire(now) {
+                    kv_store_engine.set(key(), &next);
+                    changed = true;
+                }
+                continue;
+            };
+            if next > now {
+                continue;
+            }
+            let (fires, next) = due_fires(&timer, next, now);
+            // fires are queued in the same write as the next fire time,
+            // so they're replayed rather than lost or repeated if master crashes
+            let mut batch = KvBatch::default();
+            match next {
+                Some(next) => batch.set(key(), &next),
+                None => batch.del(key()),
+            }
+            if fires.is_empty() {
+                tracing::debug!("timer of {}/{} skipped missed fires", app, func);
+            }
+            tasks.extend(self.queue_fires(&mut batch, &app, &func, fires));
+            kv_store_engine.apply_batch(batch);
+            changed = true;
+        }
+        if changed {
+            kv_store_engine.flush();
+        }
+        for (task, node) in tasks {
+            let view = self.view.clone();
+            let _ = tokio::spawn(async move {
+                view.master().schedule_one_trigger(task, node).await;
+            });
+        }
+    }
+    /// queue the fires in `batch`, they're dropped if there's no worker
+    fn queue_fires(
+        &self,
+        batch: &mut KvBatch,
+        app: &str,
+        func: &str,
+        fires: Vec<u64>,
+    ) -> Vec<(DistributeTaskReq, NodeID)> {
+        if fires.is_empty() {
+            return vec![];
+        }
+        let master = self.view.master();
+        let trigger_queue = self.view.trigger_queue();
+        fires
+            .into_iter()
+            .map_while(|scheduled_time| {
+                let node = match master.select_node(&format!("{app}/{func}")) {
+                    Ok(node) => node,
+                    Err(err) => {
+                        tracing::warn!("timer of {}/{} dropped: {}", app, func, err);
+                        return None;
+                    }
+                };
+                let mut task = DistributeTaskReq {
+                    app: app.to_owned(),
+                    func: func.to_owned(),
+                    task_id: 0,
+                    trigger: Some(Trigger::Timer(TriggerTimer { scheduled_time })),
+                    queue_id: 0,
+                };
+                trigger_queue.enqueue(batch, &mut task, node);
+                Some((task, node))
+            })
+            .collect()
+    }
+}
+
+/// scheduled times to fire at `now` since the stored `next`, and the next fire after them
+fn due_fires(timer: &TimerMeta, next: u64, now: u64) -> (Vec<u64>, Option<u64>) {
+    let mut next = Some(next);
+    if timer.missed == MissedFirePolicy::Skip && now - next.unwrap() > MISFIRE_THRESHOLD_MS {
+        // jump over the missed ones
+        next = timer.next_fire(now - MISFIRE_THRESHOLD_MS - 1);
+    }
+    let mut fires = vec![];
+    while let Some(scheduled) = next.filter(|n| *n <= now && fires.len() < MAX_FIRES_PER_TICK) {
+        fires.push(scheduled);
+        next = timer.next_fire(scheduled);
+    }
+    (fires, next)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::general::m_appmeta_manager::timer::TimerYaml;
+
+    use super::*;
+
+    #[test]
+    fn test_due_fires() {
+        let timer = |missed| {
+            TimerMeta::new(TimerYaml {
+                cron: None,
+                interval: Some(10),
+                missed,
+            })
+            .unwrap()
+        };
+        let skip = timer(MissedFirePolicy::Skip);
+        let catch_up = timer(MissedFirePolicy::CatchUp);
+
+        // on time
+        assert_eq!(due_fires(&skip, 10000, 10100), (vec![10000], Some(20000)));
+        assert_eq!(
+            due_fires(&catch_up, 10000, 10100),
+            (vec![10000], Some(20000))
+        );
+
+        // master was down from 10s to 45s
+        assert_eq!(due_fires(&skip, 10000, 45000), (vec![40000], Some(50000)));
+        assert_eq!(
+            due_fires(&catch_up, 10000, 45000),
+            (vec![10000, 20000, 30000, 40000], Some(50000))
+        );
+        assert_eq!(due_fires(&skip, 10000, 48000), (vec![], Some(50000)));
+
+        // catching up is bounded in one tick
+        let (fires, next) = due_fires(&catch_up, 10000, 10000 * 1000);
+        assert_eq!(fires.len(), MAX_FIRES_PER_TICK);
+        assert_eq!(next, Some(10000 * (MAX_FIRES_PER_TICK as u64 + 1)));
+    }
+}
@@ -1,25 +1,37 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
 use axum::{
     http::{HeaderValue, StatusCode},
     response::{IntoResponse, Redirect, Response},
+    Json,
 };
 use prometheus_client::encoding::text::encode;
 use ws_derive::LogicalModule;
 // use
 
 use crate::{
-    config::NodeConfig,
-    general::network::{
-        http_handler::{self, HttpHandler},
-        m_p2p::P2PModule,
+    config::{GatewayMode, NodeConfig},
+    general::{
+        m_appmeta_manager::{AppMetaManager, DEFAULT_FN_TIMEOUT, FN_CALL_TIMEOUT_MARGIN},
+        network::{
+            http_handler::{self, invoke_resp_response, HttpHandler, HttpReq},
+            m_p2p::P2PModule,
+            proto::sche::HttpInvokeResp,
+        },
     },
     logical_module_view_impl,
-    result::WSResult,
-    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
+    result::{WSError, WSResult, WsNetworkConnErr},
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
 };
 
-use super::{m_master::Master, m_metric_observor::MetricObservor};
+use super::{
+    m_async_invoker::{check_webhook, AsyncInvoker, InvocationOutput},
+    m_master::Master,
+    m_metric_observor::MetricObservor,
+    m_workflow::{WorkflowCtx, Workflows},
+};
 
 logical_module_view_impl!(MasterHttpHandlerView);
 logical_module_view_impl!(MasterHttpHandlerView, p2p, P2PModule);
@@ -29,6 +41,18 @@ logical_module_view_impl!(
     metric_observor,
     Option<MetricObservor>
 );
+logical_module_view_impl!(MasterHttpHandlerView, async_invoker, Option<AsyncInvoker>);
+logical_module_view_impl!(MasterHttpHandlerView, workflows, Option<Workflows>);
+logical_module_view_impl!(MasterHttpHandlerView, appmeta_manager, AppMetaManager);
+
+/// the output of async fn call is posted to the url in this header when finished
+const WEBHOOK_HEADER: &str = "x-webhook-url";
+
+/// the fns triggered by the call are tracked as a workflow if the call has this header
+const TRACK_WORKFLOW_HEADER: &str = "x-track-workflow";
+
+/// id of the workflow tracking the fns triggered by the call, answered by proxied calls
+const WORKFLOW_HEADER: &str = "x-workflow-id";
 
 #[derive(LogicalModule)]
 pub struct MasterHttpHandler {
@@ -71,6 +95,161 @@ impl MasterHttpHandler {
         );
         resp
     }
+    /// forward the fn call to `node` over p2p and answer with its response,
+    /// the call is tracked as the root of a workflow if asked
+    async fn proxy(&self, node: NodeID, route: &str, req: HttpReq) -> Response {
+        tracing::debug!("proxy {} to node {}", route, node);
+        let workflows = self.view.workflows();
+        let workflow_id = if track_workflow(&req) {
+            workflows.begin(route.to_owned(), node, String::new())
+        } else {
+            0
+        };
+        let timeout = fn_call_timeout(&self.view, route, &req.method).await;
+        let mut req = req.into_invoke_req(route);
+        req.workflow_id = workflow_id;
+        let res = self.view.master().invoke_http(node, req, timeout).await;
+        if workflow_id != 0 {
+            let (output, error) = root_call_report(&res);
+            workflows.finish_task(
+                WorkflowCtx {
+                    workflow_id,
+                    task: 0,
+                },
+                output,
+                error,
+            );
+        }
+        match res {
+            Ok(resp) => {
+                let mut resp = invoke_resp_response(resp);
+                if workflow_id != 0 {
+                    let _ = resp
+                        .headers_mut()
+                        .insert(WORKFLOW_HEADER, HeaderValue::from(workflow_id));
+                }
+                resp
+            }
+            Err(WSError::WsNetworkConnErr(WsNetworkConnErr::RPCTimout(_))) => {
+                StatusCode::GATEWAY_TIMEOUT.into_response()
+            }
+            Err(err) => {
+                tracing::warn!("proxy {} to node {} failed: {:?}", route, node, err);
+                StatusCode::BAD_GATEWAY.into_response()
+            }
+        }
+    }
+    /// run the fn call in background and answer with the invocation id at once,
+    /// the output is polled by get_invocation api, and kept only by the invocation
+    /// if the call is tracked as a workflow
+    fn invoke_async(&self, node: NodeID, route: &str, req: HttpReq) -> Response {
+        let allowed = &self.view.p2p().nodes_config.gateway.webhook_hosts;
+        let webhook = match req
+            .headers
+            .get(WEBHOOK_HEADER)
+            .map(|url| check_webhook(url, allowed))
+        {
+            Some(Ok(webhook)) => Some(webhook),
+            Some(Err(err)) => return (StatusCode::BAD_REQUEST, err).into_response(),
+            None => None,
+        };
+        let view = self.view.clone();
+        let target = route.to_owned();
+        let track = track_workflow(&req);
+        let mut workflow_id = 0;
+        let task = |invocation_id: String| {
+            if track {
+                workflow_id = view
+                    .workflows()
+                    .begin(target.clone(), node, invocation_id.clone());
+            }
+            let workflow_id = workflow_id;
+            async move {
+                let timeout = fn_call_timeout(&view, &target, &req.method).await;
+                let mut req = req.into_invoke_req(&target);
+                req.invocation_id = invocation_id;
+                req.workflow_id = workflow_id;
+                let res = view.master().invoke_http(node, req, timeout).await;
+                if workflow_id != 0 {
+                    view.workflows().finish_task(
+                        WorkflowCtx {
+                            workflow_id,
+                            task: 0,
+                        },
+                        vec![],
+                        root_call_error(&res),
+                    );
+                }
+                match res {
+                    // interrupted before the fn returns
+                    Ok(resp) if !resp.error.is_empty() => InvocationOutput::Fail(resp.error),
+                    Ok(resp) => InvocationOutput::Succ {
+                        status: resp.status as u16,
+                        body: resp.body,
+                    },
+                    Err(err) => InvocationOutput::Fail(format!(
+                        "invoke {} on node {} failed: {:?}",
+                        target, node, err
+                    )),
+                }
+            }
+        };
+        let invocation_id =
+            self.view
+                .async_invoker()
+                .invoke(route.to_owned(), Some(node), webhook, task);
+        tracing::debug!("invoke {} async as {}", route, invocation_id);
+        let mut body = serde_json::json!({ "invocation_id": invocation_id });
+        if workflow_id != 0 {
+            body["workflow_id"] = workflow_id.into();
+        }
+        (StatusCode::ACCEPTED, Json(body)).into_response()
+    }
+    /// let the client call the http port of `node` itself
+    fn redirect(&self, node: NodeID, route: &str, req: HttpReq) -> Response {
+        let target_node = self.view.p2p().nodes_config.peers.get(&node).unwrap();
+
+        let target_path = match &req.raw_query {
+            Some(query) => construct_target_path(target_node, &format!("{route}?{query}")),
+            None => construct_target_path(target_node, route),
+        };
+
+        // target_node.set_port(target_node.port() + 1);
+        tracing::debug!("redirect to {}", target_path);
+        Redirect::temporary(&target_path).into_response()
+    }
+}
+
+/// (output, error) of the root call of workflow
+fn root_call_report(res: &WSResult<HttpInvokeResp>) -> (Vec<u8>, String) {
+    let output = match res {
+        Ok(resp) if resp.error.is_empty() => resp.body.clone(),
+        _ => vec![],
+    };
+    (output, root_call_error(res))
+}
+
+/// error of the root call of workflow, answering 4xx or 5xx is failed
+fn root_call_error(res: &WSResult<HttpInvokeResp>) -> String {
+    match res {
+        Ok(resp) if !resp.error.is_empty() => resp.error.clone(),
+        Ok(resp) if resp.status >= 400 => format!("fn answers {}", resp.status),
+        Ok(_) => String::new(),
+        Err(err) => format!("{:?}", err),
+    }
+}
+
+fn track_workflow(req: &HttpReq) -> bool {
+    req.headers.contains_key(TRACK_WORKFLOW_HEADER)
+}
+
+/// `prefer: respond-async` of rfc 7240
+fn prefer_async(req: &HttpReq) -> bool {
+    req.headers.get("prefer").map_or(false, |prefer| {
+        prefer
+            .split(',')
+            .any(|v| v.trim().eq_ignore_ascii_case("respond-async"))
+    })
 }
 
 fn construct_target_path(node_config: &NodeConfig, sub_api: &str) -> String {
@@ -83,51 +262,45 @@ fn construct_target_path(node_config: &NodeConfig, sub_api: &str) -> String {
     }
 }
 
+/// time waiting for the worker to answer the call of the fn on `route`, the worker interrupts
+/// the fn at its timeout, `DEFAULT_FN_TIMEOUT` if the route doesn't match any fn here
+async fn fn_call_timeout(view: &MasterHttpHandlerView, route: &str, method: &str) -> Duration {
+    let segs: Vec<&str> = route.split('/').filter(|seg| !seg.is_empty()).collect();
+    let metas = view.appmeta_manager().meta.read().await;
+    let timeout = segs.split_first().and_then(|(&app, sub)| {
+        let app = metas.get_app_meta(app)?;
+        let (func, _) = app.route_http(sub, method).ok()?;
+        Some(app.get_fn_meta(func)?.timeout)
+    });
+    timeout.unwrap_or(DEFAULT_FN_TIMEOUT) + FN_CALL_TIMEOUT_MARGIN
+}
+
 #[async_trait]
 impl HttpHandler for MasterHttpHandler {
     // fn alloc_local_req_id(&self) -> ReqId {
     //     self.local_req_id_allocator.alloc()
     // }
-    async fn handle_request(&self, app: &str, _http_text: String) -> Response {
+    async fn handle_request(&self, app: &str, req: HttpReq) -> Response {
         tracing::debug!("handle_request {}", app);
         if app == "metrics" {
             return self.handle_prometheus();
         }
         // 选择节点
-        let node = self.view.master().handle_http_schedule(app).await;
-
-        // if self.view.p2p().nodes_config.this.0 == node {
-        //     // println!("run");
-        //     // 本节点执行
-        //     self.view
-        //         .executor()
-        //         .handle_http_task(
-        //             app.to_owned(), //     FunctionCtxBuilder::new(
-        //                             //     app.to_owned(),
-        //                             //     self.local_req_id_allocator.alloc(),
-        //                             //     node,
-        //                             // )
-        //         )
-        //         .await;
-        //     // println!("end run");
-
-        //     StatusCode::OK.into_response()
-        // } else {
-        // 转发
-        let target_node = self
-            .view
-            .p2p()
-            .nodes_config
-            .peers
-            .get(&(node as u32))
-            .unwrap();
-
-        let target_path = construct_target_path(target_node, app);
+        let node = match self.view.master().handle_http_schedule(app).await {
+            Ok(node) => node,
+            Err(err) => {
+                tracing::warn!("schedule {} failed: {}", app, err);
+                return (StatusCode::SERVICE_UNAVAILABLE, err.to_string()).into_response();
+            }
+        };
 
-        // target_node.set_port(target_node.port() + 1);
-        tracing::debug!("redirect to {}", target_path);
-        Redirect::temporary(&target_path).into_response()
-        // }
+        if prefer_async(&req) {
+            return self.invoke_async(node, app, req);
+        }
+        match self.view.p2p().nodes_config.gateway.mode {
+            GatewayMode::Redirect => self.redirect(node, app, req),
+            GatewayMode::Proxy => self.proxy(node, app, req).await,
+        }
     }
     // async fn select_node(
     //     &self,
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tokio::{sync::watch, time::Instant};
+use ws_derive::LogicalModule;
+
+use crate::{
+    general::{
+        m_appmeta_manager::{fn_event, KeyPattern},
+        m_kv_store_engine::{KvChange, KvChangeKind, KvStoreEngine},
+        network::{
+            m_p2p::{P2PModule, RPCHandler, RPCResponsor},
+            proto::{
+                self,
+                kv::{KvWatchEvent, KvWatchReq, KvWatchResp},
+            },
+        },
+    },
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
+    util::JoinHandleWrapper,
+};
+
+logical_module_view_impl!(KvWatchView);
+logical_module_view_impl!(KvWatchView, p2p, P2PModule);
+logical_module_view_impl!(KvWatchView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(KvWatchView, kv_watch, Option<KvWatch>);
+
+/// max waiting time of one watch
+const MAX_WATCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// change feed of kv on master, each set or delete gets an increasing revision,
+/// watchers long-poll the events after a revision
+#[derive(LogicalModule)]
+pub struct KvWatch {
+    rpc_handler: RPCHandler<proto::kv::KvWatchReq>,
+    // latest revision of the feed
+    revision: watch::Sender<u64>,
+    // keep the feed in revision order
+    append_lock: Mutex<()>,
+    view: KvWatchView,
+}
+
+#[async_trait]
+impl LogicalModule for KvWatch {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            rpc_handler: RPCHandler::default(),
+            revision: watch::channel(0).0,
+            append_lock: Mutex::new(()),
+            view: KvWatchView::new(args.logical_modules_ref.clone()),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let last = self
+            .view
+            .kv_store_engine()
+            .kv_change_revisions()
+            .map_or(0, |(_, last)| last);
+        let _ = self.revision.send_replace(last);
+
+        let view = self.view.clone();
+        self.rpc_handler
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    view.kv_watch().handle_kv_watch(req, responsor).await;
+                });
+                Ok(())
+            });
+
+        // keys evicted by ttl are deleted
+        let view = self.view.clone();
+        let mut expired = self.view.kv_store_engine().subscribe_expired();
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            loop {
+                match expired.recv().await {
+                    Ok(key) => view.kv_watch().record_change(key, KvChangeKind::Delete, 0),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(cnt)) => {
+                        tracing::warn!("{} expired events lost", cnt);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }))])
+    }
+}
+
+impl KvWatch {
+    /// append the change to the feed and wake up the watchers
+    pub fn record_change(&self, key: Vec<u8>, kind: KvChangeKind, version: u64) {
+        let _append_guard = self.append_lock.lock();
+        let revision = *self.revision.borrow() + 1;
+        self.view
+            .kv_store_engine()
+            .append_kv_change(revision, &KvChange { key, kind, version });
+        let _ = self.revision.send_replace(revision);
+    }
+    async fn handle_kv_watch(&self, req: KvWatchReq, responsor: RPCResponsor<KvWatchReq>) {
+        let resp = self.watch(req).await;
+        if let Err(err) = responsor.send_resp(resp).await {
+            tracing::error!("handle kv watch error:{}", err);
+        }
+    }
+    /// events of the key or keys matching the pattern after `from_revision`,
+    /// wait until any event or timeout
+    pub async fn watch(&self, req: KvWatchReq) -> KvWatchResp {
+        let pattern = req
+            .pattern
+            .then(|| KeyPattern::new(String::from_utf8_lossy(&req.key).into_owned()));
+        let matched = |key: &[u8]| match &pattern {
+            Some(pattern) => fn_event::key_match_pattern(pattern, key),
+            None => key == req.key.as_slice(),
+        };
+        let deadline =
+            Instant::now() + Duration::from_millis(req.timeout_ms as u64).min(MAX_WATCH_TIMEOUT);
+        let mut rx = self.revision.subscribe();
+        let mut scanned = if req.from_revision == 0 {
+            *rx.borrow()
+        } else {
+            req.from_revision
+        };
+        let mut compacted = false;
+        if let Some((first, _)) = self.view.kv_store_engine().kv_change_revisions() {
+            if scanned + 1 < first {
+                compacted = true;
+                scanned = first - 1;
+            }
+        }
+        loop {
+            let latest = *rx.borrow_and_update();
+            if latest > scanned {
+                let events: Vec<KvWatchEvent> = self
+                    .view
+                    .kv_store_engine()
+                    .kv_changes_between(scanned, latest)
+                    .into_iter()
+                    .filter(|(_, change)| matched(&change.key))
+                    .map(|(revision, change)| KvWatchEvent {
+                        key: change.key,
+                        delete: matches!(change.kind, KvChangeKind::Delete),
+                        version: change.version,
+                        revision,
+                    })
+                    .collect();
+                scanned = latest;
+                if !events.is_empty() {
+                    return KvWatchResp {
+                        events,
+                        revision: scanned,
+                        compacted,
+                    };
+                }
+            }
+            match tokio::time::timeout_at(deadline, rx.changed()).await {
+                Ok(Ok(())) => {}
+                // timeout or closed
+                _ => {
+                    return KvWatchResp {
+                        events: vec![],
+                        revision: scanned,
+                        compacted,
+                    }
+                }
+            }
+        }
+    }
+}
@@ -4,76 +4,156 @@ use async_trait::async_trait;
 use ws_derive::LogicalModule;
 
 use crate::{
-    general::network::{
-        m_p2p::{P2PModule, RPCCaller},
-        proto::{
-            self,
-            sche::{distribute_task_req::Trigger, DistributeTaskReq},
+    config::NodeSelectorType,
+    general::{
+        m_appmeta_manager::AppMetaManager,
+        network::{
+            m_p2p::{P2PModule, RPCCaller},
+            proto::{
+                self,
+                metric::RscMetric,
+                sche::{DistributeTaskReq, HttpInvokeReq, HttpInvokeResp},
+            },
         },
     },
     logical_module_view_impl,
-    result::WSResult,
+    result::{WSError, WSResult, WsFuncError},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
 };
 
+use super::{
+    m_dead_letters::DeadLetters, m_metric_observor::MetricObservor, m_trigger_queue::TriggerQueue,
+};
+
+/// time of a worker accepting the distributed task
+const DISTRIBUTE_TASK_TIMEOUT: Duration = Duration::from_secs(60);
+
 trait NodeWeighteFetcher: Send + Sync + 'static {
     // NOTE: get weight return node weight
     // larger is better
     fn get_node_weight(&self, id: NodeID) -> f64;
 }
 
+/// weight of a node that never reported metric or is fully loaded,
+/// keep it positive so that straw still works
+const MIN_NODE_WEIGHT: f64 = 0.001;
+
+/// idle cpu ratio * idle memory ratio
+fn rsc_metric_weight(metric: Option<&RscMetric>) -> f64 {
+    let Some(metric) = metric else {
+        return MIN_NODE_WEIGHT;
+    };
+    // cpu_used is the average usage percent of all cpus
+    let cpu_idle = (1.0 - metric.cpu_used as f64 / 100.0).clamp(0.0, 1.0);
+    let mem_idle = if metric.mem_all > 0.0 {
+        (1.0 - metric.mem_used as f64 / metric.mem_all as f64).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (cpu_idle * mem_idle).max(MIN_NODE_WEIGHT)
+}
+
+impl NodeWeighteFetcher for MasterView {
+    fn get_node_weight(&self, id: NodeID) -> f64 {
+        rsc_metric_weight(self.metric_observor().get_node_rsc_metric(id).as_ref())
+    }
+}
+
 struct StrawNodeSelector {
     weight_fetcher: Box<dyn NodeWeighteFetcher>,
 }
 
 // NOTE: Straw2 algorithm
 impl NodeSelector for StrawNodeSelector {
-    fn select_node(&self, all_node_cnt: usize, fn_name: &str) -> NodeID {
-        // NOTE: 1 is an impossible value for straw
-        let mut max_straw: f64 = 1.0;
-        let mut node_id: NodeID = 1;
-        // NOTE: node id is [1,all_node_cnt]
-        for i in 1..all_node_cnt + 1 {
+    fn select_node(&self, nodes: &[NodeID], fn_name: &str) -> NodeID {
+        let mut max_straw: Option<f64> = None;
+        let mut node_id: NodeID = nodes[0];
+        for &id in nodes {
             let mut hasher = DefaultHasher::new();
             hasher.write(fn_name.as_bytes());
-            hasher.write_u64(i as u64);
-            let hash = hasher.finish() % 63336;
-            let weight = self.weight_fetcher.get_node_weight(i as NodeID);
-            let straw = ((hash as f64) / 65536.0).ln() / weight;
-            if (max_straw - 1.0).abs() < 0.000001 || max_straw < straw {
-                max_straw = straw;
-                node_id = i as NodeID;
+            hasher.write_u64(id as u64);
+            // NOTE: +1 to avoid ln(0)
+            let hash = hasher.finish() % 65536 + 1;
+            let weight = self.weight_fetcher.get_node_weight(id);
+            let straw = ((hash as f64) / 65537.0).ln() / weight;
+            if max_straw.map_or(true, |max| max < straw) {
+                max_straw = Some(straw);
+                node_id = id;
             }
         }
-        return node_id;
+        node_id
+    }
+}
+
+/// select the node with the most idle resource,
+/// nodes with the same load are selected by hash
+struct LeastLoadNodeSelector {
+    weight_fetcher: Box<dyn NodeWeighteFetcher>,
+}
+
+impl NodeSelector for LeastLoadNodeSelector {
+    fn select_node(&self, nodes: &[NodeID], fn_name: &str) -> NodeID {
+        let weights = nodes
+            .iter()
+            .map(|&id| (id, self.weight_fetcher.get_node_weight(id)))
+            .collect::<Vec<_>>();
+        let max_weight = weights
+            .iter()
+            .map(|(_, w)| *w)
+            .fold(f64::MIN, f64::max);
+        let least_load = weights
+            .into_iter()
+            .filter(|(_, w)| (max_weight - w).abs() < 0.000001)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        HashNodeSelector.select_node(&least_load, fn_name)
     }
 }
 
 pub trait NodeSelector: Send + Sync + 'static {
-    fn select_node(&self, all_node_cnt: usize, fn_name: &str) -> NodeID;
+    /// `nodes` is the candidates, should not be empty
+    fn select_node(&self, nodes: &[NodeID], fn_name: &str) -> NodeID;
 }
 
 struct HashNodeSelector;
 
 impl NodeSelector for HashNodeSelector {
-    fn select_node(&self, all_node_cnt: usize, fn_name: &str) -> NodeID {
+    fn select_node(&self, nodes: &[NodeID], fn_name: &str) -> NodeID {
         // hash
         let mut hasher = DefaultHasher::new();
         hasher.write(fn_name.as_bytes());
         let n = hasher.finish();
 
-        (n % all_node_cnt as u64 + 1) as NodeID
+        nodes[(n % nodes.len() as u64) as usize]
+    }
+}
+
+fn new_node_selector(ty: NodeSelectorType, view: MasterView) -> Box<dyn NodeSelector> {
+    match ty {
+        NodeSelectorType::Hash => Box::new(HashNodeSelector),
+        NodeSelectorType::Straw => Box::new(StrawNodeSelector {
+            weight_fetcher: Box::new(view),
+        }),
+        NodeSelectorType::LeastLoad => Box::new(LeastLoadNodeSelector {
+            weight_fetcher: Box::new(view),
+        }),
     }
 }
 
 logical_module_view_impl!(MasterView);
 logical_module_view_impl!(MasterView, p2p, P2PModule);
 logical_module_view_impl!(MasterView, master, Option<Master>);
+logical_module_view_impl!(MasterView, metric_observor, Option<MetricObservor>);
+logical_module_view_impl!(MasterView, appmeta_manager, AppMetaManager);
+logical_module_view_impl!(MasterView, dead_letters, Option<DeadLetters>);
+logical_module_view_impl!(MasterView, trigger_queue, Option<TriggerQueue>);
 
 #[derive(LogicalModule)]
 pub struct Master {
     pub rpc_caller_distribute_task: RPCCaller<proto::sche::DistributeTaskReq>,
+    rpc_caller_http_invoke: RPCCaller<proto::sche::HttpInvokeReq>,
+    node_selector: Box<dyn NodeSelector>,
     view: MasterView,
 }
 
@@ -83,48 +163,290 @@ impl LogicalModule for Master {
     where
         Self: Sized,
     {
+        let view = MasterView::new(args.logical_modules_ref.clone());
         Self {
-            view: MasterView::new(args.logical_modules_ref.clone()),
+            node_selector: new_node_selector(args.nodes_config.sche.node_selector, view.clone()),
+            view,
             rpc_caller_distribute_task: RPCCaller::default(),
+            rpc_caller_http_invoke: RPCCaller::default(),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
         tracing::info!("start as master");
         self.rpc_caller_distribute_task.regist(&self.view.p2p());
+        self.rpc_caller_http_invoke.regist(&self.view.p2p());
 
         Ok(vec![])
     }
 }
 
 impl Master {
-    pub async fn handle_http_schedule(&self, _app: &str) -> NodeID {
-        self.select_node()
+    pub async fn handle_http_schedule(&self, app: &str) -> WSResult<NodeID> {
+        self.select_node(app)
+    }
+    /// `task` should be queued by trigger queue, `node` should be decided by
+    /// `select_node_for_data` or `select_node`,
+    /// failed one is retried in background by the retry policy of fn
+    pub async fn schedule_one_trigger(&self, task: DistributeTaskReq, node: NodeID) {
+        if let Err(err) = self.distribute_task(node, task.clone()).await {
+            tracing::warn!("schedule_one_trigger to node {} err: {:?}", node, err);
+            // the kv operation firing the event shouldn't wait for the backoff
+            let view = self.view.clone();
+            let _ = tokio::spawn(async move {
+                view.master().retry_task(task, node, err).await;
+            });
+        }
     }
-    pub async fn schedule_one_trigger(&self, app: String, func: String, trigger_data: Trigger) {
-        match self
+    /// retry the task failed on `node` with `err` on other workers,
+    /// kept as a dead letter when all attempts failed
+    async fn retry_task(&self, task: DistributeTaskReq, node: NodeID, mut err: WSError) {
+        let policy = self
             .view
-            .master()
+            .appmeta_manager()
+            .meta
+            .read()
+            .await
+            .fn_retry_policy(&task.app, &task.func);
+        let fn_name = format!("{}/{}", task.app, task.func);
+        let mut tried = vec![node];
+        for retry in 1..policy.max_attempts {
+            tokio::time::sleep(policy.backoff_of(retry)).await;
+            let node = match self.select_retry_node(&fn_name, &tried) {
+                Ok(node) => node,
+                Err(e) => {
+                    err = e;
+                    break;
+                }
+            };
+            tried.push(node);
+            match self.distribute_task(node, task.clone()).await {
+                Ok(()) => return,
+                Err(e) => {
+                    tracing::warn!(
+                        "retry {} of {} on node {} err: {:?}",
+                        retry,
+                        fn_name,
+                        node,
+                        e
+                    );
+                    err = e;
+                }
+            }
+        }
+        let _ = self.view.dead_letters().put(&task, tried, err.to_string());
+        // the dead letter is replayed by api instead
+        self.view.trigger_queue().remove(task.queue_id);
+    }
+    pub async fn distribute_task(&self, node: NodeID, task: DistributeTaskReq) -> WSResult<()> {
+        let (app, func) = (task.app.clone(), task.func.clone());
+        let resp = self
             .rpc_caller_distribute_task
-            .call(
-                self.view.p2p(),
-                self.select_node(),
-                DistributeTaskReq {
-                    app,
-                    func,
-                    task_id: 0, // TODO: Context task id for one request
-                    trigger: Some(trigger_data),
-                },
-                Duration::from_secs(60).into(),
-            )
+            .call(self.view.p2p(), node, task, Some(DISTRIBUTE_TASK_TIMEOUT))
+            .await?;
+        if !resp.error.is_empty() {
+            return Err(WsFuncError::TaskRejected {
+                node,
+                app,
+                func,
+                err: resp.error,
+            }
+            .into());
+        }
+        Ok(())
+    }
+    /// run the fn of http request on `node` and wait for its response
+    pub async fn invoke_http(
+        &self,
+        node: NodeID,
+        req: HttpInvokeReq,
+        timeout: Duration,
+    ) -> WSResult<HttpInvokeResp> {
+        self.rpc_caller_http_invoke
+            .call(self.view.p2p(), node, req, Some(timeout))
             .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                tracing::error!("schedule_one_trigger err: {:?}", err);
+    }
+    /// prefer the node holding the data, otherwise select by node selector
+    pub fn select_node_for_data(
+        &self,
+        app: &str,
+        func: &str,
+        data_pos: Option<NodeID>,
+    ) -> WSResult<NodeID> {
+        if let Some(node) = data_pos {
+            if self.view.p2p().nodes_config.is_worker_node(node) {
+                return Ok(node);
+            }
+        }
+        self.select_node(&format!("{app}/{func}"))
+    }
+    /// fails if there's no worker node
+    pub fn select_node(&self, fn_name: &str) -> WSResult<NodeID> {
+        let workers = self.workers(fn_name)?;
+        Ok(self.node_selector.select_node(&workers, fn_name))
+    }
+    /// prefer the workers not `tried` yet, fails if there's no worker node
+    pub fn select_retry_node(&self, fn_name: &str, tried: &[NodeID]) -> WSResult<NodeID> {
+        let workers = self.workers(fn_name)?;
+        Ok(self
+            .node_selector
+            .select_node(&retry_candidates(workers, tried), fn_name))
+    }
+    fn workers(&self, fn_name: &str) -> WSResult<Vec<NodeID>> {
+        let workers = self.view.p2p().nodes_config.get_worker_nodes();
+        if workers.is_empty() {
+            return Err(WsFuncError::NoWorker {
+                target: fn_name.to_owned(),
             }
+            .into());
+        }
+        Ok(workers)
+    }
+}
+
+/// workers not tried yet, or all workers if each is tried
+fn retry_candidates(workers: Vec<NodeID>, tried: &[NodeID]) -> Vec<NodeID> {
+    let untried: Vec<NodeID> = workers
+        .iter()
+        .copied()
+        .filter(|node| !tried.contains(node))
+        .collect();
+    if untried.is_empty() {
+        workers
+    } else {
+        untried
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use std::time::Instant;
+
+    use super::*;
+    use crate::master::m_metric_observor::ReceivedRscMetric;
+
+    impl NodeWeighteFetcher for HashMap<NodeID, RscMetric> {
+        fn get_node_weight(&self, id: NodeID) -> f64 {
+            rsc_metric_weight(self.get(&id))
+        }
+    }
+
+    impl NodeWeighteFetcher for HashMap<NodeID, ReceivedRscMetric> {
+        fn get_node_weight(&self, id: NodeID) -> f64 {
+            rsc_metric_weight(self.get(&id).and_then(ReceivedRscMetric::fresh))
+        }
+    }
+
+    fn metric(cpu_used: f32, mem_used: f32) -> RscMetric {
+        RscMetric {
+            cpu_used,
+            mem_used,
+            cpu_all: 100.0,
+            mem_all: 100.0,
+            cold_starts: vec![],
+            pools: vec![],
+        }
+    }
+
+    fn synthetic_metrics() -> HashMap<NodeID, RscMetric> {
+        let mut metrics = HashMap::new();
+        let _ = metrics.insert(2, metric(90.0, 80.0));
+        let _ = metrics.insert(3, metric(10.0, 20.0));
+        let _ = metrics.insert(4, metric(50.0, 50.0));
+        metrics
+    }
+
+    #[test]
+    fn test_rsc_metric_weight() {
+        assert_eq!(rsc_metric_weight(None), MIN_NODE_WEIGHT);
+        assert_eq!(rsc_metric_weight(Some(&metric(100.0, 100.0))), MIN_NODE_WEIGHT);
+        assert!((rsc_metric_weight(Some(&metric(50.0, 50.0))) - 0.25).abs() < 0.000001);
+    }
+
+    #[test]
+    fn test_hash_selector_in_candidates() {
+        let nodes = vec![2, 3, 4];
+        for i in 0..100 {
+            let fn_name = format!("app/fn{}", i);
+            let node = HashNodeSelector.select_node(&nodes, &fn_name);
+            assert!(nodes.contains(&node));
+            assert_eq!(node, HashNodeSelector.select_node(&nodes, &fn_name));
         }
     }
-    fn select_node(&self) -> NodeID {
-        2
+
+    #[test]
+    fn test_least_load_selector() {
+        let selector = LeastLoadNodeSelector {
+            weight_fetcher: Box::new(synthetic_metrics()),
+        };
+        for i in 0..100 {
+            assert_eq!(selector.select_node(&[2, 3, 4], &format!("app/fn{}", i)), 3);
+        }
+        // node 3 is not a candidate
+        assert_eq!(selector.select_node(&[2, 4], "app/fn"), 4);
+        // node 5 never reported metric
+        assert_eq!(selector.select_node(&[2, 5], "app/fn"), 2);
+    }
+
+    #[test]
+    fn test_least_load_selector_spread_on_same_load() {
+        let selector = LeastLoadNodeSelector {
+            weight_fetcher: Box::new(HashMap::<NodeID, RscMetric>::new()),
+        };
+        let nodes = vec![2, 3, 4];
+        let selected = (0..100)
+            .map(|i| selector.select_node(&nodes, &format!("app/fn{}", i)))
+            .collect::<std::collections::HashSet<_>>();
+        assert!(selected.len() > 1);
+    }
+
+    #[test]
+    fn test_selector_ignore_stale_metric() {
+        let mut metrics = HashMap::new();
+        // node 3 was idle but stopped reporting
+        let _ = metrics.insert(
+            3,
+            ReceivedRscMetric {
+                metric: metric(10.0, 20.0),
+                received: Instant::now() - Duration::from_secs(60),
+            },
+        );
+        let _ = metrics.insert(
+            4,
+            ReceivedRscMetric {
+                metric: metric(50.0, 50.0),
+                received: Instant::now(),
+            },
+        );
+        assert_eq!(metrics.get_node_weight(3), MIN_NODE_WEIGHT);
+        let selector = LeastLoadNodeSelector {
+            weight_fetcher: Box::new(metrics),
+        };
+        for i in 0..100 {
+            assert_eq!(selector.select_node(&[3, 4], &format!("app/fn{}", i)), 4);
+        }
+    }
+
+    #[test]
+    fn test_straw_selector_prefer_idle_node() {
+        let selector = StrawNodeSelector {
+            weight_fetcher: Box::new(synthetic_metrics()),
+        };
+        let mut cnt: HashMap<NodeID, usize> = HashMap::new();
+        for i in 0..1000 {
+            let node = selector.select_node(&[2, 3, 4], &format!("app/fn{}", i));
+            *cnt.entry(node).or_default() += 1;
+        }
+        assert!(cnt.get(&3).unwrap() > cnt.get(&4).unwrap());
+        assert!(cnt.get(&4).unwrap_or(&0) > cnt.get(&2).unwrap_or(&0));
+    }
+
+    #[test]
+    fn test_retry_candidates() {
+        assert_eq!(retry_candidates(vec![2, 3, 4], &[3]), vec![2, 4]);
+        assert_eq!(retry_candidates(vec![2, 3, 4], &[3, 2, 3]), vec![4]);
+        // each worker failed once, retry on any of them
+        assert_eq!(retry_candidates(vec![2, 3], &[2, 3]), vec![2, 3]);
     }
 }
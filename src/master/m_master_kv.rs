@@ -1,26 +1,33 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     sync::{atomic::AtomicU32, Arc},
+    time::Duration,
 };
 
 use async_trait::async_trait;
 use parking_lot::RwLock;
-use tokio::sync::Notify;
+use tokio::{sync::Notify, task::JoinHandle};
 use ws_derive::LogicalModule;
 
 use crate::{
     general::{
+        kv_interface::KvOps,
         m_appmeta_manager::{
             fn_event::{self, EventTriggerInfo},
-            AppMetaManager,
+            AppMetaManager, KvEventKind,
+        },
+        m_kv_store_engine::{
+            KeyTypeKv, KeyTypeKvPosition, KvBatch, KvChangeKind, KvStoreEngine, KvValue,
         },
-        m_kv_store_engine::{KeyTypeKv, KeyTypeKvPosition, KvStoreEngine},
         network::{
-            m_p2p::{P2PModule, RPCHandler, RPCResponsor, TaskId},
-            msg_pack::KvResponseExt,
+            m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor, TaskId},
+            msg_pack::{KeyRangeExt, KvResponseExt, KvResponsesExt},
             proto::{
                 self,
-                kv::{KvRequests, KvResponse, KvResponses},
+                kv::{
+                    KvPositionReq, KvPositionResp, KvRequest, KvRequests, KvResponse, KvResponses,
+                },
+                sche::{distribute_task_req::Trigger, DistributeTaskReq},
             },
         },
     },
@@ -30,7 +37,12 @@ use crate::{
     util::JoinHandleWrapper,
 };
 
-use super::m_master::Master;
+use super::{
+    m_kv_watch::KvWatch,
+    m_master::Master,
+    m_trigger_queue::{KeyTypeTriggerQueue, TriggerQueue, UnconfirmedWrite},
+    m_workflow::{WorkflowCtx, Workflows},
+};
 
 logical_module_view_impl!(MasterKvView);
 logical_module_view_impl!(MasterKvView, p2p, P2PModule);
@@ -38,15 +50,85 @@ logical_module_view_impl!(MasterKvView, appmeta_manager, AppMetaManager);
 logical_module_view_impl!(MasterKvView, master, Option<Master>);
 logical_module_view_impl!(MasterKvView, master_kv, Option<MasterKv>);
 logical_module_view_impl!(MasterKvView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(MasterKvView, kv_watch, Option<KvWatch>);
+logical_module_view_impl!(MasterKvView, trigger_queue, Option<TriggerQueue>);
+logical_module_view_impl!(MasterKvView, workflows, Option<Workflows>);
 
 #[derive(LogicalModule)]
 pub struct MasterKv {
     lock_notifiers: RwLock<HashMap<Vec<u8>, (NodeID, u32, Arc<Notify>)>>,
     rpc_handler: RPCHandler<proto::kv::KvRequests>,
+    rpc_handler_kv_position: RPCHandler<proto::kv::KvPositionReq>,
+    // send data operations to the worker holding the data
+    rpc_caller_kv: RPCCaller<proto::kv::KvRequests>,
     view: MasterKvView,
     kv_ope_id_allocator: AtomicU32,
     // kv_ope_notify: tokio::sync::RwLock<HashMap<u32, Arc<Notify>>>,
     kv_ope_notify: RwLock<HashMap<u32, Arc<Notify>>>,
+    // keys being written, a transaction holds its keys until they're moved back
+    key_locks: KeyLocks,
+}
+
+/// fns a set, cas, add or delete may fire, queued as unconfirmed before the write is sent to
+/// the data node and confirmed by the version written, so that none of them is lost
+/// if master goes down in between
+struct WriteTriggers<'a> {
+    kv_opeid: &'a mut Option<u32>,
+    workflow: WorkflowCtx,
+    event: Option<&'a EventTriggerInfo>,
+    targets: &'a [((String, String, KvEventKind), NodeID)],
+    unconfirmed: Vec<(DistributeTaskReq, NodeID)>,
+    /// confirmed to be fired by the write
+    fired: Vec<(DistributeTaskReq, NodeID)>,
+}
+
+/// exclusive locks of keys written through master, so that a transaction moving a key
+/// doesn't miss the writes routed to the node holding it meanwhile.
+/// writes by `KvOptions::with_spec_node` go to the node directly and are not blocked
+#[derive(Default)]
+struct KeyLocks {
+    locked: parking_lot::Mutex<HashSet<Vec<u8>>>,
+    released: Notify,
+}
+
+struct KeyLocksGuard<'a> {
+    locks: &'a KeyLocks,
+    keys: Vec<Vec<u8>>,
+}
+
+impl KeyLocks {
+    /// wait until none of the keys is locked and lock all of them
+    async fn lock(&self, keys: impl IntoIterator<Item = Vec<u8>>) -> KeyLocksGuard<'_> {
+        let keys: Vec<Vec<u8>> = keys
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            // so that releasing after the check still wakes us
+            let _ = released.as_mut().enable();
+            {
+                let mut locked = self.locked.lock();
+                if keys.iter().all(|key| !locked.contains(key)) {
+                    locked.extend(keys.iter().cloned());
+                    return KeyLocksGuard { locks: self, keys };
+                }
+            }
+            released.await;
+        }
+    }
+}
+
+impl Drop for KeyLocksGuard<'_> {
+    fn drop(&mut self) {
+        let mut locked = self.locks.locked.lock();
+        for key in &self.keys {
+            let _ = locked.remove(key);
+        }
+        self.locks.released.notify_waiters();
+    }
 }
 
 #[async_trait]
@@ -58,10 +140,13 @@ impl LogicalModule for MasterKv {
         Self {
             lock_notifiers: RwLock::new(HashMap::new()),
             rpc_handler: RPCHandler::default(),
+            rpc_handler_kv_position: RPCHandler::default(),
+            rpc_caller_kv: RPCCaller::default(),
             view: MasterKvView::new(args.logical_modules_ref.clone()),
             kv_ope_id_allocator: AtomicU32::new(0),
             // kv_ope_notify: tokio::sync::RwLock::new(HashMap::new()),
             kv_ope_notify: RwLock::new(HashMap::new()),
+            key_locks: KeyLocks::default(),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
@@ -74,8 +159,31 @@ impl LogicalModule for MasterKv {
                 });
                 Ok(())
             });
+        let view = self.view.clone();
+        self.rpc_handler_kv_position
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    view.master_kv().handle_kv_position(req, responsor).await;
+                });
+                Ok(())
+            });
+        self.rpc_caller_kv.regist(self.view.p2p());
 
-        Ok(vec![])
+        // keys evicted by ttl trigger delete events
+        let view = self.view.clone();
+        let mut expired = self.view.kv_store_engine().subscribe_expired();
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            loop {
+                match expired.recv().await {
+                    Ok(key) => view.master_kv().handle_kv_expired(key).await,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(cnt)) => {
+                        tracing::warn!("{} expired events lost", cnt);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }))])
     }
 }
 
@@ -92,6 +200,37 @@ impl MasterKv {
             .collect()
     }
 
+    /// decide the node of each triggered fn and where the data should be placed
+    /// - fns consuming the data (declared by `kvs` in app.yaml) run where the data lives
+    /// - if the data is not placed on any worker yet, place it to the first consumer
+    /// - fns are dropped if there's no worker to run them
+    async fn decide_trigger_placement(
+        &self,
+        key: &[u8],
+        app_fns: &[(String, String, KvEventKind)],
+    ) -> (Vec<((String, String, KvEventKind), NodeID)>, Option<NodeID>) {
+        let master = self.view.master();
+        let metas = self.view.appmeta_manager().meta.read().await;
+        let mut data_pos = self.view.kv_store_engine().get(KeyTypeKvPosition(key));
+        let mut targets = vec![];
+        for appfn in app_fns {
+            let (app, func, _) = appfn;
+            let consume = metas.fn_consume_key(app, func, key);
+            let node = match master.select_node_for_data(app, func, data_pos.filter(|_| consume)) {
+                Ok(node) => node,
+                Err(err) => {
+                    tracing::warn!("event of {}/{} dropped: {}", app, func, err);
+                    continue;
+                }
+            };
+            if consume {
+                let _ = data_pos.insert(node);
+            }
+            targets.push((appfn.clone(), node));
+        }
+        (targets, data_pos)
+    }
+
     async fn handle_kv_requests(
         &self,
         reqs: proto::kv::KvRequests,
@@ -111,49 +250,71 @@ impl MasterKv {
                 noted.await
             }
         }
-        let mut kv_responses = KvResponses { responses: vec![] };
         // pre-collect each operation's event trigger info
         let trigger = self.collect_event_infos(&reqs).await;
-        let mut kv_opeid = None;
+        // fns triggered join the workflow of the caller
+        let workflow = WorkflowCtx {
+            workflow_id: reqs.workflow_id,
+            task: reqs.workflow_task,
+        };
+        if reqs.txn {
+            self.handle_kv_txn(reqs, trigger, workflow, responsor).await;
+            return;
+        }
+        let mut kv_responses = KvResponses {
+            responses: vec![],
+            aborted: false,
+            error: String::new(),
+        };
         for (req, event) in reqs.requests.into_iter().zip(trigger) {
-            let mut sub_tasks = vec![];
+            let mut kv_opeid = None;
             // if with event
-            if let Some(mut trigger) = event {
-                let app_fns = std::mem::take(&mut trigger.trigger_appfns);
-
-                for (app, func) in app_fns {
-                    if kv_opeid.is_none() {
-                        kv_opeid = Some(
-                            self.kv_ope_id_allocator
-                                .fetch_add(1, std::sync::atomic::Ordering::Relaxed),
-                        );
-                        assert!(self
-                            .kv_ope_notify
-                            .write()
-                            .insert(kv_opeid.unwrap(), Notify::new().into())
-                            .is_none());
-                    }
-                    let trigger_data = trigger.to_trigger(kv_opeid.unwrap());
-                    let view = self.view.clone();
-                    // schedule sub tasks parallelly
-                    sub_tasks.push(tokio::spawn(async move {
-                        view.master()
-                            .schedule_one_trigger(app, func, trigger_data)
-                            .await;
-                    }));
-                }
+            let mut data_pos = None;
+            let mut targets = vec![];
+            if let Some(trigger) = event.as_ref() {
+                (targets, data_pos) = self
+                    .decide_trigger_placement(&trigger.key, &trigger.trigger_appfns)
+                    .await;
             }
-            kv_responses.responses.push(match req.op.unwrap() {
+            let change = kv_change_of(&req);
+            let key_guard = self
+                .key_locks
+                .lock(change.iter().map(|(key, _)| key.clone()))
+                .await;
+            let mut triggers = WriteTriggers {
+                kv_opeid: &mut kv_opeid,
+                workflow,
+                event: event.as_ref(),
+                targets: &targets,
+                unconfirmed: vec![],
+                fired: vec![],
+            };
+            let resp = match req.op.unwrap() {
                 proto::kv::kv_request::Op::Set(set) => {
-                    self.handle_kv_set(set, responsor.node_id()).await
+                    self.handle_kv_set(set, responsor.node_id(), data_pos, &mut triggers)
+                        .await
                 }
                 proto::kv::kv_request::Op::Get(get) => self.handle_kv_get(get).await,
-                proto::kv::kv_request::Op::Delete(delete) => self.handle_kv_delete(delete).await,
+                proto::kv::kv_request::Op::Delete(delete) => {
+                    self.handle_kv_delete(delete, (&reqs.app, &reqs.func), &mut triggers)
+                        .await
+                }
                 proto::kv::kv_request::Op::Lock(lock) => {
                     self.handle_kv_lock(lock, responsor.node_id(), responsor.task_id())
                         .await
-                } // notify sub tasks to run because data's persisted
-            });
+                }
+                op @ (proto::kv::kv_request::Op::Cas(_) | proto::kv::kv_request::Op::Add(_)) => {
+                    self.handle_kv_atomic(op, responsor.node_id(), data_pos, &mut triggers)
+                        .await
+                }
+                proto::kv::kv_request::Op::Guard(guard) => self.handle_kv_guard(guard).await,
+            };
+            let fired = triggers.fired;
+            self.record_change(change, &resp);
+            drop(key_guard);
+            let sub_tasks = self.spawn_tasks(fired);
+            // notify sub tasks to run because data's persisted
+            kv_responses.responses.push(resp);
             tracing::debug!("notify all waiting kv operations");
             // notify all waiting kv operations
             if let Some(opeid) = kv_opeid {
@@ -173,64 +334,865 @@ impl MasterKv {
             tracing::error!("handle kv requests error:{}", err);
         };
     }
+    /// all operations are applied in one transaction on master,
+    /// keys held by other nodes are moved to master first and back after commit,
+    /// the keys are locked meanwhile, triggers are queued in the transaction
+    /// and scheduled after commit.
+    /// keys created by the transaction are held by master, no consumer decides their place
+    async fn handle_kv_txn(
+        &self,
+        reqs: KvRequests,
+        trigger: Vec<Option<EventTriggerInfo>>,
+        workflow: WorkflowCtx,
+        responsor: RPCResponsor<KvRequests>,
+    ) {
+        let keys: BTreeSet<&[u8]> = reqs.requests.iter().filter_map(txn_key).collect();
+        let key_guard = self
+            .key_locks
+            .lock(keys.iter().map(|key| key.to_vec()))
+            .await;
+        let this_node = self.view.p2p().nodes_config.this_node();
+        let kv_store_engine = self.view.kv_store_engine();
+        let mut moved_in = HashMap::new();
+        let mut moved_from = vec![];
+        for key in keys {
+            let Some(pos) = kv_store_engine
+                .get(KeyTypeKvPosition(key))
+                .filter(|pos| *pos != this_node)
+            else {
+                continue;
+            };
+            let req = KvRequest {
+                op: Some(proto::kv::kv_request::Op::Get(
+                    proto::kv::kv_request::KvGetRequest {
+                        range: Some(proto::kv::KeyRange {
+                            start: key.to_owned(),
+                            end: vec![],
+                            prefix: false,
+                        }),
+                        revision: 0,
+                    },
+                )),
+            };
+            match self.call_data_node(pos, req).await {
+                Ok(resp) => {
+                    let value = resp.common_kvs().and_then(|kvs| kvs.first()).map(|kv| {
+                        KvValue {
+                            value: kv.value.clone(),
+                            version: kv.version,
+                        }
+                    });
+                    let _ = moved_in.insert(key.to_owned(), value);
+                    moved_from.push((pos, key.to_owned()));
+                }
+                Err(err) => {
+                    tracing::error!("kv txn get from node {} failed: {:?}", pos, err);
+                    let err = format!("get key of transaction from node {} failed: {:?}", pos, err);
+                    if let Err(err) = responsor
+                        .send_resp(KvResponses::new_err(&reqs.requests, err))
+                        .await
+                    {
+                        tracing::error!("handle kv txn error:{}", err);
+                    }
+                    return;
+                }
+            }
+        }
+        // the tasks each operation may fire, queued in the transaction if they're fired
+        let trigger_queue = self.view.trigger_queue();
+        let mut kv_opeid = None;
+        let mut pending = vec![];
+        for (idx, (req, event)) in reqs.requests.iter().zip(trigger.iter()).enumerate() {
+            let deleted;
+            let (trigger, targets) = match event {
+                Some(trigger) => {
+                    let (targets, _) = self
+                        .decide_trigger_placement(&trigger.key, &trigger.trigger_appfns)
+                        .await;
+                    (trigger, targets)
+                }
+                None => {
+                    // deleted keys that exist before the transaction fire delete events
+                    let Some(key) = txn_key(req).filter(|key| {
+                        matches!(req.op, Some(proto::kv::kv_request::Op::Delete(_)))
+                            && kv_store_engine.get(KeyTypeKvPosition(key)).is_some()
+                    }) else {
+                        continue;
+                    };
+                    let Some((trigger, targets)) =
+                        self.delete_event(key, Some((&reqs.app, &reqs.func))).await
+                    else {
+                        continue;
+                    };
+                    deleted = trigger;
+                    (&deleted, targets)
+                }
+            };
+            for (target, node) in targets {
+                // the version written is set when it's queued
+                let mut task = self.trigger_task(&mut kv_opeid, workflow, trigger, target, 0);
+                trigger_queue.reserve(&mut task);
+                pending.push((idx, task, node));
+            }
+        }
+        let queue_fired = |responses: &[KvResponse]| {
+            let mut batch = KvBatch::default();
+            let fired = self.queue_txn_triggers(&mut batch, &pending, responses);
+            (batch, fired)
+        };
+        let Some(responses) =
+            kv_store_engine.apply_kv_txn(&reqs.requests, &moved_in, Some(this_node), |resps| {
+                queue_fired(resps).0
+            })
+        else {
+            tracing::debug!("kv txn aborted");
+            // nothing fired
+            if let Some(opeid) = kv_opeid {
+                let _ = self.kv_ope_notify.write().remove(&opeid);
+            }
+            if let Err(err) = responsor
+                .send_resp(KvResponses::new_aborted(&reqs.requests))
+                .await
+            {
+                tracing::error!("handle kv txn error:{}", err);
+            }
+            return;
+        };
+        kv_store_engine.flush();
+        for (node, key) in moved_from {
+            self.move_back_to_node(node, key).await;
+        }
+        drop(key_guard);
+
+        for (req, resp) in reqs.requests.iter().zip(responses.iter()) {
+            self.record_change(kv_change_of(req), resp);
+        }
+
+        let (_, fired) = queue_fired(&responses);
+        for (task, node) in &fired {
+            self.add_workflow_task(workflow, task, *node);
+        }
+        let sub_tasks = self.spawn_tasks(fired);
+        if let Some(opeid) = kv_opeid {
+            if let Some(notify) = self.kv_ope_notify.write().remove(&opeid) {
+                notify.notify_waiters();
+            }
+        }
+        for task in sub_tasks {
+            task.await.unwrap();
+        }
+
+        if let Err(err) = responsor
+            .send_resp(KvResponses {
+                responses,
+                aborted: false,
+                error: String::new(),
+            })
+            .await
+        {
+            tracing::error!("handle kv txn error:{}", err);
+        }
+    }
+    /// keys evicted by ttl are deleted by system, any fn listening the deletion fires,
+    /// the triggers are queued after the key's evicted, they're lost if master goes down between
+    async fn handle_kv_expired(&self, key: Vec<u8>) {
+        let Some((trigger, targets)) = self.delete_event(&key, None).await else {
+            return;
+        };
+        let mut kv_opeid = None;
+        let sub_tasks =
+            self.spawn_triggers(&mut kv_opeid, WorkflowCtx::default(), &trigger, 0, targets);
+        if let Some(opeid) = kv_opeid {
+            if let Some(notify) = self.kv_ope_notify.write().remove(&opeid) {
+                notify.notify_waiters();
+            }
+        }
+        for task in sub_tasks {
+            task.await.unwrap();
+        }
+    }
+    /// fns listening the deletion of the key and the nodes to run them,
+    /// `source` is the app fn deleting it, None for system
+    async fn delete_event(
+        &self,
+        key: &[u8],
+        source: Option<(&str, &str)>,
+    ) -> Option<(
+        EventTriggerInfo,
+        Vec<((String, String, KvEventKind), NodeID)>,
+    )> {
+        let trigger = {
+            let metas = self.view.appmeta_manager().meta.read().await;
+            fn_event::match_kv_event(&metas, key, KvOps::Delete, source)
+        }?;
+        let (targets, _) = self
+            .decide_trigger_placement(key, &trigger.trigger_appfns)
+            .await;
+        Some((trigger, targets))
+    }
+    /// publish the set or delete to watchers
+    fn record_change(&self, change: Option<(Vec<u8>, KvChangeKind)>, resp: &KvResponse) {
+        match change {
+            Some((key, KvChangeKind::Set)) => {
+                if let Some(version) = written_revision(resp) {
+                    self.view
+                        .kv_watch()
+                        .record_change(key, KvChangeKind::Set, version);
+                }
+            }
+            Some((key, KvChangeKind::Delete)) => {
+                self.view
+                    .kv_watch()
+                    .record_change(key, KvChangeKind::Delete, 0);
+            }
+            None => {}
+        }
+    }
+    /// check the value on the node holding the key
+    async fn handle_kv_guard(&self, guard: proto::kv::kv_request::KvGuardRequest) -> KvResponse {
+        let pos = self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeKvPosition(&guard.key))
+            .unwrap_or_else(|| self.view.p2p().nodes_config.this_node());
+        let req = KvRequest {
+            op: Some(proto::kv::kv_request::Op::Guard(guard)),
+        };
+        match self.call_data_node(pos, req).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                tracing::error!("kv guard on node {} failed: {:?}", pos, err);
+                KvResponse::new_atomic(false, vec![])
+                    .with_err(format!("guard on node {} failed: {:?}", pos, err))
+            }
+        }
+    }
+    /// queue the fns the write may fire before it's sent to `data_node`, as unconfirmed,
+    /// the version of the key there is taken so that they can be resolved without master
+    async fn queue_unconfirmed_triggers(
+        &self,
+        triggers: &mut WriteTriggers<'_>,
+        key: &[u8],
+        data_node: NodeID,
+    ) -> WSResult<()> {
+        let Some(trigger) = triggers.event.filter(|_| !triggers.targets.is_empty()) else {
+            return Ok(());
+        };
+        let prev_version = self.version_on_node(data_node, key).await?;
+        let trigger_queue = self.view.trigger_queue();
+        let mut batch = KvBatch::default();
+        for (target, node) in triggers.targets.iter().cloned() {
+            // the version written is set when confirmed
+            let mut task =
+                self.trigger_task(triggers.kv_opeid, triggers.workflow, trigger, target, 0);
+            trigger_queue.enqueue_unconfirmed(
+                &mut batch,
+                &mut task,
+                node,
+                UnconfirmedWrite {
+                    key: key.to_owned(),
+                    data_node,
+                    prev_version,
+                },
+            );
+            triggers.unconfirmed.push((task, node));
+        }
+        let kv_store_engine = self.view.kv_store_engine();
+        kv_store_engine.apply_batch(batch);
+        kv_store_engine.flush();
+        Ok(())
+    }
+    /// confirm the unconfirmed triggers in `batch` by the version written, 0 for delete,
+    /// all of them are removed if nothing's written
+    fn confirm_triggers(
+        &self,
+        triggers: &mut WriteTriggers<'_>,
+        batch: &mut KvBatch,
+        revision: Option<u64>,
+    ) {
+        let trigger_queue = self.view.trigger_queue();
+        for (mut task, node) in triggers.unconfirmed.drain(..) {
+            let Some(revision) = revision else {
+                batch.del(KeyTypeTriggerQueue(task.queue_id));
+                continue;
+            };
+            if !trigger_queue.confirm(batch, &mut task, node, revision) {
+                continue;
+            }
+            self.add_workflow_task(triggers.workflow, &task, node);
+            triggers.fired.push((task, node));
+        }
+    }
+    /// queue the tasks reserved for the operations of a transaction in `batch`
+    /// if they're fired by the responses, returns the fired ones
+    fn queue_txn_triggers(
+        &self,
+        batch: &mut KvBatch,
+        pending: &[(usize, DistributeTaskReq, NodeID)],
+        responses: &[KvResponse],
+    ) -> Vec<(DistributeTaskReq, NodeID)> {
+        let trigger_queue = self.view.trigger_queue();
+        pending
+            .iter()
+            .filter_map(|(idx, task, node)| {
+                let mut task = task.clone();
+                let revision = match task.trigger {
+                    Some(Trigger::KvDelete(_)) => 0,
+                    _ => written_revision(&responses[*idx])?,
+                };
+                trigger_queue
+                    .confirm(batch, &mut task, *node, revision)
+                    .then_some((task, *node))
+            })
+            .collect()
+    }
+    /// the task is tracked in the workflow of the fn firing it
+    fn add_workflow_task(&self, workflow: WorkflowCtx, task: &DistributeTaskReq, node: NodeID) {
+        if workflow.workflow_id != 0 {
+            self.view.workflows().add_task(
+                workflow,
+                task.queue_id,
+                format!("{}/{}", task.app, task.func),
+                node,
+            );
+        }
+    }
+    /// task of the target fn fired by the event, kv operation id is allocated at the first trigger
+    fn trigger_task(
+        &self,
+        kv_opeid: &mut Option<u32>,
+        workflow: WorkflowCtx,
+        trigger: &EventTriggerInfo,
+        (app, func, kind): (String, String, KvEventKind),
+        revision: u64,
+    ) -> DistributeTaskReq {
+        let opeid = *kv_opeid.get_or_insert_with(|| {
+            let opeid = self
+                .kv_ope_id_allocator
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            assert!(self
+                .kv_ope_notify
+                .write()
+                .insert(opeid, Notify::new().into())
+                .is_none());
+            opeid
+        });
+        DistributeTaskReq {
+            app,
+            func,
+            task_id: workflow.workflow_id,
+            trigger: Some(trigger.to_trigger(kind, opeid, revision)),
+            queue_id: 0,
+        }
+    }
+    /// build the task of each target fn and queue it in `batch`
+    fn queue_triggers(
+        &self,
+        batch: &mut KvBatch,
+        kv_opeid: &mut Option<u32>,
+        workflow: WorkflowCtx,
+        trigger: &EventTriggerInfo,
+        revision: u64,
+        targets: Vec<((String, String, KvEventKind), NodeID)>,
+    ) -> Vec<(DistributeTaskReq, NodeID)> {
+        let trigger_queue = self.view.trigger_queue();
+        targets
+            .into_iter()
+            .map(|(target, node)| {
+                let mut task = self.trigger_task(kv_opeid, workflow, trigger, target, revision);
+                trigger_queue.enqueue(batch, &mut task, node);
+                self.add_workflow_task(workflow, &task, node);
+                (task, node)
+            })
+            .collect()
+    }
+    /// queue the triggers by a write of their own and schedule them
+    fn spawn_triggers(
+        &self,
+        kv_opeid: &mut Option<u32>,
+        workflow: WorkflowCtx,
+        trigger: &EventTriggerInfo,
+        revision: u64,
+        targets: Vec<((String, String, KvEventKind), NodeID)>,
+    ) -> Vec<JoinHandle<()>> {
+        if targets.is_empty() {
+            return vec![];
+        }
+        let mut batch = KvBatch::default();
+        let tasks = self.queue_triggers(&mut batch, kv_opeid, workflow, trigger, revision, targets);
+        let kv_store_engine = self.view.kv_store_engine();
+        kv_store_engine.apply_batch(batch);
+        kv_store_engine.flush();
+        self.spawn_tasks(tasks)
+    }
+    fn spawn_tasks(&self, tasks: Vec<(DistributeTaskReq, NodeID)>) -> Vec<JoinHandle<()>> {
+        tasks
+            .into_iter()
+            .map(|(task, node)| {
+                let view = self.view.clone();
+                // schedule sub tasks parallelly
+                tokio::spawn(async move {
+                    view.master().schedule_one_trigger(task, node).await;
+                })
+            })
+            .collect()
+    }
+    /// cas and add are executed atomically by the node holding the data,
+    /// the data is not moved since the operation depends on current value
+    async fn handle_kv_atomic(
+        &self,
+        op: proto::kv::kv_request::Op,
+        from: NodeID,
+        data_pos: Option<NodeID>,
+        triggers: &mut WriteTriggers<'_>,
+    ) -> KvResponse {
+        let ttl = match &op {
+            proto::kv::kv_request::Op::Cas(cas) => cas.ttl,
+            proto::kv::kv_request::Op::Add(add) => add.ttl,
+            _ => 0,
+        };
+        let req = KvRequest { op: Some(op) };
+        let key = fn_event::kv_set_key(&req).unwrap().to_owned();
+        tracing::debug!("handle_kv_atomic:{:?}", key);
+        let nodes_config = &self.view.p2p().nodes_config;
+        let old_pos = self.view.kv_store_engine().get(KeyTypeKvPosition(&key));
+        let pos = old_pos
+            .or(data_pos)
+            .or(Some(from).filter(|node| nodes_config.is_worker_node(*node)))
+            .unwrap_or_else(|| nodes_config.this_node());
+        if let Err(err) = self.queue_unconfirmed_triggers(triggers, &key, pos).await {
+            tracing::error!("queue triggers of kv atomic operation failed: {:?}", err);
+            return KvResponse::new_atomic(false, vec![])
+                .with_err(format!("queue triggers failed: {:?}", err));
+        }
+        match self.call_data_node(pos, req).await {
+            Ok(resp) => {
+                let mut batch = KvBatch::default();
+                if old_pos.is_none() && resp.atomic_res().map_or(false, |(succ, _)| succ) {
+                    // the key is created, its directory record expires with the data
+                    batch.set(KeyTypeKvPosition(&key), &pos);
+                    batch.set_kv_expire(&key, ttl);
+                }
+                self.confirm_triggers(triggers, &mut batch, written_revision(&resp));
+                let kv_store_engine = self.view.kv_store_engine();
+                kv_store_engine.apply_batch(batch);
+                kv_store_engine.flush();
+                resp
+            }
+            Err(err) => {
+                // the unconfirmed triggers are resolved when they're dispatched again
+                tracing::error!("kv atomic operation on node {} failed: {:?}", pos, err);
+                // not a mismatch, the current value is unknown
+                KvResponse::new_atomic(false, vec![]).with_err(format!(
+                    "atomic operation on node {} failed: {:?}",
+                    pos, err
+                ))
+            }
+        }
+    }
+    /// apply the data operation on the node holding the data
+    async fn call_data_node(&self, node: NodeID, req: KvRequest) -> WSResult<KvResponse> {
+        if node == self.view.p2p().nodes_config.this_node() {
+            let resp = self.view.kv_store_engine().apply_kv_request(req);
+            self.view.kv_store_engine().flush();
+            return Ok(resp);
+        }
+        let mut resps = self
+            .rpc_caller_kv
+            .call(
+                self.view.p2p(),
+                node,
+                KvRequests {
+                    app: String::new(),
+                    func: String::new(),
+                    requests: vec![req],
+                    prev_kv_opeid: -1,
+                    txn: false,
+                    workflow_id: 0,
+                    workflow_task: 0,
+                },
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
+        Ok(resps.responses.pop().unwrap())
+    }
+    /// current version of the key on node, 0 if not exist
+    async fn data_version_on_node(&self, node: NodeID, key: &[u8]) -> u64 {
+        self.version_on_node(node, key).await.unwrap_or_else(|err| {
+            tracing::warn!("get version on node {} failed: {:?}", node, err);
+            0
+        })
+    }
+    /// current version of the key on node, 0 if not exist
+    pub async fn version_on_node(&self, node: NodeID, key: &[u8]) -> WSResult<u64> {
+        let req = KvRequest {
+            op: Some(proto::kv::kv_request::Op::Get(
+                proto::kv::kv_request::KvGetRequest {
+                    range: Some(proto::kv::KeyRange {
+                        start: key.to_owned(),
+                        end: vec![],
+                        prefix: false,
+                    }),
+                    revision: 0,
+                },
+            )),
+        };
+        let resp = self.call_data_node(node, req).await?;
+        Ok(resp
+            .common_kvs()
+            .and_then(|kvs| kvs.first())
+            .map_or(0, |kv| kv.version))
+    }
+    /// drop the data of the key on node, after it's deleted or moved to other node
+    async fn drop_data_on_node(&self, node: NodeID, key: Vec<u8>) -> WSResult<()> {
+        let req = KvRequest {
+            op: Some(proto::kv::kv_request::Op::Delete(
+                proto::kv::kv_request::KvDeleteRequest {
+                    range: Some(proto::kv::KeyRange {
+                        start: key,
+                        end: vec![],
+                        prefix: false,
+                    }),
+                },
+            )),
+        };
+        let _ = self.call_data_node(node, req).await?;
+        Ok(())
+    }
+    /// the key moved to master by transaction is written back to the node held it,
+    /// or its stale data there is dropped if the transaction deleted it
+    async fn move_back_to_node(&self, node: NodeID, key: Vec<u8>) {
+        let kv_store_engine = self.view.kv_store_engine();
+        let Some(value) = kv_store_engine.get(KeyTypeKv(&key)) else {
+            // the stale data left is not read as the key has no position
+            if let Err(err) = self.drop_data_on_node(node, key).await {
+                tracing::warn!("drop deleted data on node {} failed: {:?}", node, err);
+            }
+            return;
+        };
+        let ttl = kv_store_engine.kv_ttl_left(&key);
+        let req = KvRequest {
+            op: Some(proto::kv::kv_request::Op::Set(
+                proto::kv::kv_request::KvPutRequest {
+                    kv: Some(proto::kv::KvPair {
+                        key: key.clone(),
+                        value: value.value,
+                        // written as the same version
+                        version: value.version - 1,
+                    }),
+                    ttl,
+                },
+            )),
+        };
+        if let Err(err) = self.call_data_node(node, req).await {
+            tracing::warn!(
+                "move key back to node {} failed, it's held by master: {:?}",
+                node,
+                err
+            );
+            return;
+        }
+        kv_store_engine.del_kv(&key);
+        let mut batch = KvBatch::default();
+        batch.set(KeyTypeKvPosition(&key), &node);
+        batch.set_kv_expire(&key, ttl);
+        kv_store_engine.apply_batch(batch);
+        kv_store_engine.flush();
+    }
+    /// `data_pos` is the node decided to hold the data,
+    /// if not decided, keep the data where it was or where it's produced
     async fn handle_kv_set(
         &self,
         set: proto::kv::kv_request::KvPutRequest,
-        _from: NodeID,
+        from: NodeID,
+        data_pos: Option<NodeID>,
+        triggers: &mut WriteTriggers<'_>,
     ) -> KvResponse {
         tracing::debug!("handle_kv_set:{:?}", set.kv.as_ref().map(|v| &v.key));
 
-        if let Some(kv) = set.kv {
-            self.view
-                .kv_store_engine()
-                .set(KeyTypeKv(&kv.key), &kv.value);
-            self.view.kv_store_engine().set(
-                KeyTypeKvPosition(&kv.key),
-                &self.view.p2p().nodes_config.this_node(),
-            );
+        let Some(mut kv) = set.kv else {
+            return KvResponse::new_common(vec![]);
+        };
+        let nodes_config = &self.view.p2p().nodes_config;
+        let old_pos = self.view.kv_store_engine().get(KeyTypeKvPosition(&kv.key));
+        let pos = data_pos
+            .or(old_pos.filter(|node| nodes_config.is_worker_node(*node)))
+            .or(Some(from).filter(|node| nodes_config.is_worker_node(*node)))
+            .unwrap_or_else(|| nodes_config.this_node());
+        let key = kv.key.clone();
+        // keep the version increasing when the key moves to other node
+        if let Some(old_pos) = old_pos.filter(|old_pos| *old_pos != pos) {
+            kv.version = kv.version.max(self.data_version_on_node(old_pos, &key).await);
+        }
+        let ttl = set.ttl;
+        let req = KvRequest {
+            op: Some(proto::kv::kv_request::Op::Set(
+                proto::kv::kv_request::KvPutRequest { kv: Some(kv), ttl },
+            )),
+        };
+        if let Err(err) = self.queue_unconfirmed_triggers(triggers, &key, pos).await {
+            tracing::error!("queue triggers of kv set failed: {:?}", err);
+            return KvResponse::new_common(vec![])
+                .with_err(format!("queue triggers failed: {:?}", err));
+        }
+        let resp = match self.call_data_node(pos, req).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                // the unconfirmed triggers are resolved when they're dispatched again
+                tracing::error!("kv set to node {} failed: {:?}", pos, err);
+                return KvResponse::new_common(vec![])
+                    .with_err(format!("set to node {} failed: {:?}", pos, err));
+            }
+        };
+        let mut batch = KvBatch::default();
+        batch.set(KeyTypeKvPosition(&key), &pos);
+        // the directory record expires with the data
+        batch.set_kv_expire(&key, ttl);
+        self.confirm_triggers(triggers, &mut batch, written_revision(&resp));
+        let kv_store_engine = self.view.kv_store_engine();
+        kv_store_engine.apply_batch(batch);
+        kv_store_engine.flush();
 
-            self.view.kv_store_engine().flush();
+        if let Some(old_pos) = old_pos.filter(|old_pos| *old_pos != pos) {
+            // the stale data left is not read as the key's held by the new node
+            if let Err(err) = self.drop_data_on_node(old_pos, key).await {
+                tracing::warn!("drop stale data on node {} failed: {:?}", old_pos, err);
+            }
         }
 
-        KvResponse::new_common(vec![])
+        resp
+    }
+    /// positions of keys in range, grouped by node
+    fn kv_range_positions(
+        &self,
+        range: &proto::kv::KeyRange,
+    ) -> BTreeMap<NodeID, BTreeSet<Vec<u8>>> {
+        let mut node_keys: BTreeMap<NodeID, BTreeSet<Vec<u8>>> = BTreeMap::new();
+        for (key, node) in self.view.kv_store_engine().scan(range, KeyTypeKvPosition) {
+            let _ = node_keys.entry(node).or_default().insert(key);
+        }
+        node_keys
+    }
+    /// each node holding keys in range scans its own shard
+    async fn handle_kv_range_get(&self, get: proto::kv::kv_request::KvGetRequest) -> KvResponse {
+        let node_keys = self.kv_range_positions(get.range.as_ref().unwrap());
+        let mut kvs = vec![];
+        for (node, keys) in node_keys {
+            let req = KvRequest {
+                op: Some(proto::kv::kv_request::Op::Get(get.clone())),
+            };
+            match self.call_data_node(node, req).await {
+                Ok(resp) => {
+                    // stale data not recorded by directory is ignored
+                    kvs.extend(
+                        resp.common_kvs()
+                            .unwrap()
+                            .iter()
+                            .filter(|kv| keys.contains(&kv.key))
+                            .cloned(),
+                    );
+                }
+                Err(err) => {
+                    tracing::error!("kv range get from node {} failed: {:?}", node, err);
+                    // partial results of the range are not returned
+                    return KvResponse::new_common(vec![])
+                        .with_err(format!("range get from node {} failed: {:?}", node, err));
+                }
+            }
+        }
+        kvs.sort_by(|a, b| a.key.cmp(&b.key));
+        KvResponse::new_common(kvs)
+    }
+    async fn handle_kv_range_delete(
+        &self,
+        delete: proto::kv::kv_request::KvDeleteRequest,
+        source: (&str, &str),
+        triggers: &mut WriteTriggers<'_>,
+    ) -> KvResponse {
+        let node_keys = self.kv_range_positions(delete.range.as_ref().unwrap());
+        let _key_guard = self
+            .key_locks
+            .lock(node_keys.values().flatten().cloned())
+            .await;
+        // keys on other nodes are still deleted, the failure is reported
+        let mut error = String::new();
+        for (node, keys) in node_keys {
+            let mut queued = Ok(());
+            for key in &keys {
+                queued = self
+                    .queue_delete_triggers(triggers, key, node, source)
+                    .await;
+                if queued.is_err() {
+                    break;
+                }
+            }
+            if let Err(err) = queued {
+                tracing::error!("queue triggers of kv range delete failed: {:?}", err);
+                error = format!("queue triggers failed: {:?}", err);
+                // the queued ones are dropped when they're resolved
+                triggers.unconfirmed.clear();
+                continue;
+            }
+            let req = KvRequest {
+                op: Some(proto::kv::kv_request::Op::Delete(delete.clone())),
+            };
+            if let Err(err) = self.call_data_node(node, req).await {
+                tracing::error!("kv range delete on node {} failed: {:?}", node, err);
+                error = format!("range delete on node {} failed: {:?}", node, err);
+                // the unconfirmed triggers are resolved when they're dispatched again
+                triggers.unconfirmed.clear();
+                continue;
+            }
+            let mut batch = KvBatch::default();
+            for key in keys {
+                batch.del_kv_position(&key);
+                self.view
+                    .kv_watch()
+                    .record_change(key, KvChangeKind::Delete, 0);
+            }
+            self.confirm_triggers(triggers, &mut batch, Some(0));
+            let kv_store_engine = self.view.kv_store_engine();
+            kv_store_engine.apply_batch(batch);
+            kv_store_engine.flush();
+        }
+        KvResponse::new_common(vec![]).with_err(error)
     }
     async fn handle_kv_get(&self, get: proto::kv::kv_request::KvGetRequest) -> KvResponse {
         tracing::debug!("handle_kv_get:{:?}", get);
-        let mut kvs = vec![];
-        if let Some(v) = self
+        if !get.range.as_ref().unwrap().is_single_key() {
+            return self.handle_kv_range_get(get).await;
+        }
+        let key = &get.range.as_ref().unwrap().start;
+        let pos = self
             .view
             .kv_store_engine()
-            .get(KeyTypeKv(&get.range.as_ref().unwrap().start))
-        {
-            kvs.push(proto::kv::KvPair {
-                key: get.range.unwrap().start,
-                value: v.clone(),
-            });
+            .get(KeyTypeKvPosition(key))
+            .unwrap_or_else(|| self.view.p2p().nodes_config.this_node());
+        let req = KvRequest {
+            op: Some(proto::kv::kv_request::Op::Get(get)),
+        };
+        match self.call_data_node(pos, req).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                tracing::error!("kv get from node {} failed: {:?}", pos, err);
+                KvResponse::new_common(vec![])
+                    .with_err(format!("get from node {} failed: {:?}", pos, err))
+            }
         }
-        KvResponse::new_common(kvs)
     }
-    async fn handle_kv_delete(&self, delete: proto::kv::kv_request::KvDeleteRequest) -> KvResponse {
+    /// the fns listening the deletion of existing keys are queued before the data's dropped,
+    /// `source` is the app fn deleting the keys
+    async fn handle_kv_delete(
+        &self,
+        delete: proto::kv::kv_request::KvDeleteRequest,
+        source: (&str, &str),
+        triggers: &mut WriteTriggers<'_>,
+    ) -> KvResponse {
         tracing::debug!("handle_kv_delete:{:?}", delete);
-        // let res = self
-        //     .kv_map
-        //     .write()
-        //     .remove(&delete.range.as_ref().unwrap().start);
-        self.view
-            .kv_store_engine()
-            .del(KeyTypeKvPosition(&delete.range.as_ref().unwrap().start));
-        self.view
-            .kv_store_engine()
-            .del(KeyTypeKv(&delete.range.as_ref().unwrap().start));
-        self.view.kv_store_engine().flush();
-        // let mut kvs = vec![];
-        // if let Some(v) = res {
-        //     kvs.push(proto::kv::KvPair {
-        //         key: delete.range.unwrap().start,
-        //         value: v,
-        //     });
-        // }
+        if !delete.range.as_ref().unwrap().is_single_key() {
+            return self.handle_kv_range_delete(delete, source, triggers).await;
+        }
+        let key = delete.range.as_ref().unwrap().start.clone();
+        let old_pos = self.view.kv_store_engine().get(KeyTypeKvPosition(&key));
+        let pos = old_pos.unwrap_or_else(|| self.view.p2p().nodes_config.this_node());
+        if old_pos.is_some() {
+            if let Err(err) = self
+                .queue_delete_triggers(triggers, &key, pos, source)
+                .await
+            {
+                tracing::error!("queue triggers of kv delete failed: {:?}", err);
+                return KvResponse::new_common(vec![])
+                    .with_err(format!("queue triggers failed: {:?}", err));
+            }
+        }
+        if let Err(err) = self.drop_data_on_node(pos, key.clone()).await {
+            // the key is kept, the unconfirmed triggers are resolved when they're dispatched again
+            tracing::error!("kv delete on node {} failed: {:?}", pos, err);
+            return KvResponse::new_common(vec![])
+                .with_err(format!("delete on node {} failed: {:?}", pos, err));
+        }
+        let mut batch = KvBatch::default();
+        batch.del_kv_position(&key);
+        self.confirm_triggers(triggers, &mut batch, Some(0));
+        let kv_store_engine = self.view.kv_store_engine();
+        kv_store_engine.apply_batch(batch);
+        kv_store_engine.flush();
         KvResponse::new_common(vec![])
     }
+    /// queue the fns listening the deletion of the key held by `data_node` as unconfirmed,
+    /// they're confirmed with the others of `triggers` after the data's dropped
+    async fn queue_delete_triggers(
+        &self,
+        triggers: &mut WriteTriggers<'_>,
+        key: &[u8],
+        data_node: NodeID,
+        source: (&str, &str),
+    ) -> WSResult<()> {
+        let Some((trigger, targets)) = self.delete_event(key, Some(source)).await else {
+            return Ok(());
+        };
+        let mut key_triggers = WriteTriggers {
+            kv_opeid: &mut *triggers.kv_opeid,
+            workflow: triggers.workflow,
+            event: Some(&trigger),
+            targets: &targets,
+            unconfirmed: vec![],
+            fired: vec![],
+        };
+        self.queue_unconfirmed_triggers(&mut key_triggers, key, data_node)
+            .await?;
+        triggers.unconfirmed.append(&mut key_triggers.unconfirmed);
+        Ok(())
+    }
+    /// directory service, look up the positions of keys or migrate them to other node
+    async fn handle_kv_position(
+        &self,
+        req: KvPositionReq,
+        responsor: RPCResponsor<KvPositionReq>,
+    ) {
+        tracing::debug!("handle_kv_position:{:?}", req);
+        let _key_guard = if req.migrate_to != 0 {
+            Some(self.key_locks.lock(req.keys.iter().cloned()).await)
+        } else {
+            None
+        };
+        let kv_store_engine = self.view.kv_store_engine();
+        let nodes: Vec<NodeID> = req
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(idx, key)| {
+                let old_pos = kv_store_engine.get(KeyTypeKvPosition(key));
+                if req.migrate_to != 0 {
+                    kv_store_engine.set(KeyTypeKvPosition(key), &req.migrate_to);
+                    kv_store_engine.set_kv_expire(key, req.ttls.get(idx).copied().unwrap_or(0));
+                }
+                old_pos.unwrap_or(0)
+            })
+            .collect();
+        kv_store_engine.flush();
+
+        // the keys are held by the new node anyway, the stale data left is reported
+        let mut error = String::new();
+        if req.migrate_to != 0 {
+            for (key, &old_pos) in req.keys.into_iter().zip(nodes.iter()) {
+                if old_pos != 0 && old_pos != req.migrate_to {
+                    if let Err(err) = self.drop_data_on_node(old_pos, key.clone()).await {
+                        tracing::error!("drop stale data on node {} failed: {:?}", old_pos, err);
+                        error = format!("drop stale data on node {} failed: {:?}", old_pos, err);
+                    }
+                }
+                // written by the spec node, version is unknown here
+                self.view
+                    .kv_watch()
+                    .record_change(key, KvChangeKind::Set, 0);
+            }
+        }
+
+        if let Err(err) = responsor.send_resp(KvPositionResp { nodes, error }).await {
+            tracing::error!("handle kv position error:{}", err);
+        }
+    }
     async fn handle_kv_lock(
         &self,
         lock: proto::kv::kv_request::KvLockRequest,
@@ -296,3 +1258,70 @@ impl MasterKv {
         }
     }
 }
+
+/// key changed by set, cas, add or single key delete,
+/// range delete is recorded per key when executed
+fn kv_change_of(req: &KvRequest) -> Option<(Vec<u8>, KvChangeKind)> {
+    if let Some(key) = fn_event::kv_set_key(req) {
+        return Some((key.to_owned(), KvChangeKind::Set));
+    }
+    match req.op.as_ref().unwrap() {
+        proto::kv::kv_request::Op::Delete(delete) => {
+            let range = delete.range.as_ref().unwrap();
+            range
+                .is_single_key()
+                .then(|| (range.start.clone(), KvChangeKind::Delete))
+        }
+        _ => None,
+    }
+}
+
+/// the single key accessed in transaction
+fn txn_key(req: &KvRequest) -> Option<&[u8]> {
+    match req.op.as_ref().unwrap() {
+        proto::kv::kv_request::Op::Set(set) => set.kv.as_ref().map(|kv| kv.key.as_slice()),
+        proto::kv::kv_request::Op::Get(get) => get.range.as_ref().map(|r| r.start.as_slice()),
+        proto::kv::kv_request::Op::Delete(delete) => {
+            delete.range.as_ref().map(|r| r.start.as_slice())
+        }
+        proto::kv::kv_request::Op::Guard(guard) => Some(&guard.key),
+        _ => None,
+    }
+}
+
+/// version of the value written by set, cas or add, None if nothing written
+fn written_revision(resp: &KvResponse) -> Option<u64> {
+    if let Some((succ, kvs)) = resp.atomic_res() {
+        return kvs.first().filter(|_| succ).map(|kv| kv.version);
+    }
+    resp.common_kvs()
+        .and_then(|kvs| kvs.first())
+        .map(|kv| kv.version)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_key_locks() {
+        let locks = KeyLocks::default();
+        let a = locks.lock([b"a".to_vec()]).await;
+        // keys not locked are not blocked
+        drop(locks.lock([b"b".to_vec(), b"b".to_vec()]).await);
+        let waiting = locks.lock([b"b".to_vec(), b"a".to_vec()]);
+        tokio::pin!(waiting);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), waiting.as_mut())
+                .await
+                .is_err()
+        );
+        // none of the keys is held while waiting
+        drop(locks.lock([b"b".to_vec()]).await);
+        drop(a);
+        let both = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap();
+        assert_eq!(both.keys, vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+}
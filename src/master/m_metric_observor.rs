@@ -9,10 +9,14 @@ use crate::{
     util::JoinHandleWrapper,
 };
 use async_trait::async_trait;
+use crossbeam_skiplist::SkipMap;
 use prometheus_client::registry::Registry;
+use std::time::{Duration, Instant};
 use ws_derive::LogicalModule;
 
-use self::prometheus::{Metrics, RscLabels, RscType};
+use self::prometheus::{
+    ColdStartLabels, Metrics, ModuleSource, PoolLabels, PoolState, RscLabels, RscType,
+};
 
 // pub struct NodeRscMetric {
 //     used_cpu: f64,
@@ -28,6 +32,7 @@ pub mod prometheus {
     use prometheus_client::metrics::counter::Counter;
     use prometheus_client::metrics::family::Family;
     use prometheus_client::metrics::gauge::Gauge;
+    use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
     use prometheus_client::registry::Registry;
 
     use crate::sys::NodeID;
@@ -63,9 +68,47 @@ pub mod prometheus {
         MemUsed,
     }
 
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+    pub struct ColdStartLabels {
+        pub node_id: NodeID,
+        pub app: String,
+        pub module: ModuleSource,
+    }
+
+    /// where the module of the new instance came from
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+    pub enum ModuleSource {
+        Cached,
+        Wasm,
+        Aot,
+    }
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+    pub struct PoolLabels {
+        pub node_id: NodeID,
+        pub app: String,
+        pub state: PoolState,
+    }
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+    pub enum PoolState {
+        Idle,
+        Busy,
+        Target,
+    }
+
     pub struct Metrics {
         pub requests: Family<RequestLabels, Counter>,
         pub rscs: Family<RscLabels, Gauge<f64, AtomicU64>>,
+        /// seconds of creating instances on workers
+        pub cold_starts: Family<ColdStartLabels, Histogram, fn() -> Histogram>,
+        /// instances of apps on workers
+        pub pools: Family<PoolLabels, Gauge>,
+    }
+
+    /// 1ms to about 16s
+    fn cold_start_histogram() -> Histogram {
+        Histogram::new(exponential_buckets(0.001, 2.0, 15))
     }
 
     pub fn new_registry_and_metrics() -> (Metrics, Registry) {
@@ -73,6 +116,8 @@ pub mod prometheus {
         let metrics = Metrics {
             requests: Family::default(),
             rscs: Family::default(),
+            cold_starts: Family::new_with_constructor(cold_start_histogram),
+            pools: Family::default(),
         };
         registry.register(
             "requests",
@@ -80,12 +125,37 @@ pub mod prometheus {
             metrics.requests.clone(),
         );
         registry.register("rscs", "Resource usage record", metrics.rscs.clone());
+        registry.register(
+            "cold_start_seconds",
+            "Latency of creating fn instances",
+            metrics.cold_starts.clone(),
+        );
+        registry.register(
+            "instance_pool",
+            "Instances of apps pooled or running on workers",
+            metrics.pools.clone(),
+        );
         (metrics, registry)
     }
 }
 
 pub struct NodeFnCacheMetric();
 
+/// metric of a node is reported each second, a node silent for several reports may be down
+const RSC_METRIC_STALE_AFTER: Duration = Duration::from_secs(5);
+
+pub struct ReceivedRscMetric {
+    pub metric: proto::metric::RscMetric,
+    pub received: Instant,
+}
+
+impl ReceivedRscMetric {
+    /// None if the metric is too old to tell the load of the node
+    pub fn fresh(&self) -> Option<&proto::metric::RscMetric> {
+        (self.received.elapsed() < RSC_METRIC_STALE_AFTER).then_some(&self.metric)
+    }
+}
+
 logical_module_view_impl!(MetricObservorView);
 logical_module_view_impl!(MetricObservorView, p2p, P2PModule);
 logical_module_view_impl!(MetricObservorView, metric_observor, Option<MetricObservor>);
@@ -94,7 +164,7 @@ logical_module_view_impl!(MetricObservorView, metric_observor, Option<MetricObse
 pub struct MetricObservor {
     pub registry: Registry,
     metrics: Metrics,
-    // node_rsc_metric: SkipMap<NodeID, proto::metric::RscMetric>,
+    node_rsc_metric: SkipMap<NodeID, ReceivedRscMetric>,
     view: MetricObservorView,
     msg_handler: MsgHandler<proto::metric::RscMetric>,
 }
@@ -109,7 +179,7 @@ impl LogicalModule for MetricObservor {
         Self {
             registry,
             metrics,
-
+            node_rsc_metric: SkipMap::new(),
             view: MetricObservorView::new(args.logical_modules_ref.clone()),
             msg_handler: MsgHandler::default(),
         }
@@ -130,8 +200,36 @@ impl LogicalModule for MetricObservor {
 }
 
 impl MetricObservor {
-    fn insert_node_rsc_metric(&self, nid: NodeID, msg: proto::metric::RscMetric) {
-        // let _ = self.node_rsc_metric.insert(nid, msg);
+    /// latest resource metric reported by the node, None if never reported or stale
+    pub fn get_node_rsc_metric(&self, nid: NodeID) -> Option<proto::metric::RscMetric> {
+        self.node_rsc_metric
+            .get(&nid)
+            .and_then(|v| v.value().fresh().cloned())
+    }
+    fn insert_node_rsc_metric(&self, nid: NodeID, mut msg: proto::metric::RscMetric) {
+        for cold_start in std::mem::take(&mut msg.cold_starts) {
+            let module = match cold_start.module() {
+                proto::metric::ModuleSource::Cached => ModuleSource::Cached,
+                proto::metric::ModuleSource::Wasm => ModuleSource::Wasm,
+                proto::metric::ModuleSource::Aot => ModuleSource::Aot,
+            };
+            self.metrics
+                .cold_starts
+                .get_or_create(&ColdStartLabels {
+                    node_id: nid,
+                    app: cold_start.app,
+                    module,
+                })
+                .observe(cold_start.seconds as f64);
+        }
+        self.update_pools(nid, &msg.pools);
+        let _ = self.node_rsc_metric.insert(
+            nid,
+            ReceivedRscMetric {
+                metric: msg.clone(),
+                received: Instant::now(),
+            },
+        );
         let _ = self
             .metrics
             .rscs
@@ -165,4 +263,40 @@ impl MetricObservor {
             })
             .set(msg.mem_used as f64);
     }
+    fn update_pools(&self, nid: NodeID, pools: &[proto::metric::PoolOccupancy]) {
+        let states = [PoolState::Idle, PoolState::Busy, PoolState::Target];
+        // apps no longer pooled on the node
+        if let Some(last) = self.node_rsc_metric.get(&nid) {
+            for old in &last.value().metric.pools {
+                if pools.iter().any(|pool| pool.app == old.app) {
+                    continue;
+                }
+                for state in states.clone() {
+                    let _ = self.metrics.pools.remove(&PoolLabels {
+                        node_id: nid,
+                        app: old.app.clone(),
+                        state,
+                    });
+                }
+            }
+        }
+        for pool in pools {
+            for (state, count) in
+                states
+                    .clone()
+                    .into_iter()
+                    .zip([pool.idle, pool.busy, pool.target])
+            {
+                let _ = self
+                    .metrics
+                    .pools
+                    .get_or_create(&PoolLabels {
+                        node_id: nid,
+                        app: pool.app.clone(),
+                        state,
+                    })
+                    .set(count as i64);
+            }
+        }
+    }
 }
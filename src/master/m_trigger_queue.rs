@@ -0,0 +1,400 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use ws_derive::LogicalModule;
+
+use crate::{
+    general::{
+        m_appmeta_manager::{fn_event, AppMetaManager, KvEventKind, FN_CALL_TIMEOUT_MARGIN},
+        m_kv_store_engine::{make_raw_key, KeyType, KvBatch, KvStoreEngine},
+        network::{
+            m_p2p::{P2PModule, RPCHandler},
+            proto::{
+                self,
+                sche::{distribute_task_req::Trigger, DistributeTaskReq, TriggerAckResp},
+            },
+        },
+    },
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
+    util::{now_ms, JoinHandleWrapper},
+};
+
+use super::{
+    m_master::Master,
+    m_master_kv::MasterKv,
+    m_workflow::{WorkflowCtx, Workflows},
+};
+
+logical_module_view_impl!(TriggerQueueView);
+logical_module_view_impl!(TriggerQueueView, p2p, P2PModule);
+logical_module_view_impl!(TriggerQueueView, master, Option<Master>);
+logical_module_view_impl!(TriggerQueueView, master_kv, Option<MasterKv>);
+logical_module_view_impl!(TriggerQueueView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(TriggerQueueView, appmeta_manager, AppMetaManager);
+logical_module_view_impl!(TriggerQueueView, trigger_queue, Option<TriggerQueue>);
+logical_module_view_impl!(TriggerQueueView, workflows, Option<Workflows>);
+
+/// wait for workers to connect before replaying the queue
+const REPLAY_DELAY: Duration = Duration::from_secs(5);
+
+/// how often the queue is checked for triggers not acked in time
+const REDISPATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// trigger dispatched to a worker and not acked yet, dispatched again if the fn doesn't finish
+/// in its timeout or master restarts before the ack
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedTrigger {
+    /// DistributeTaskReq encoded by prost, with the queue id
+    pub task: Vec<u8>,
+    /// worker the trigger is scheduled to first
+    pub node: NodeID,
+    /// unix time in ms it's queued or dispatched again
+    pub enqueue_time: u64,
+    /// set until the write firing the trigger is confirmed by the data node
+    pub unconfirmed: Option<UnconfirmedWrite>,
+}
+
+/// write sent to the data node without known result, it fired the trigger
+/// if the version of the key there has grown since, or dropped for delete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnconfirmedWrite {
+    pub key: Vec<u8>,
+    pub data_node: NodeID,
+    /// version of the key on the data node before the write, 0 if not exist
+    pub prev_version: u64,
+}
+
+/// trigger waiting for the ack of its fn, by queue id
+pub struct KeyTypeTriggerQueue(pub u64);
+
+impl KeyType for KeyTypeTriggerQueue {
+    type Value = QueuedTrigger;
+    fn id(&self) -> u8 {
+        11
+    }
+    // ordered by queue id
+    fn make_key(&self) -> Vec<u8> {
+        make_raw_key(self.id(), &self.0.to_be_bytes())
+    }
+}
+
+impl Serialize for KeyTypeTriggerQueue {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+/// triggers are queued durably before they're scheduled and removed when workers ack them
+/// after running their fns. the ones left when master went down are replayed on start, and
+/// the ones not acked within the fn timeout are dispatched again, so fns run at least once.
+/// triggers fired by a write on a data node are queued before the write is sent
+#[derive(LogicalModule)]
+pub struct TriggerQueue {
+    rpc_handler_trigger_ack: RPCHandler<proto::sche::TriggerAckReq>,
+    next_id: AtomicU64,
+    view: TriggerQueueView,
+}
+
+#[async_trait]
+impl LogicalModule for TriggerQueue {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            rpc_handler_trigger_ack: RPCHandler::default(),
+            next_id: AtomicU64::new(1),
+            view: TriggerQueueView::new(args.logical_modules_ref.clone()),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let unacked = self.queued();
+        if let Some((last, _)) = unacked.last() {
+            self.next_id.store(last + 1, Ordering::Relaxed);
+        }
+        let view = self.view.clone();
+        self.rpc_handler_trigger_ack
+            .regist(self.view.p2p(), move |responser, r| {
+                view.trigger_queue().remove(r.queue_id);
+                view.workflows().finish_task(
+                    WorkflowCtx {
+                        workflow_id: r.workflow_id,
+                        task: r.queue_id,
+                    },
+                    r.output,
+                    r.error,
+                );
+                let _ = tokio::spawn(async move {
+                    if let Err(err) = responser.send_resp(TriggerAckResp {}).await {
+                        tracing::error!("send trigger ack resp failed with err: {}", err);
+                    }
+                });
+                Ok(())
+            });
+        let view = self.view.clone();
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            tokio::time::sleep(REPLAY_DELAY).await;
+            if !unacked.is_empty() {
+                tracing::info!("replay {} unacked triggers", unacked.len());
+                view.trigger_queue().dispatch_again(unacked);
+            }
+            let mut interval = tokio::time::interval(REDISPATCH_INTERVAL);
+            loop {
+                let _ = interval.tick().await;
+                view.trigger_queue().redispatch_stale().await;
+            }
+        }))])
+    }
+}
+
+impl TriggerQueue {
+    /// queue the task to be scheduled to `node` in `batch`, its queue id is set
+    pub fn enqueue(&self, batch: &mut KvBatch, task: &mut DistributeTaskReq, node: NodeID) {
+        self.reserve(task);
+        self.put(batch, task, node, None);
+    }
+    /// set the queue id of the task queued later by `confirm`,
+    /// for the triggers queued in the transaction firing them
+    pub fn reserve(&self, task: &mut DistributeTaskReq) {
+        task.queue_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    }
+    /// queue the task fired by a write before the write is sent to the data node,
+    /// it's confirmed after the write, or resolved when dispatched again if its result is lost
+    pub fn enqueue_unconfirmed(
+        &self,
+        batch: &mut KvBatch,
+        task: &mut DistributeTaskReq,
+        node: NodeID,
+        write: UnconfirmedWrite,
+    ) {
+        self.reserve(task);
+        self.put(batch, task, node, Some(write));
+    }
+    /// the write firing the task wrote version `revision`, 0 for delete, the task is removed
+    /// instead if it doesn't fire on the write, returns whether it's fired
+    pub fn confirm(
+        &self,
+        batch: &mut KvBatch,
+        task: &mut DistributeTaskReq,
+        node: NodeID,
+        revision: u64,
+    ) -> bool {
+        if !fire_at_revision(task, revision) {
+            batch.del(KeyTypeTriggerQueue(task.queue_id));
+            return false;
+        }
+        self.put(batch, task, node, None);
+        true
+    }
+    fn put(
+        &self,
+        batch: &mut KvBatch,
+        task: &DistributeTaskReq,
+        node: NodeID,
+        unconfirmed: Option<UnconfirmedWrite>,
+    ) {
+        batch.set(
+            KeyTypeTriggerQueue(task.queue_id),
+            &QueuedTrigger {
+                task: task.encode_to_vec(),
+                node,
+                enqueue_time: now_ms(),
+                unconfirmed,
+            },
+        );
+    }
+    /// triggers not acked yet in the order of queue id
+    fn queued(&self) -> Vec<(u64, QueuedTrigger)> {
+        self.view
+            .kv_store_engine()
+            .scan_type(KeyTypeTriggerQueue(0))
+            .into_iter()
+            .map(|(id, queued)| (u64::from_be_bytes(id.try_into().unwrap()), queued))
+            .collect()
+    }
+    /// queue the task by a write of its own
+    pub fn enqueue_now(&self, task: &mut DistributeTaskReq, node: NodeID) {
+        let mut batch = KvBatch::default();
+        self.enqueue(&mut batch, task, node);
+        let kv_store_engine = self.view.kv_store_engine();
+        kv_store_engine.apply_batch(batch);
+        kv_store_engine.flush();
+    }
+    /// the trigger is acked or given up, it won't be replayed
+    pub fn remove(&self, queue_id: u64) {
+        self.view
+            .kv_store_engine()
+            .del(KeyTypeTriggerQueue(queue_id));
+    }
+    /// dispatch the triggers not acked within the timeout of their fns again
+    async fn redispatch_stale(&self) {
+        let queued = self.queued();
+        if queued.is_empty() {
+            return;
+        }
+        let now = now_ms();
+        let stale: Vec<_> = {
+            let metas = self.view.appmeta_manager().meta.read().await;
+            queued
+                .into_iter()
+                .filter(|(_, queued)| {
+                    let Ok(task) = DistributeTaskReq::decode(queued.task.as_slice()) else {
+                        return true;
+                    };
+                    let timeout = metas.fn_timeout(&task.app, &task.func) + FN_CALL_TIMEOUT_MARGIN;
+                    queued.enqueue_time + timeout.as_millis() as u64 <= now
+                })
+                .collect()
+        };
+        if !stale.is_empty() {
+            tracing::warn!("dispatch {} triggers not acked in time again", stale.len());
+            self.dispatch_again(stale);
+        }
+    }
+    /// schedule the queued triggers again, the unconfirmed ones are resolved first,
+    /// their enqueue time is renewed so they're not dispatched again until the next timeout
+    fn dispatch_again(&self, queued: Vec<(u64, QueuedTrigger)>) {
+        let kv_store_engine = self.view.kv_store_engine();
+        let mut renewed = vec![];
+        for (queue_id, _) in queued {
+            // the trigger may be acked meanwhile, it's renewed only if it's still queued
+            let (_, queued) = kv_store_engine.update(KeyTypeTriggerQueue(queue_id), |queued| {
+                queued.map(|queued| QueuedTrigger {
+                    enqueue_time: now_ms(),
+                    ..queued
+                })
+            });
+            let Some(queued) = queued else {
+                continue;
+            };
+            match DistributeTaskReq::decode(queued.task.as_slice()) {
+                Ok(task) => renewed.push((task, queued)),
+                Err(err) => {
+                    tracing::warn!("queued trigger {} is broken: {}", queue_id, err);
+                    self.remove(queue_id);
+                }
+            }
+        }
+        kv_store_engine.flush();
+        for (task, queued) in renewed {
+            let view = self.view.clone();
+            let _ = tokio::spawn(async move {
+                match queued.unconfirmed {
+                    Some(write) => view.trigger_queue().resolve(task, queued.node, write).await,
+                    None => view.master().schedule_one_trigger(task, queued.node).await,
+                }
+            });
+        }
+    }
+    /// schedule the trigger if its write reached the data node and fired it, otherwise drop it,
+    /// it's left to be dispatched again if the data node can't tell
+    async fn resolve(&self, mut task: DistributeTaskReq, node: NodeID, write: UnconfirmedWrite) {
+        let version = match self
+            .view
+            .master_kv()
+            .version_on_node(write.data_node, &write.key)
+            .await
+        {
+            Ok(version) => version,
+            Err(err) => {
+                tracing::warn!(
+                    "resolve queued trigger {} on node {} failed: {:?}",
+                    task.queue_id,
+                    write.data_node,
+                    err
+                );
+                return;
+            }
+        };
+        let mut batch = KvBatch::default();
+        let fired = if write_happened(&task, write.prev_version, version) {
+            self.confirm(&mut batch, &mut task, node, version)
+        } else {
+            batch.del(KeyTypeTriggerQueue(task.queue_id));
+            false
+        };
+        let kv_store_engine = self.view.kv_store_engine();
+        kv_store_engine.apply_batch(batch);
+        kv_store_engine.flush();
+        if fired {
+            self.view.master().schedule_one_trigger(task, node).await;
+        }
+    }
+}
+
+/// whether the write firing the task reached the data node, the key's version there was
+/// `prev_version` before the write and is `version` now. a set grows the version, while a delete
+/// resets it as versions restart from 1 after the key's deleted
+fn write_happened(task: &DistributeTaskReq, prev_version: u64, version: u64) -> bool {
+    match task.trigger {
+        Some(Trigger::KvDelete(_)) => version < prev_version,
+        _ => version > prev_version,
+    }
+}
+
+/// set the version written to the kv set trigger of the task,
+/// false if the trigger doesn't fire on the write
+fn fire_at_revision(task: &mut DistributeTaskReq, revision: u64) -> bool {
+    let (kind, set) = match task.trigger.as_mut() {
+        Some(Trigger::KvSet(set)) => (KvEventKind::Set, set),
+        Some(Trigger::KvNew(set)) => (KvEventKind::New, set),
+        Some(Trigger::KvChange(set)) => (KvEventKind::Change, set),
+        _ => return true,
+    };
+    set.revision = revision;
+    kind.fires_on(fn_event::set_happened(revision))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::general::network::proto::sche::distribute_task_req::{
+        TriggerKvDelete, TriggerKvSet,
+    };
+
+    #[test]
+    fn test_fire_at_revision() {
+        let task = |trigger: fn(TriggerKvSet) -> Trigger| DistributeTaskReq {
+            trigger: Some(trigger(TriggerKvSet {
+                key: b"k".to_vec(),
+                opeid: 1,
+                revision: 0,
+            })),
+            ..Default::default()
+        };
+        let mut new = task(Trigger::KvNew);
+        assert!(fire_at_revision(&mut new, 1));
+        assert!(matches!(new.trigger, Some(Trigger::KvNew(ref set)) if set.revision == 1));
+        assert!(!fire_at_revision(&mut task(Trigger::KvNew), 2));
+        assert!(!fire_at_revision(&mut task(Trigger::KvChange), 1));
+        assert!(fire_at_revision(&mut task(Trigger::KvChange), 2));
+        assert!(fire_at_revision(&mut task(Trigger::KvSet), 1));
+    }
+
+    #[test]
+    fn test_write_happened() {
+        let set = DistributeTaskReq {
+            trigger: Some(Trigger::KvSet(TriggerKvSet::default())),
+            ..Default::default()
+        };
+        assert!(write_happened(&set, 0, 1));
+        assert!(write_happened(&set, 3, 4));
+        assert!(!write_happened(&set, 3, 3));
+        let delete = DistributeTaskReq {
+            trigger: Some(Trigger::KvDelete(TriggerKvDelete::default())),
+            ..Default::default()
+        };
+        assert!(write_happened(&delete, 3, 0));
+        // deleted and set again
+        assert!(write_happened(&delete, 3, 1));
+        assert!(!write_happened(&delete, 3, 3));
+        assert!(!write_happened(&delete, 3, 4));
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use ws_derive::LogicalModule;
+
+use crate::{
+    apis::{self, GetWorkflowResp},
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
+    util::{now_ms, JoinHandleWrapper, Retention},
+};
+
+logical_module_view_impl!(WorkflowsView);
+logical_module_view_impl!(WorkflowsView, workflows, Option<Workflows>);
+
+/// workflows are kept in memory, so not for long
+const WORKFLOW_RETENTION: Retention = Retention {
+    finished_ms: 3600 * 1000,
+    unfinished_ms: 24 * 3600 * 1000,
+};
+
+/// longest wait of one get, clients wait longer by getting again
+const MAX_WORKFLOW_WAIT: Duration = Duration::from_secs(60);
+
+/// the workflow and the task in it a fn call belongs to, workflow id 0 if not tracked
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WorkflowCtx {
+    pub workflow_id: u64,
+    /// queue id of the trigger running the fn, 0 for the root call
+    pub task: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkflowTask {
+    /// queue id of the trigger running the fn, 0 for the root call
+    pub task: u64,
+    /// task whose kv operations fired this one, the root is its own parent
+    pub parent: u64,
+    /// fn route of the root call, or app/fn of triggered ones
+    pub target: String,
+    pub node: NodeID,
+    /// unix time in ms
+    pub start_time: u64,
+    /// 0 while running
+    pub end_time: u64,
+    pub output: Vec<u8>,
+    /// empty if succeeded
+    pub error: String,
+}
+
+/// root call and the fns triggered from it
+#[derive(Debug, Clone)]
+pub struct WorkflowRecord {
+    /// async invocation of the root call keeping its output, empty for proxied calls
+    pub invocation_id: String,
+    /// unix time in ms
+    pub start_time: u64,
+    /// 0 while any task is running
+    pub end_time: u64,
+    /// the root call first, then the triggered ones in the order they're fired
+    pub tasks: Vec<WorkflowTask>,
+}
+
+impl WorkflowRecord {
+    fn new(target: String, node: NodeID, invocation_id: String, now: u64) -> Self {
+        Self {
+            invocation_id,
+            start_time: now,
+            end_time: 0,
+            tasks: vec![WorkflowTask {
+                task: 0,
+                parent: 0,
+                target,
+                node,
+                start_time: now,
+                end_time: 0,
+                output: vec![],
+                error: String::new(),
+            }],
+        }
+    }
+    fn add_task(&mut self, parent: u64, task: u64, target: String, node: NodeID, now: u64) {
+        self.tasks.push(WorkflowTask {
+            task,
+            parent,
+            target,
+            node,
+            start_time: now,
+            end_time: 0,
+            output: vec![],
+            error: String::new(),
+        });
+        // a finished workflow is reopened by replaying its dead letter
+        self.end_time = 0;
+    }
+    /// returns whether the whole workflow finished by the task
+    fn finish_task(&mut self, task: u64, output: Vec<u8>, error: String, now: u64) -> bool {
+        let Some(t) = self
+            .tasks
+            .iter_mut()
+            .find(|t| t.task == task && t.end_time == 0)
+        else {
+            return false;
+        };
+        t.end_time = now;
+        t.output = output;
+        t.error = error;
+        if self.tasks.iter().all(|t| t.end_time != 0) {
+            self.end_time = now;
+            return true;
+        }
+        false
+    }
+    fn into_resp(self, workflow_id: u64) -> GetWorkflowResp {
+        let Self {
+            invocation_id,
+            start_time,
+            end_time,
+            tasks,
+        } = self;
+        let succ = tasks.iter().all(|t| t.error.is_empty());
+        let tasks = tasks
+            .into_iter()
+            .map(|t| apis::WorkflowTask {
+                task: t.task,
+                parent: t.parent,
+                target: t.target,
+                node: t.node as u64,
+                start_time: t.start_time,
+                end_time: t.end_time,
+                output: String::from_utf8_lossy(&t.output).into_owned(),
+                error: t.error,
+            })
+            .collect();
+        if end_time == 0 {
+            return GetWorkflowResp::Running {
+                workflow_id,
+                invocation_id,
+                start_time,
+                tasks,
+            };
+        }
+        GetWorkflowResp::Done {
+            workflow_id,
+            invocation_id,
+            succ,
+            start_time,
+            end_time,
+            tasks,
+        }
+    }
+}
+
+/// tracks the fns triggered by the kv operations of a root call until all of them finished,
+/// the workflow id is carried by the fn calls and their kv requests.
+/// only the calls asking for it are tracked, in memory as the tasks finish quickly,
+/// so the workflows are lost when master restarts
+#[derive(LogicalModule)]
+pub struct Workflows {
+    next_id: AtomicU64,
+    records: Mutex<HashMap<u64, WorkflowRecord>>,
+    /// waiters of unfinished workflows
+    waiters: Mutex<HashMap<u64, Arc<Notify>>>,
+    view: WorkflowsView,
+}
+
+#[async_trait]
+impl LogicalModule for Workflows {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            next_id: AtomicU64::new(1),
+            records: Mutex::new(HashMap::new()),
+            waiters: Mutex::new(HashMap::new()),
+            view: WorkflowsView::new(args.logical_modules_ref.clone()),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let view = self.view.clone();
+        Ok(vec![Retention::spawn_gc(move |now| {
+            view.workflows().evict_finished(now)
+        })])
+    }
+}
+
+impl Workflows {
+    /// track the root call of `target` on `node` as a new workflow, returns its id,
+    /// the output of the root call is kept by the async invocation if `invocation_id` isn't empty
+    pub fn begin(&self, target: String, node: NodeID, invocation_id: String) -> u64 {
+        let workflow_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let _ = self.records.lock().insert(
+            workflow_id,
+            WorkflowRecord::new(target, node, invocation_id, now_ms()),
+        );
+        workflow_id
+    }
+    /// the trigger queued as `task` is fired by the fn call of `ctx`
+    pub fn add_task(&self, ctx: WorkflowCtx, task: u64, target: String, node: NodeID) {
+        if let Some(record) = self.records.lock().get_mut(&ctx.workflow_id) {
+            record.add_task(ctx.task, task, target, node, now_ms());
+        }
+    }
+    /// the fn of the task finished with `output`, or failed with `error` if it's not empty
+    pub fn finish_task(&self, ctx: WorkflowCtx, output: Vec<u8>, error: String) {
+        let done = self
+            .records
+            .lock()
+            .get_mut(&ctx.workflow_id)
+            .map_or(false, |record| {
+                record.finish_task(ctx.task, output, error, now_ms())
+            });
+        if done {
+            if let Some(notify) = self.waiters.lock().remove(&ctx.workflow_id) {
+                notify.notify_waiters();
+            }
+        }
+    }
+    /// status of the workflow, waits for it to finish at most `wait`
+    pub async fn get(&self, workflow_id: u64, wait: Duration) -> GetWorkflowResp {
+        let wait = wait.min(MAX_WORKFLOW_WAIT);
+        let notify = self.waiters.lock().entry(workflow_id).or_default().clone();
+        let notified = notify.notified();
+        tokio::pin!(notified);
+        // so that finishing after the record is read still wakes us
+        let _ = notified.as_mut().enable();
+
+        let record = |workflow_id| self.records.lock().get(&workflow_id).cloned();
+        let record = match record(workflow_id) {
+            Some(r) if r.end_time == 0 && !wait.is_zero() => {
+                let _ = tokio::time::timeout(wait, notified).await;
+                record(workflow_id)
+            }
+            r => r,
+        };
+        {
+            let mut waiters = self.waiters.lock();
+            // nobody else is waiting
+            if waiters.get(&workflow_id).map_or(false, |n| {
+                Arc::ptr_eq(n, &notify) && Arc::strong_count(n) == 2
+            }) {
+                let _ = waiters.remove(&workflow_id);
+            }
+        }
+        match record {
+            Some(record) => record.into_resp(workflow_id),
+            None => GetWorkflowResp::NotFound {},
+        }
+    }
+    fn evict_finished(&self, now: u64) {
+        self.records
+            .lock()
+            .retain(|_, r| !WORKFLOW_RETENTION.expired(r.start_time, r.end_time, now));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_workflow_finish() {
+        let mut record = WorkflowRecord::new("word_count/split".to_owned(), 2, String::new(), 100);
+        record.add_task(0, 5, "word_count/handle_one".to_owned(), 2, 101);
+        record.add_task(0, 6, "word_count/handle_one".to_owned(), 3, 101);
+        // root answered before its triggered tasks
+        assert!(!record.finish_task(0, b"ok".to_vec(), String::new(), 102));
+        record.add_task(5, 7, "word_count/merge".to_owned(), 2, 103);
+        assert!(!record.finish_task(5, vec![], String::new(), 104));
+        assert!(!record.finish_task(6, vec![], String::new(), 104));
+        // unknown or finished task
+        assert!(!record.finish_task(9, vec![], String::new(), 105));
+        assert!(!record.finish_task(6, vec![], String::new(), 105));
+        assert!(record.finish_task(7, b"done".to_vec(), String::new(), 106));
+        match record.clone().into_resp(1) {
+            GetWorkflowResp::Done {
+                succ,
+                start_time,
+                end_time,
+                tasks,
+                ..
+            } => {
+                assert!(succ);
+                assert_eq!((start_time, end_time), (100, 106));
+                assert_eq!(tasks.len(), 4);
+                assert_eq!(tasks[3].parent, 5);
+                assert_eq!(tasks[3].output, "done");
+            }
+            resp => panic!("unexpected {:?}", resp.serialize()),
+        }
+
+        // replaying a dead letter reopens it
+        record.add_task(7, 8, "word_count/merge".to_owned(), 3, 107);
+        assert!(matches!(
+            record.clone().into_resp(1),
+            GetWorkflowResp::Running { .. }
+        ));
+        assert!(record.finish_task(8, vec![], "trapped".to_owned(), 108));
+        assert!(matches!(
+            record.into_resp(1),
+            GetWorkflowResp::Done { succ: false, .. }
+        ));
+    }
+}
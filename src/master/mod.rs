@@ -1,4 +1,10 @@
+pub mod m_async_invoker;
+pub mod m_dead_letters;
+pub mod m_fn_timer;
 pub mod m_http_handler;
+pub mod m_kv_watch;
 pub mod m_master;
 pub mod m_master_kv;
 pub mod m_metric_observor;
+pub mod m_trigger_queue;
+pub mod m_workflow;
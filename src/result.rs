@@ -49,6 +49,11 @@ pub enum WsSerialErr {
         index: usize,
         kvs_len: Option<usize>,
     },
+    /// the db of kv store engine is written by a newer version
+    DbFormatUnsupported {
+        version: u32,
+        supported: u32,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -66,6 +71,67 @@ pub enum WsPermissionErr {
     },
 }
 
+#[derive(Debug)]
+pub enum WsFuncError {
+    /// the fn already runs `max` calls on this worker
+    ConcurrencyExceeded {
+        app: String,
+        func: String,
+        max: u32,
+    },
+    /// no worker node to run the fn of `target`, app/fn or route
+    NoWorker {
+        target: String,
+    },
+    /// the worker rejected the triggered call before running it
+    TaskRejected {
+        node: NodeID,
+        app: String,
+        func: String,
+        err: String,
+    },
+    /// the call used up the fuel of the fn, declared or bounded by its timeout
+    FuelExhausted {
+        app: String,
+        func: String,
+        fuel: u64,
+    },
+    /// the call trapped with the linear memory grown to the declared max
+    MemoryExceeded {
+        app: String,
+        func: String,
+        max_pages: u32,
+    },
+    InstanceCreateFailed {
+        app: String,
+        err: String,
+    },
+    /// `max` instances of the app were busy until the call timed out waiting for one
+    InstancesExhausted {
+        app: String,
+        max: u64,
+    },
+    /// the params of fn can't be prepared from the event
+    PrepareParamsFailed {
+        app: String,
+        func: String,
+        msg: String,
+    },
+    /// guest passed invalid pointers or arguments to the host func
+    HostFuncFailed {
+        app: String,
+        func: String,
+        host_func: String,
+        msg: String,
+    },
+    /// the guest trapped
+    RunFailed {
+        app: String,
+        func: String,
+        err: String,
+    },
+}
+
 #[derive(Error, Debug)]
 pub enum WSError {
     #[error("Io error: {0:?}")]
@@ -89,6 +155,9 @@ pub enum WSError {
     #[error("Format error: {0:?}")]
     WsFormatErr(WsFormatErr),
 
+    #[error("Function error: {0:?}")]
+    WsFuncError(WsFuncError),
+
     #[error("Not Implemented")]
     NotImplemented,
 }
@@ -129,6 +198,12 @@ impl From<WsPermissionErr> for WSError {
     }
 }
 
+impl From<WsFuncError> for WSError {
+    fn from(e: WsFuncError) -> Self {
+        WSError::WsFuncError(e)
+    }
+}
+
 pub struct ErrCvt<T>(pub T);
 
 macro_rules! impl_err_convertor {
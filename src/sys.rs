@@ -8,14 +8,16 @@ use crate::{
         network::{http_handler::HttpHandler, m_p2p::P2PModule},
     },
     master::{
-        m_http_handler::MasterHttpHandler, m_master::Master, m_master_kv::MasterKv,
-        m_metric_observor::MetricObservor,
+        m_async_invoker::AsyncInvoker, m_dead_letters::DeadLetters, m_fn_timer::FnTimer,
+        m_http_handler::MasterHttpHandler, m_kv_watch::KvWatch, m_master::Master,
+        m_master_kv::MasterKv, m_metric_observor::MetricObservor, m_trigger_queue::TriggerQueue,
+        m_workflow::Workflows,
     },
     util,
     worker::{
         m_executor::Executor, m_http_handler::WorkerHttpHandler,
         m_instance_manager::InstanceManager, m_kv_user_client::KvUserClient, m_worker::WorkerCore,
-        wasm_host_funcs::set_singleton_modules,
+        m_worker_kv::WorkerKv, wasm_host_funcs::set_singleton_modules,
     },
 };
 use crate::{
@@ -321,14 +323,28 @@ logical_modules!(
     Option<MetricObservor>,
     master,
     Option<Master>,
+    trigger_queue,
+    Option<TriggerQueue>,
     master_kv,
     Option<MasterKv>,
+    kv_watch,
+    Option<KvWatch>,
+    fn_timer,
+    Option<FnTimer>,
+    async_invoker,
+    Option<AsyncInvoker>,
+    dead_letters,
+    Option<DeadLetters>,
+    workflows,
+    Option<Workflows>,
     ////////////////////////////
     // worker
     worker,
     Option<WorkerCore>,
     kv_user_client,
     Option<KvUserClient>,
+    worker_kv,
+    Option<WorkerKv>,
     instance_manager,
     Option<InstanceManager>,
     // kv_storage,
@@ -375,9 +391,16 @@ impl LogicalModules {
             appmeta_manager: AppMetaManager::new(args.clone()),
             metric_observor: None,
             master: None,
+            trigger_queue: None,
             master_kv: None,
+            kv_watch: None,
+            fn_timer: None,
+            async_invoker: None,
+            dead_letters: None,
+            workflows: None,
             worker: None,
             kv_user_client: None,
+            worker_kv: None,
             instance_manager: None,
             executor: None,
             start_cnt: 0,
@@ -386,9 +409,16 @@ impl LogicalModules {
         if is_master {
             logical_modules.metric_observor = Some(MetricObservor::new(args.clone()));
             logical_modules.master = Some(Master::new(args.clone()));
+            logical_modules.trigger_queue = Some(TriggerQueue::new(args.clone()));
             logical_modules.master_kv = Some(MasterKv::new(args.clone()));
+            logical_modules.kv_watch = Some(KvWatch::new(args.clone()));
+            logical_modules.fn_timer = Some(FnTimer::new(args.clone()));
+            logical_modules.async_invoker = Some(AsyncInvoker::new(args.clone()));
+            logical_modules.dead_letters = Some(DeadLetters::new(args.clone()));
+            logical_modules.workflows = Some(Workflows::new(args.clone()));
         } else {
             logical_modules.kv_user_client = Some(KvUserClient::new(args.clone()));
+            logical_modules.worker_kv = Some(WorkerKv::new(args.clone()));
             logical_modules.instance_manager = Some(InstanceManager::new(args.clone()));
             logical_modules.executor = Some(Executor::new(args.clone()));
             logical_modules.worker = Some(WorkerCore::new(args.clone()));
@@ -409,10 +439,18 @@ impl LogicalModules {
         // master
         start_module_opt!(self, sys, metric_observor);
         start_module_opt!(self, sys, master);
+        // before master kv, so that queue ids continue from the unacked ones
+        start_module_opt!(self, sys, trigger_queue);
         start_module_opt!(self, sys, master_kv);
+        start_module_opt!(self, sys, kv_watch);
+        start_module_opt!(self, sys, fn_timer);
+        start_module_opt!(self, sys, async_invoker);
+        start_module_opt!(self, sys, dead_letters);
+        start_module_opt!(self, sys, workflows);
         //worker
         start_module_opt!(self, sys, worker);
         start_module_opt!(self, sys, kv_user_client);
+        start_module_opt!(self, sys, worker_kv);
         start_module_opt!(self, sys, instance_manager);
         start_module_opt!(self, sys, executor);
 
@@ -4,11 +4,53 @@ use std::{
     pin::Pin,
     ptr::NonNull,
     task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 #[cfg(test)]
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
+/// unix time in ms
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// interval of evicting the records out of their retention
+const RETENTION_GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// how long the record of a background job is kept for polling
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// since the job finished
+    pub finished_ms: u64,
+    /// since the job started if it never finishes, some part of it was lost
+    pub unfinished_ms: u64,
+}
+
+impl Retention {
+    /// whether the record of job started at `start_time` and finished at `end_time`,
+    /// 0 if unfinished, should be evicted at `now`
+    pub fn expired(&self, start_time: u64, end_time: u64, now: u64) -> bool {
+        if end_time != 0 {
+            end_time.saturating_add(self.finished_ms) < now
+        } else {
+            start_time.saturating_add(self.unfinished_ms) < now
+        }
+    }
+    /// call `evict` with the current time periodically to evict the records out of retention
+    pub fn spawn_gc(evict: impl Fn(u64) + Send + 'static) -> JoinHandleWrapper {
+        JoinHandleWrapper::from(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(RETENTION_GC_INTERVAL).await;
+                evict(now_ms());
+            }
+        }))
+    }
+}
+
 #[cfg(test)]
 pub fn test_tracing_start() {
     let my_filter = tracing_subscriber::filter::filter_fn(|v| {
@@ -1,30 +1,41 @@
 use core::panic;
-use std::{mem::ManuallyDrop, sync::atomic::AtomicU32};
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicU32, Arc},
+    time::{Duration, Instant},
+};
 
-use super::m_instance_manager::InstanceManager;
+use super::m_instance_manager::{FnCallPermit, InstanceManager};
 use crate::{
     general::{
-        m_appmeta_manager::{AppMetaManager, FnArg},
+        m_appmeta_manager::{AppMetaManager, FnArg, FnLimits, FnMeta, KvEventKind},
         network::{
-            http_handler::ReqId,
-            m_p2p::{P2PModule, RPCHandler, RPCResponsor},
+            http_handler::{HttpReq, ReqId},
+            m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor},
             proto::{
                 self,
-                sche::{distribute_task_req, DistributeTaskResp},
+                sche::{distribute_task_req, CancelInvocationResp, DistributeTaskResp},
             },
         },
     },
     logical_module_view_impl,
-    result::WSResult,
+    result::{WSError, WSResult, WsFuncError},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
     util::JoinHandleWrapper,
     worker::wasm::{WasmInstance, WasmValue},
 };
 use async_trait::async_trait;
-use tokio::{sync::oneshot, task::JoinHandle};
-#[cfg(target_os = "linux")]
-use wasmedge_sdk::r#async::AsyncState;
+use tokio::{
+    sync::{oneshot, Notify},
+    task::JoinHandle,
+};
 use wasmedge_sdk::Vm;
+#[cfg(target_os = "linux")]
+use wasmedge_sdk::{
+    error::{CoreCommonError, CoreError, WasmEdgeError},
+    r#async::AsyncState,
+    WasmEdgeResult,
+};
 use ws_derive::LogicalModule;
 
 pub type SubTaskId = u32;
@@ -43,6 +54,10 @@ logical_module_view_impl!(ExecutorView, executor, Option<Executor>);
 pub struct Executor {
     sub_task_id: AtomicU32,
     rpc_handler_distribute_task: RPCHandler<proto::sche::DistributeTaskReq>,
+    rpc_handler_cancel_invocation: RPCHandler<proto::sche::CancelInvocationReq>,
+    rpc_caller_trigger_ack: RPCCaller<proto::sche::TriggerAckReq>,
+    /// invocation id to the signal interrupting its fn
+    running_invocations: parking_lot::Mutex<HashMap<String, Arc<Notify>>>,
     view: ExecutorView,
 }
 
@@ -85,19 +100,32 @@ impl VmExt for Vm {
 
 #[derive(Clone, Debug)]
 pub enum EventCtx {
-    Http(String),
-    KvSet { key: Vec<u8>, opeid: Option<u32> },
+    Http(HttpReq),
+    /// `kind` tells the fn which kv event fired,
+    /// `revision` is the version written by the set, 0 for delete
+    Kv {
+        kind: KvEventKind,
+        key: Vec<u8>,
+        revision: u64,
+        opeid: Option<u32>,
+    },
+    /// `scheduled_time` of the fire in unix ms
+    Timer { scheduled_time: u64 },
 }
 
 impl EventCtx {
     pub fn take_prev_kv_opeid(&mut self) -> Option<u32> {
         match self {
-            EventCtx::KvSet { opeid, .. } => opeid.take(),
+            EventCtx::Kv { opeid, .. } => opeid.take(),
             _ => None,
         }
     }
-    pub fn conv_to_wasm_params(&self, fn_arg: &FnArg, vm: &WasmInstance) -> Vec<WasmValue> {
-        fn prepare_vec_in_vm(vm: &WasmInstance, v: &[u8]) -> (i32, i32) {
+    pub fn conv_to_wasm_params(
+        &self,
+        fn_arg: &FnArg,
+        vm: &WasmInstance,
+    ) -> Result<Vec<WasmValue>, String> {
+        fn prepare_vec_in_vm(vm: &WasmInstance, v: &[u8]) -> Result<(i32, i32), String> {
             let vm_ins = vm.vm_instance_name();
             let ptr = vm
                 .run_func(
@@ -105,48 +133,175 @@ impl EventCtx {
                     "allocate",
                     vec![WasmValue::from_i32(v.len() as i32)],
                 )
-                .unwrap_or_else(|err| {
-                    panic!("err:{:?} vm instance names:{:?}", err, vm.instance_names())
-                })[0]
+                .map_err(|err| format!("allocate in guest failed: {}", err))?
+                .first()
+                .ok_or("allocate in guest returns nothing")?
                 .to_i32();
-            let mut mem = ManuallyDrop::new(unsafe {
-                Vec::from_raw_parts(
-                    vm.named_module(&vm_ins)
-                        .unwrap()
-                        .memory("memory")
-                        .unwrap()
-                        .data_pointer_mut(ptr as u32, v.len() as u32)
-                        .unwrap(),
-                    v.len(),
-                    v.len(),
-                )
-            });
-            mem.copy_from_slice(v);
-            (ptr, v.len() as i32)
+            let mut memory = vm
+                .named_module(&vm_ins)
+                .and_then(|module| module.memory("memory"))
+                .map_err(|err| format!("guest has no memory: {}", err))?;
+            memory
+                .write(v, ptr as u32)
+                .map_err(|err| format!("allocated {} out of guest memory: {}", ptr, err))?;
+            Ok((ptr, v.len() as i32))
         }
         match (self, fn_arg) {
-            (EventCtx::Http(text), FnArg::HttpText) => {
-                if text.len() == 0 {
-                    return vec![];
+            (EventCtx::Http(req), FnArg::HttpText) => {
+                if req.body.len() == 0 {
+                    return Ok(vec![]);
                 }
-                let (ptr, len) = prepare_vec_in_vm(vm, text.as_bytes());
-                vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)]
+                let (ptr, len) = prepare_vec_in_vm(vm, &req.body)?;
+                Ok(vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)])
+            }
+            (EventCtx::Http(req), FnArg::HttpJson) => {
+                // body is checked to be json before run
+                let mut json = serde_json::to_value(req).map_err(|err| err.to_string())?;
+                json["body"] = serde_json::from_slice(&req.body).unwrap_or_default();
+                let json = serde_json::to_vec(&json).map_err(|err| err.to_string())?;
+                let (ptr, len) = prepare_vec_in_vm(vm, &json)?;
+                Ok(vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)])
+            }
+            (EventCtx::Http(req), FnArg::HttpHeaders) => {
+                let headers = serde_json::to_vec(&req.headers).map_err(|err| err.to_string())?;
+                let (ptr, len) = prepare_vec_in_vm(vm, &headers)?;
+                Ok(vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)])
             }
-            (EventCtx::KvSet { key, .. }, FnArg::KvKey(_)) => {
-                let (ptr, len) = prepare_vec_in_vm(vm, &key);
-                vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)]
+            (EventCtx::Kv { key, .. }, FnArg::KvKey(_)) => {
+                let (ptr, len) = prepare_vec_in_vm(vm, &key)?;
+                Ok(vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)])
             }
-            (e, f) => panic!("not support event ctx and fn arg: {:?} {:?}", e, f),
+            (EventCtx::Timer { scheduled_time }, FnArg::TimerTime) => {
+                Ok(vec![WasmValue::from_i64(*scheduled_time as i64)])
+            }
+            (e, f) => Err(format!("not support event ctx and fn arg: {:?} {:?}", e, f)),
+        }
+    }
+}
+
+/// why the fn is stopped before it returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FnInterrupt {
+    Timeout(Duration),
+    Cancelled,
+}
+
+impl FnInterrupt {
+    pub fn status(&self) -> u16 {
+        match self {
+            FnInterrupt::Timeout(_) => 504,
+            // client closed request
+            FnInterrupt::Cancelled => 499,
+        }
+    }
+    pub fn msg(&self) -> String {
+        match self {
+            FnInterrupt::Timeout(timeout) => format!("fn timeout after {:?}", timeout),
+            FnInterrupt::Cancelled => "fn cancelled".to_owned(),
+        }
+    }
+}
+
+/// written back by fn through `write_result` or `write_http_response`
+#[derive(Debug)]
+pub enum FnResult {
+    Text(String),
+    Http {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    },
+    Interrupted(FnInterrupt),
+    /// rejected or stopped by the limits of fn, or the instance can't be created
+    Err(WSError),
+}
+
+impl FnResult {
+    fn status(status: u16) -> FnResult {
+        FnResult::Http {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+    /// reject the http request before run if the fn can't take it
+    fn check_http_req(fnmeta: &FnMeta, req: &HttpReq) -> Option<FnResult> {
+        let want_json = fnmeta.args.iter().any(|arg| matches!(arg, FnArg::HttpJson));
+        if want_json
+            && !req.body.is_empty()
+            && serde_json::from_slice::<serde_json::Value>(&req.body).is_err()
+        {
+            return Some(FnResult::Http {
+                status: 400,
+                headers: vec![],
+                body: b"request body is not json".to_vec(),
+            });
+        }
+        None
+    }
+}
+
+/// (output, error) of the fn reported to master, answering 4xx or 5xx is failed
+fn fn_result_report(res: Option<FnResult>) -> (Vec<u8>, String) {
+    match res {
+        None => (vec![], String::new()),
+        Some(FnResult::Text(text)) => (text.into_bytes(), String::new()),
+        Some(FnResult::Http { status, body, .. }) if status >= 400 => {
+            (body, format!("fn answers {}", status))
         }
+        Some(FnResult::Http { body, .. }) => (body, String::new()),
+        Some(FnResult::Interrupted(interrupt)) => (vec![], interrupt.msg()),
+        Some(FnResult::Err(err)) => (vec![], err.to_string()),
     }
 }
 
+/// the limit of fn broken by the failed run
+#[cfg(target_os = "linux")]
+fn broken_limit(
+    app: &str,
+    func: &str,
+    fuel: u64,
+    limits: &FnLimits,
+    vm: &WasmInstance,
+    err: &WasmEdgeError,
+) -> Option<WsFuncError> {
+    if let WasmEdgeError::Core(CoreError::Common(CoreCommonError::CostLimitExceeded)) = err {
+        return Some(WsFuncError::FuelExhausted {
+            app: app.to_owned(),
+            func: func.to_owned(),
+            fuel,
+        });
+    }
+    // memory.grow fails at the max pages, and the guest traps when it can't allocate
+    let max_pages = limits.max_memory_pages?;
+    let pages = vm
+        .named_module(vm.vm_instance_name())
+        .and_then(|module| module.memory("memory"))
+        .map(|memory| memory.page())
+        .ok()?;
+    (pages >= max_pages).then(|| WsFuncError::MemoryExceeded {
+        app: app.to_owned(),
+        func: func.to_owned(),
+        max_pages,
+    })
+}
+
 pub struct FunctionCtx {
     pub app: String,
     pub func: String,
     pub req_id: ReqId,
     pub event_ctx: EventCtx,
-    pub res: Option<String>,
+    pub res: Option<FnResult>,
+    /// id of async invocation, to cancel the fn
+    pub invocation_id: Option<String>,
+    /// first invalid call of guest to host funcs, fails the invocation
+    pub host_err: Option<WsFuncError>,
+    /// workflow tracked on master, the fns triggered by kv operations join it, 0 if not tracked
+    pub workflow_id: u64,
+    /// queue id of the trigger running the fn, 0 for the root call of workflow
+    pub workflow_task: u64,
+    /// when the fn is interrupted by its timeout, set when it starts running
+    pub deadline: Option<Instant>,
     /// remote scheduling tasks
     pub sub_waiters: Vec<JoinHandle<()>>, // pub trigger_node: NodeID,
 }
@@ -159,11 +314,15 @@ impl LogicalModule for Executor {
     {
         Self {
             rpc_handler_distribute_task: RPCHandler::default(),
+            rpc_handler_cancel_invocation: RPCHandler::default(),
+            rpc_caller_trigger_ack: RPCCaller::default(),
+            running_invocations: parking_lot::Mutex::new(HashMap::new()),
             view: ExecutorView::new(args.logical_modules_ref.clone()),
             sub_task_id: AtomicU32::new(0),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        self.rpc_caller_trigger_ack.regist(self.view.p2p());
         let view = self.view.clone();
         self.view.executor().rpc_handler_distribute_task.regist(
             self.view.p2p(),
@@ -183,6 +342,17 @@ impl LogicalModule for Executor {
                 Ok(())
             },
         );
+        let view = self.view.clone();
+        self.rpc_handler_cancel_invocation
+            .regist(self.view.p2p(), move |responser, r| {
+                let found = view.executor().cancel_invocation(&r.invocation_id);
+                let _ = tokio::spawn(async move {
+                    if let Err(err) = responser.send_resp(CancelInvocationResp { found }).await {
+                        tracing::error!("send cancel resp failed with err: {}", err);
+                    }
+                });
+                Ok(())
+            });
         // self.view
         //     .p2p()
         //     .regist_rpc::<proto::sche::ScheReq, _>();
@@ -197,6 +367,38 @@ impl Executor {
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         taskid
     }
+    /// interrupt the fn running for the invocation, false if it's not running here
+    pub fn cancel_invocation(&self, invocation_id: &str) -> bool {
+        let Some(cancel) = self.running_invocations.lock().get(invocation_id).cloned() else {
+            return false;
+        };
+        tracing::info!("cancel invocation {}", invocation_id);
+        cancel.notify_one();
+        true
+    }
+    /// the interrupted guest keeps its instance and its slot of the fn concurrency until it
+    /// really stops, at its next host function call or when its fuel is used up
+    #[cfg(target_os = "linux")]
+    fn poison_when_stopped(
+        &self,
+        app: String,
+        limits: FnLimits,
+        run: JoinHandle<(WasmInstance, Option<WasmEdgeResult<Vec<WasmValue>>>)>,
+        permit: FnCallPermit,
+    ) {
+        let view = self.view.clone();
+        let _ = tokio::spawn(async move {
+            let (vm, _) = run.await.unwrap();
+            // host functions of the guest use the context until it stops
+            let _ = view
+                .instance_manager()
+                .instance_running_function
+                .write()
+                .remove(&vm.vm_instance_name());
+            view.instance_manager().poison(&app, &limits, vm);
+            drop(permit);
+        });
+    }
     pub async fn handle_distribute_task(
         &self,
         resp: RPCResponsor<proto::sche::DistributeTaskReq>,
@@ -205,76 +407,145 @@ impl Executor {
         tracing::debug!("receive distribute task: {:?}", req);
         let app = req.app.to_owned();
         let func = req.func.to_owned();
+        let queue_id = req.queue_id;
+        let workflow_id = req.task_id;
         let ctx = FunctionCtx {
             app: req.app,
             func: req.func,
             req_id: 0,
             res: None,
+            invocation_id: None,
+            host_err: None,
+            workflow_id,
+            workflow_task: queue_id,
+            deadline: None,
             event_ctx: match req.trigger.unwrap() {
-                distribute_task_req::Trigger::KvSet(set) => EventCtx::KvSet {
+                distribute_task_req::Trigger::KvSet(set) => EventCtx::Kv {
+                    kind: KvEventKind::Set,
+                    key: set.key,
+                    revision: set.revision,
+                    opeid: Some(set.opeid),
+                },
+                distribute_task_req::Trigger::KvNew(set) => EventCtx::Kv {
+                    kind: KvEventKind::New,
                     key: set.key,
+                    revision: set.revision,
                     opeid: Some(set.opeid),
                 },
+                distribute_task_req::Trigger::KvChange(set) => EventCtx::Kv {
+                    kind: KvEventKind::Change,
+                    key: set.key,
+                    revision: set.revision,
+                    opeid: Some(set.opeid),
+                },
+                distribute_task_req::Trigger::KvDelete(delete) => EventCtx::Kv {
+                    kind: KvEventKind::Delete,
+                    key: delete.key,
+                    revision: 0,
+                    opeid: Some(delete.opeid),
+                },
+                distribute_task_req::Trigger::Timer(timer) => EventCtx::Timer {
+                    scheduled_time: timer.scheduled_time,
+                },
             },
             sub_waiters: vec![],
         };
-        if let Err(err) = resp.send_resp(DistributeTaskResp {}).await {
+        // the task is rejected before accepted, so that master retries it on other workers
+        let (accepted, error) = match self.accept(&app, &func).await {
+            Ok(accepted) => (accepted, String::new()),
+            Err(err) => (None, err.to_string()),
+        };
+        let rejected = !error.is_empty();
+        if let Err(err) = resp.send_resp(DistributeTaskResp { error }).await {
             tracing::error!("send sche resp for app:{app} fn:{func} failed with err: {err}");
         }
-        let _ = self.execute(ctx).await;
+        if rejected {
+            return;
+        }
+        let res = match accepted {
+            Some(accepted) => self.execute_accepted(ctx, accepted).await,
+            None => None,
+        };
+        if queue_id != 0 {
+            let (output, error) = fn_result_report(res);
+            self.ack_trigger(proto::sche::TriggerAckReq {
+                queue_id,
+                workflow_id,
+                output,
+                error,
+            })
+            .await;
+        }
+    }
+    /// the fn of the queued trigger finished, so that master won't replay it,
+    /// its result is recorded in the workflow
+    async fn ack_trigger(&self, ack: proto::sche::TriggerAckReq) {
+        let p2p = self.view.p2p();
+        let queue_id = ack.queue_id;
+        if let Err(err) = self
+            .rpc_caller_trigger_ack
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                ack,
+                Some(Duration::from_secs(10)),
+            )
+            .await
+        {
+            tracing::warn!(
+                "ack trigger {} failed, it may run again: {:?}",
+                queue_id,
+                err
+            );
+        }
     }
     pub async fn handle_http_task(
         &self,
         route: &str,
         req_id: ReqId,
-        text: String,
-    ) -> Option<String> {
-        let split = route.split("/").into_iter().collect::<Vec<_>>();
+        mut req: HttpReq,
+        invocation_id: Option<String>,
+        workflow_id: u64,
+    ) -> Option<FnResult> {
+        let segs = route
+            .split('/')
+            .filter(|seg| !seg.is_empty())
+            .collect::<Vec<_>>();
 
         // trigger app
-        let appname = split[0];
+        let Some((&appname, sub)) = segs.split_first() else {
+            return Some(FnResult::status(404));
+        };
         let app_meta_man = self.view.appmeta_manager().meta.read().await;
-        if let Some(app) = app_meta_man.get_app_meta(appname) {
-            if split.len() == 1 {
-                if let Some(funcname) = app.http_trigger_fn() {
-                    let ctx = FunctionCtx {
-                        app: appname.to_owned(),
-                        func: funcname.to_owned(),
-                        req_id,
-                        res: None,
-                        event_ctx: EventCtx::Http(text),
-                        sub_waiters: vec![],
-                    };
-                    drop(app_meta_man);
-                    return self.execute(ctx).await;
-                    // self.execute().await;
-                } else {
-                    tracing::warn!("app {} http trigger not found", appname);
-                }
-            } else if split.len() == 2 {
-                let funcname = split[1];
-                if let Some(_func) = app.get_fn_meta(funcname) {
-                    let ctx = FunctionCtx {
-                        app: appname.to_owned(),
-                        func: funcname.to_owned(),
-                        req_id,
-                        res: None,
-                        event_ctx: EventCtx::Http(text),
-                        sub_waiters: vec![],
-                    };
-                    drop(app_meta_man);
-                    return self.execute(ctx).await;
-                } else {
-                    tracing::warn!("func {} not found, exist:{:?}", funcname, app.fns());
-                }
-            } else {
-                tracing::warn!("not support route: {}", route);
-            }
-        } else {
+        let Some(app) = app_meta_man.get_app_meta(appname) else {
             tracing::warn!("app {} not found", appname);
-        }
-
-        None
+            return Some(FnResult::status(404));
+        };
+        let funcname = match app.route_http(sub, &req.method) {
+            Ok((funcname, params)) => {
+                req.params = params;
+                funcname.to_owned()
+            }
+            Err(status) => {
+                tracing::warn!("not support {} route: {}", req.method, route);
+                return Some(FnResult::status(status));
+            }
+        };
+        drop(app_meta_man);
+        let ctx = FunctionCtx {
+            app: appname.to_owned(),
+            func: funcname,
+            req_id,
+            res: None,
+            invocation_id,
+            host_err: None,
+            workflow_id,
+            workflow_task: 0,
+            deadline: None,
+            event_ctx: EventCtx::Http(req),
+            sub_waiters: vec![],
+        };
+        self.execute(ctx).await
     }
     // pub async fn execute_http_app(&self, fn_ctx_builder: FunctionCtxBuilder) {
     //     let app_meta_man = self.view.instance_manager().app_meta_manager.read().await;
@@ -306,82 +577,223 @@ impl Executor {
     //     //     .finish_using(&sche_req.app, vm)
     //     //     .await
     // }
-    async fn execute(&self, fn_ctx: FunctionCtx) -> Option<String> {
+    /// meta of the fn with a slot of its concurrency, None if the fn is not found
+    async fn accept(&self, app: &str, func: &str) -> WSResult<Option<(Arc<FnMeta>, FnCallPermit)>> {
+        // the meta is taken out of the lock, which is not held while the fn runs,
+        // so that the app can be switched to another version meanwhile
+        let fnmeta = {
+            let app_metas = self.view.appmeta_manager().meta.read().await;
+            let Some(app_meta) = app_metas.get_app_meta(app) else {
+                tracing::warn!("app {} not found", app);
+                return Ok(None);
+            };
+            let Some(fnmeta) = app_meta.get_fn_meta(func) else {
+                tracing::warn!("app {} func {} not found", app, func);
+                return Ok(None);
+            };
+            fnmeta.clone()
+        };
+        let permit = self
+            .view
+            .instance_manager()
+            .acquire_fn_call(app, func, &fnmeta.limits)
+            .map_err(|err| {
+                tracing::warn!("reject app {} fn {}: {}", app, func, err);
+                err
+            })?;
+        Ok(Some((fnmeta, permit)))
+    }
+    async fn execute(&self, fn_ctx: FunctionCtx) -> Option<FnResult> {
+        match self.accept(&fn_ctx.app, &fn_ctx.func).await {
+            Ok(accepted) => self.execute_accepted(fn_ctx, accepted?).await,
+            Err(err) => Some(FnResult::Err(err)),
+        }
+    }
+    async fn execute_accepted(
+        &self,
+        mut fn_ctx: FunctionCtx,
+        (fnmeta, permit): (Arc<FnMeta>, FnCallPermit),
+    ) -> Option<FnResult> {
         let app = fn_ctx.app.clone();
         let func = fn_ctx.func.clone();
         let event = fn_ctx.event_ctx.clone();
 
-        // Get app meta data for func and args
-        let app_metas = self.view.appmeta_manager().meta.read().await;
-        if let Some(app_meta) = app_metas.get_app_meta(&app) {
-            if let Some(fnmeta) = app_meta.get_fn_meta(&func) {
-                let vm = self.view.instance_manager().load_instance(&app).await;
-                let _ = self
-                    .view
-                    .instance_manager()
-                    .instance_running_function
-                    .write()
-                    .insert(vm.vm_instance_name().to_owned(), fn_ctx);
-                tracing::debug!(
-                    "start run instance {} app {} fn {}",
-                    vm.vm_instance_name(),
-                    app,
-                    func
-                );
-                // TODO: input value should be passed from context, like http request or prev trigger
-                let params = fnmeta
-                    .args
-                    .iter()
-                    .map(|arg| event.conv_to_wasm_params(arg, &vm))
-                    .flatten()
-                    .collect::<Vec<_>>();
+        if let EventCtx::Http(req) = &event {
+            if let Some(res) = FnResult::check_http_req(&fnmeta, req) {
+                return Some(res);
+            }
+        }
+        let timeout = fnmeta.timeout;
+        let limits = fnmeta.limits;
+        let mut vm = match self
+            .view
+            .instance_manager()
+            // the call times out waiting for a busy instance
+            .load_instance(&app, &limits, timeout)
+            .await
+        {
+            Ok(vm) => vm,
+            Err(err) => {
+                tracing::error!("load instance of app {} failed: {}", app, err);
+                return Some(FnResult::Err(err));
+            }
+        };
+        // TODO: input value should be passed from context, like http request or prev trigger
+        let params = match fnmeta
+            .args
+            .iter()
+            .map(|arg| event.conv_to_wasm_params(arg, &vm))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(params) => params.into_iter().flatten().collect::<Vec<_>>(),
+            Err(msg) => {
+                tracing::warn!("prepare params of app {} fn {}: {}", app, func, msg);
+                // the guest may trap in allocating
+                self.view.instance_manager().poison(&app, &limits, vm);
+                return Some(FnResult::Err(
+                    WsFuncError::PrepareParamsFailed { app, func, msg }.into(),
+                ));
+            }
+        };
 
-                tracing::debug!("execure params: {:?}", params);
+        tracing::debug!("execure params: {:?}", params);
+        let invocation_id = fn_ctx.invocation_id.clone();
+        let cancel = Arc::new(Notify::new());
+        if let Some(invocation_id) = &invocation_id {
+            let _ = self
+                .running_invocations
+                .lock()
+                .insert(invocation_id.clone(), cancel.clone());
+        }
+        // kv operations of the fn wait no longer than it runs
+        fn_ctx.deadline = Some(Instant::now() + timeout);
+        let _ = self
+            .view
+            .instance_manager()
+            .instance_running_function
+            .write()
+            .insert(vm.vm_instance_name().to_owned(), fn_ctx);
+        tracing::debug!(
+            "start run instance {} app {} fn {}",
+            vm.vm_instance_name(),
+            app,
+            func
+        );
 
-                #[cfg(target_os = "linux")]
-                {
-                    let mut a = None;
-                    if vm.active_module().is_err() {
-                        a = Some(vm.vm_instance_name())
-                    }
-                    if let Err(err) = vm
-                        .run_func_async(&AsyncState::new(), a.as_ref().map(|s| &**s), func, params)
-                        .await
-                    {
-                        tracing::error!("run func failed with err: {}", err);
-                    }
+        // the guest runs on a blocking thread so that a fn spinning without calling any host
+        // function doesn't hold a runtime thread, when interrupted it's stopped at its next
+        // host function call, or by its fuel which is bounded by the timeout if not declared
+        #[cfg(target_os = "linux")]
+        let (vm, stopped) = {
+            let fuel = limits.fuel_within(timeout);
+            // cost is accumulated in the instance reused by calls
+            if let Some(stat) = vm.statistics_mut() {
+                stat.set_cost_limit(stat.cost().saturating_add(fuel));
+            }
+            let stop = Arc::new(Notify::new());
+            let mut run = tokio::task::spawn_blocking({
+                let (stop, func) = (stop.clone(), func.clone());
+                let runtime = tokio::runtime::Handle::current();
+                move || {
+                    let res = runtime.block_on(async {
+                        let name = vm.active_module().is_err().then(|| vm.vm_instance_name());
+                        let async_state = AsyncState::new();
+                        tokio::select! {
+                            res = vm.run_func_async(&async_state, name.as_deref(), &func, params) => {
+                                Some(res)
+                            }
+                            _ = stop.notified() => None,
+                        }
+                    });
+                    (vm, res)
                 }
-                #[cfg(target_os = "macos")]
-                let _ = vm
-                    .run_func(Some(&vm.instance_names()[0]), func, params)
-                    .unwrap_or_else(|_| panic!("vm instance names {:?}", vm.instance_names()));
-
-                let mut fn_ctx = self
-                    .view
-                    .instance_manager()
-                    .instance_running_function
-                    .write()
-                    .remove(&vm.vm_instance_name())
-                    .unwrap();
-                tracing::debug!(
-                    "finish run instance {} fn {}",
-                    vm.vm_instance_name(),
-                    fn_ctx.func
-                );
-                while let Some(t) = fn_ctx.sub_waiters.pop() {
-                    let _ = t.await.unwrap();
+            });
+            let interrupted = async {
+                tokio::select! {
+                    _ = tokio::time::sleep(timeout) => FnInterrupt::Timeout(timeout),
+                    _ = cancel.notified() => FnInterrupt::Cancelled,
                 }
-                self.view.instance_manager().finish_using(&app, vm).await;
+            };
+            let (vm, res) = tokio::select! {
+                joined = &mut run => joined.unwrap(),
+                interrupt = interrupted => {
+                    if let Some(invocation_id) = &invocation_id {
+                        let _ = self.running_invocations.lock().remove(invocation_id);
+                    }
+                    stop.notify_one();
+                    self.poison_when_stopped(app.clone(), limits, run, permit);
+                    let stopped = FnResult::Interrupted(interrupt);
+                    tracing::warn!("stop app {} fn {}: {:?}", app, func, stopped);
+                    return Some(stopped);
+                }
+            };
+            let res = res.expect("stopped only when interrupted");
+            let stopped = res.err().map(|err| {
+                tracing::error!("run func failed with err: {}", err);
+                let err =
+                    broken_limit(&app, &func, fuel, &limits, &vm, &err).unwrap_or_else(|| {
+                        WsFuncError::RunFailed {
+                            app: app.clone(),
+                            func: func.clone(),
+                            err: err.to_string(),
+                        }
+                    });
+                FnResult::Err(err.into())
+            });
+            (vm, stopped)
+        };
+        #[cfg(target_os = "macos")]
+        let (vm, stopped) = {
+            let _ = vm
+                .run_func(Some(&vm.instance_names()[0]), &func, params)
+                .unwrap_or_else(|_| panic!("vm instance names {:?}", vm.instance_names()));
+            (vm, None)
+        };
 
-                fn_ctx.res
-            } else {
-                tracing::warn!("app {} func {} not found", app, func);
-                None
+        if let Some(invocation_id) = &invocation_id {
+            let _ = self.running_invocations.lock().remove(invocation_id);
+        }
+        let mut fn_ctx = self
+            .view
+            .instance_manager()
+            .instance_running_function
+            .write()
+            .remove(&vm.vm_instance_name())
+            .unwrap();
+        if let Some(mut stopped) = stopped {
+            // the failure of host func is more exact than the trap following it
+            if let (Some(err), FnResult::Err(_)) = (fn_ctx.host_err.take(), &stopped) {
+                stopped = FnResult::Err(err.into());
             }
-        } else {
-            tracing::warn!("app {} not found", app);
-            None
+            tracing::warn!(
+                "stop instance {} app {} fn {}: {:?}",
+                vm.vm_instance_name(),
+                app,
+                fn_ctx.func,
+                stopped
+            );
+            // the vm stopped in the middle of the fn, trapped or hit its limit, never reuse it
+            self.view.instance_manager().poison(&app, &limits, vm);
+            return Some(stopped);
+        }
+        tracing::debug!(
+            "finish run instance {} fn {}",
+            vm.vm_instance_name(),
+            fn_ctx.func
+        );
+        while let Some(t) = fn_ctx.sub_waiters.pop() {
+            let _ = t.await.unwrap();
+        }
+        self.view
+            .instance_manager()
+            .finish_using(&app, &limits, vm)
+            .await;
+
+        if let Some(err) = fn_ctx.host_err {
+            tracing::warn!("app {} fn {} failed: {:?}", app, func, err);
+            return Some(FnResult::Err(err.into()));
         }
+        fn_ctx.res
 
         // let _ = vm
         //     .run_func_async(
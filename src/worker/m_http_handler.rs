@@ -1,23 +1,30 @@
+use std::sync::Arc;
+
 use crate::{
-    general::network::http_handler::{start_http_handler, HttpHandler, LocalReqIdAllocator},
+    general::network::{
+        http_handler::{
+            invoke_resp_response, start_http_handler, HttpHandler, HttpReq, LocalReqIdAllocator,
+            MAX_PROXIED_BODY,
+        },
+        m_p2p::{P2PModule, RPCHandler, RPCResponsor},
+        proto,
+    },
     logical_module_view_impl,
-    result::WSResult,
+    result::{WSError, WSResult, WsFuncError},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
     util::JoinHandleWrapper,
 };
 use async_trait::async_trait;
-use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
-};
+use axum::response::Response;
 use ws_derive::LogicalModule;
 
-use super::m_executor::Executor;
+use super::m_executor::{Executor, FnResult};
 
 #[derive(LogicalModule)]
 pub struct WorkerHttpHandler {
     view: WorkerHttpHandlerView,
-    local_req_id_allocator: LocalReqIdAllocator,
+    local_req_id_allocator: Arc<LocalReqIdAllocator>,
+    rpc_handler_http_invoke: RPCHandler<proto::sche::HttpInvokeReq>,
 }
 
 #[async_trait]
@@ -28,12 +35,26 @@ impl LogicalModule for WorkerHttpHandler {
     {
         Self {
             view: WorkerHttpHandlerView::new(args.logical_modules_ref.clone()),
-            local_req_id_allocator: LocalReqIdAllocator::new(),
+            local_req_id_allocator: Arc::new(LocalReqIdAllocator::new()),
+            rpc_handler_http_invoke: RPCHandler::default(),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
         tracing::info!("start as worker");
 
+        // fn calls proxied by the gateway of master
+        let view = self.view.clone();
+        let req_id_allocator = self.local_req_id_allocator.clone();
+        self.rpc_handler_http_invoke
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let req_id_allocator = req_id_allocator.clone();
+                let _ = tokio::spawn(async move {
+                    handle_http_invoke(view, &req_id_allocator, responsor, req).await;
+                });
+                Ok(())
+            });
+
         let view = self.view.clone();
         Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
             start_http_handler(view.inner).await;
@@ -41,28 +62,129 @@ impl LogicalModule for WorkerHttpHandler {
     }
 }
 
+async fn handle_http_invoke(
+    view: WorkerHttpHandlerView,
+    req_id_allocator: &LocalReqIdAllocator,
+    responsor: RPCResponsor<proto::sche::HttpInvokeReq>,
+    mut req: proto::sche::HttpInvokeReq,
+) {
+    tracing::debug!("handle_http_invoke {}", req.route);
+    let route = std::mem::take(&mut req.route);
+    let invocation_id = Some(std::mem::take(&mut req.invocation_id)).filter(|id| !id.is_empty());
+    let workflow_id = req.workflow_id;
+    let res = view
+        .executor()
+        .handle_http_task(
+            &route,
+            req_id_allocator.alloc(),
+            HttpReq::from(req),
+            invocation_id,
+            workflow_id,
+        )
+        .await;
+    if let Err(err) = responsor
+        .send_resp(limit_proxied_body(fn_result_invoke_resp(res)))
+        .await
+    {
+        tracing::error!("send http invoke resp failed with err: {}", err);
+    }
+}
+
+/// no result is answered with an empty 200
+fn fn_result_invoke_resp(res: Option<FnResult>) -> proto::sche::HttpInvokeResp {
+    match res {
+        None => proto::sche::HttpInvokeResp {
+            status: 200,
+            headers: vec![],
+            body: vec![],
+            error: String::new(),
+        },
+        Some(FnResult::Text(text)) => proto::sche::HttpInvokeResp {
+            status: 200,
+            headers: vec![proto::sche::HttpHeader {
+                name: "content-type".to_owned(),
+                value: "text/plain; charset=utf-8".to_owned(),
+            }],
+            body: text.into_bytes(),
+            error: String::new(),
+        },
+        Some(FnResult::Http {
+            status,
+            headers,
+            body,
+        }) => proto::sche::HttpInvokeResp {
+            status: status as u32,
+            headers: headers
+                .into_iter()
+                .map(|(name, value)| proto::sche::HttpHeader { name, value })
+                .collect(),
+            body,
+            error: String::new(),
+        },
+        Some(FnResult::Interrupted(interrupt)) => proto::sche::HttpInvokeResp {
+            status: interrupt.status() as u32,
+            headers: vec![],
+            body: interrupt.msg().into_bytes(),
+            error: interrupt.msg(),
+        },
+        Some(FnResult::Err(err)) => {
+            let status = match &err {
+                WSError::WsFuncError(
+                    WsFuncError::ConcurrencyExceeded { .. }
+                    | WsFuncError::InstancesExhausted { .. },
+                ) => 429,
+                WSError::WsFuncError(WsFuncError::InstanceCreateFailed { .. }) => 503,
+                _ => 500,
+            };
+            let msg = err.to_string();
+            proto::sche::HttpInvokeResp {
+                status,
+                headers: vec![],
+                body: msg.clone().into_bytes(),
+                error: msg,
+            }
+        }
+    }
+}
+
+/// the response answered to master is refused with 502 if its body exceeds `MAX_PROXIED_BODY`
+fn limit_proxied_body(resp: proto::sche::HttpInvokeResp) -> proto::sche::HttpInvokeResp {
+    if resp.body.len() <= MAX_PROXIED_BODY {
+        return resp;
+    }
+    let msg = format!(
+        "response body of {} bytes exceeds {} bytes proxied by master",
+        resp.body.len(),
+        MAX_PROXIED_BODY
+    );
+    tracing::warn!("{}", msg);
+    proto::sche::HttpInvokeResp {
+        status: 502,
+        headers: vec![],
+        body: msg.clone().into_bytes(),
+        error: msg,
+    }
+}
+
 logical_module_view_impl!(WorkerHttpHandlerView);
+logical_module_view_impl!(WorkerHttpHandlerView, p2p, P2PModule);
 logical_module_view_impl!(WorkerHttpHandlerView, executor, Option<Executor>);
 
 #[async_trait]
 impl HttpHandler for WorkerHttpHandler {
-    async fn handle_request(&self, route: &str, http_text: String) -> Response {
+    async fn handle_request(&self, route: &str, req: HttpReq) -> Response {
         tracing::debug!("handle_request {}", route);
-        if let Some(res) = self
+        let res = self
             .view
             .executor()
-            .handle_http_task(route, self.local_req_id_allocator.alloc(), http_text)
+            .handle_http_task(route, self.local_req_id_allocator.alloc(), req, None, 0)
             // .execute_http_app(FunctionCtxBuilder::new(
             //     app.to_owned(),
             //     self.local_req_id_allocator.alloc(),
             //     self.request_handler_view.p2p().nodes_config.this.0,
             // ))
-            .await
-        {
-            (StatusCode::OK, res).into_response()
-        } else {
-            StatusCode::OK.into_response()
-        }
+            .await;
+        invoke_resp_response(fn_result_invoke_resp(res))
     }
     // async fn select_node(
     //     &self,
@@ -71,3 +193,86 @@ impl HttpHandler for WorkerHttpHandler {
     //     panic!("worker should not select node");
     // }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::worker::m_executor::FnInterrupt;
+
+    #[test]
+    fn test_fn_result_response() {
+        let resp = invoke_resp_response(fn_result_invoke_resp(None));
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = invoke_resp_response(fn_result_invoke_resp(Some(FnResult::Text(
+            "hello".to_owned(),
+        ))));
+        assert_eq!(resp.headers()["content-type"], "text/plain; charset=utf-8");
+
+        let resp = invoke_resp_response(fn_result_invoke_resp(Some(FnResult::Http {
+            status: 201,
+            headers: vec![
+                ("x-a".to_owned(), "1".to_owned()),
+                ("x-a".to_owned(), "2".to_owned()),
+                ("bad name".to_owned(), "3".to_owned()),
+            ],
+            body: vec![],
+        })));
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get_all("x-a").iter().count(), 2);
+        assert!(resp.headers().get("bad name").is_none());
+
+        let resp = invoke_resp_response(fn_result_invoke_resp(Some(FnResult::Http {
+            status: 0,
+            headers: vec![],
+            body: vec![],
+        })));
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let interrupt = FnInterrupt::Timeout(Duration::from_secs(3));
+        let invoke_resp = fn_result_invoke_resp(Some(FnResult::Interrupted(interrupt)));
+        assert_eq!(invoke_resp.error, interrupt.msg());
+        let resp = invoke_resp_response(invoke_resp);
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let err = WsFuncError::ConcurrencyExceeded {
+            app: "app".to_owned(),
+            func: "fn".to_owned(),
+            max: 1,
+        };
+        let invoke_resp = fn_result_invoke_resp(Some(FnResult::Err(err.into())));
+        assert!(invoke_resp.error.contains("ConcurrencyExceeded"));
+        let resp = invoke_resp_response(invoke_resp);
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let err = WsFuncError::InstancesExhausted {
+            app: "app".to_owned(),
+            max: 2,
+        };
+        let resp = invoke_resp_response(fn_result_invoke_resp(Some(FnResult::Err(err.into()))));
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_limit_proxied_body() {
+        let resp = |len| {
+            fn_result_invoke_resp(Some(FnResult::Http {
+                status: 200,
+                headers: vec![],
+                body: vec![0; len],
+            }))
+        };
+        let limited = limit_proxied_body(resp(MAX_PROXIED_BODY));
+        assert_eq!(
+            (limited.status, limited.body.len()),
+            (200, MAX_PROXIED_BODY)
+        );
+        let limited = limit_proxied_body(resp(MAX_PROXIED_BODY + 1));
+        assert_eq!(limited.status, 502);
+        assert!(!limited.error.is_empty());
+    }
+}
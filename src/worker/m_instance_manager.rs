@@ -1,7 +1,17 @@
-use super::{m_executor::FunctionCtx, wasm::WasmInstance};
+use super::{
+    m_executor::{FunctionCtx, VmExt},
+    wasm::WasmInstance,
+};
 use crate::{
-    result::WSResult,
-    sys::{LogicalModule, LogicalModuleNewArgs},
+    general::{
+        m_appmeta_manager::{
+            AppMetaManager, FnLimits, PoolPolicy, DEFAULT_FUEL_PER_SEC, DEFAULT_POOL_MAX_INSTANCES,
+        },
+        network::proto::metric::{ColdStart, ModuleSource, PoolOccupancy},
+    },
+    logical_module_view_impl,
+    result::{WSError, WSResult, WsFuncError},
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
     util::JoinHandleWrapper,
     worker::wasm_host_funcs, // worker::host_funcs,
 };
@@ -10,8 +20,11 @@ use crossbeam_skiplist::SkipMap;
 use std::{
     collections::{HashMap, VecDeque},
     path::{Path, PathBuf},
-    sync::atomic::{AtomicU64, Ordering},
-    time::Duration,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::sync::Notify;
 use ws_derive::LogicalModule;
@@ -19,12 +32,26 @@ use ws_derive::LogicalModule;
 #[cfg(target_os = "macos")]
 use wasmer::{imports, Instance, Module, Store};
 
+#[cfg(all(target_os = "linux", feature = "aot"))]
+use wasmedge_sdk::{config::CompilerConfigOptions, Compiler, CompilerOutputFormat};
 #[cfg(target_os = "linux")]
 use wasmedge_sdk::{
-    config::{CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions},
-    Module, VmBuilder,
+    config::{
+        CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions, RuntimeConfigOptions,
+        StatisticsConfigOptions,
+    },
+    r#async::AsyncState,
+    Module, Statistics, VmBuilder, WasmEdgeResult,
 };
 
+logical_module_view_impl!(InstanceManagerView);
+logical_module_view_impl!(InstanceManagerView, appmeta_manager, AppMetaManager);
+logical_module_view_impl!(
+    InstanceManagerView,
+    instance_manager,
+    Option<InstanceManager>
+);
+
 pub struct LRUCache<R> {
     capacity: usize,
     cache: HashMap<String, R>,
@@ -98,89 +125,435 @@ pub struct KvEventDef {
 //     // }
 // }
 
+/// lifecycle of an instance, created → idle ⇄ busy → destroyed,
+/// a busy one stopped in the middle of a call is poisoned and destroyed without reuse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InstanceState {
+    /// instantiated and running the guest `init`
+    Created,
+    Idle,
+    Busy,
+    Poisoned,
+    Destroyed,
+}
+
+/// guest export called once the instance is created, failing it fails the creation,
+/// like teardown it runs out of any fn call, so kv apis are not available in it
+const GUEST_INIT: &str = "init";
+
+/// guest export called before an instance not poisoned is destroyed
+const GUEST_TEARDOWN: &str = "teardown";
+
+/// guest init or teardown running longer is taken as failed,
+/// a hook spinning without calling any host function is stopped by the fuel of the time
+const GUEST_HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
 struct EachAppCache {
-    cache: moka::sync::Cache<u64, WasmInstance>,
-    next_instance_id: AtomicU64,
-    using: AtomicU64,
-    getting: Notify,
+    /// idle instances, the most recently used last
+    pool: parking_lot::Mutex<VecDeque<WasmInstance>>,
+    /// instances in each state, the destroyed ones are counted since start
+    states: [AtomicU64; 5],
+    /// linear memory limit of the instances
+    max_memory_pages: Option<u32>,
+    /// instances with smaller global id are created from an old app.wasm
+    first_valid_id: AtomicU64,
+    /// most instances created or busy since the last scale
+    peak_using: AtomicU64,
+    /// peak instances in use of each scale interval within the idle ttl, the latest last
+    history: parking_lot::Mutex<VecDeque<u64>>,
+    /// instances wanted by the last scale
+    target: AtomicU64,
+    /// instances alive at most, calls wait for one to be released beyond it
+    max_instances: AtomicU64,
+    /// an instance is pooled or destroyed
+    released: Notify,
 }
 impl EachAppCache {
-    pub fn new() -> Self {
+    pub fn new(max_memory_pages: Option<u32>) -> Self {
         Self {
-            cache: moka::sync::CacheBuilder::new(100)
-                .time_to_live(Duration::from_secs(60))
-                .build(),
-            next_instance_id: AtomicU64::new(0),
-            using: AtomicU64::new(0),
-            getting: Notify::new(),
+            pool: parking_lot::Mutex::new(VecDeque::new()),
+            states: Default::default(),
+            max_memory_pages,
+            first_valid_id: AtomicU64::new(0),
+            peak_using: AtomicU64::new(0),
+            history: parking_lot::Mutex::new(VecDeque::new()),
+            target: AtomicU64::new(0),
+            max_instances: AtomicU64::new(DEFAULT_POOL_MAX_INSTANCES as u64),
+            released: Notify::new(),
         }
     }
-    pub async fn get(&self, file_dir: impl AsRef<Path>, instance_name: &str) -> WasmInstance {
-        loop {
-            let using = self.getting.notified();
-
-            if self.using.fetch_add(1, Ordering::Relaxed) >= 100 {
-                // wait for put
-                using.await;
-            } else {
-                break;
+    fn count(&self, state: InstanceState) -> u64 {
+        self.states[state as usize].load(Ordering::Relaxed)
+    }
+    /// move an instance from state `from` to `to`, None for a new one
+    fn transit(&self, from: Option<InstanceState>, to: InstanceState) {
+        if let Some(from) = from {
+            let _ = self.states[from as usize].fetch_sub(1, Ordering::Relaxed);
+        }
+        let _ = self.states[to as usize].fetch_add(1, Ordering::Relaxed);
+        if matches!(to, InstanceState::Idle | InstanceState::Destroyed) {
+            self.released.notify_one();
+        }
+    }
+    /// instances created or busy, which the calls are using
+    fn using(&self) -> u64 {
+        self.count(InstanceState::Created) + self.count(InstanceState::Busy)
+    }
+    /// instances not destroyed yet
+    fn alive(&self) -> u64 {
+        self.using() + self.count(InstanceState::Idle) + self.count(InstanceState::Poisoned)
+    }
+    /// count a new instance in created state unless max instances are alive
+    fn reserve(&self) -> bool {
+        let _pool = self.pool.lock();
+        if self.alive() >= self.max_instances.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.transit(None, InstanceState::Created);
+        true
+    }
+    /// pooled instance or a new one of `module`, `instance_id` names the module registered in it,
+    /// waits for an instance to be released at most `wait` if max instances are busy,
+    /// also returns whether it's newly created
+    pub async fn get(
+        &self,
+        app: &str,
+        instance_id: u64,
+        module: &Module,
+        wait: Duration,
+    ) -> WSResult<(WasmInstance, bool)> {
+        let deadline = tokio::time::Instant::now() + wait;
+        let pooled = loop {
+            let released = self.released.notified();
+            {
+                let mut pool = self.pool.lock();
+                if let Some(vm) = pool.pop_back() {
+                    self.transit(Some(InstanceState::Idle), InstanceState::Busy);
+                    break Some(vm);
+                }
+            }
+            if self.reserve() {
+                break None;
             }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                return Err(WsFuncError::InstancesExhausted {
+                    app: app.to_owned(),
+                    max: self.max_instances.load(Ordering::Relaxed),
+                }
+                .into());
+            }
+        };
+        let res = match pooled {
+            Some(vm) => Ok((vm, false)),
+            None => {
+                tracing::info!("new vm");
+                self.create(app, instance_id, module.clone())
+                    .await
+                    .map(|vm| {
+                        self.transit(Some(InstanceState::Created), InstanceState::Busy);
+                        (vm, true)
+                    })
+                    .map_err(|err| {
+                        WsFuncError::InstanceCreateFailed {
+                            app: app.to_owned(),
+                            err,
+                        }
+                        .into()
+                    })
+            }
+        };
+        let _ = self.peak_using.fetch_max(self.using(), Ordering::Relaxed);
+        res
+    }
+    /// new instance initialized by the guest, left in created state, it's reserved before
+    async fn create(
+        &self,
+        app: &str,
+        instance_id: u64,
+        module: Module,
+    ) -> Result<WasmInstance, String> {
+        let (name, max_memory_pages) = (app.to_owned(), self.max_memory_pages);
+        // compiling and instantiating take long
+        let vm = tokio::task::spawn_blocking(move || {
+            new_instance(&name, instance_id, module, max_memory_pages)
+        })
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|res| res.map_err(|err| err.to_string()));
+        let res = match vm {
+            Ok(vm) => run_guest_hook(vm, GUEST_INIT)
+                .await
+                .map_err(|err| format!("guest {} failed: {}", GUEST_INIT, err)),
+            Err(err) => Err(err),
+        };
+        if res.is_err() {
+            self.transit(Some(InstanceState::Created), InstanceState::Destroyed);
         }
-
-        if let Some(a) = self.cache.iter().next() {
-            if let Some(a) = self.cache.remove(&*a.0) {
-                return a;
+        res
+    }
+    /// instances of an old app.wasm or beyond max instances are destroyed instead of pooled
+    pub fn put(&self, instance_id: u64, vm: WasmInstance) {
+        {
+            let mut pool = self.pool.lock();
+            if instance_id >= self.first_valid_id.load(Ordering::Relaxed)
+                && self.alive() <= self.max_instances.load(Ordering::Relaxed)
+            {
+                pool.push_back(vm);
+                self.transit(Some(InstanceState::Busy), InstanceState::Idle);
+                return;
             }
         }
+        self.destroy(InstanceState::Busy, vm);
+    }
+    /// the instance stopped in the middle of a call, its state can't be trusted anymore,
+    /// so it's dropped without teardown
+    pub fn poison(&self, vm: WasmInstance) {
+        self.transit(Some(InstanceState::Busy), InstanceState::Poisoned);
+        drop(vm);
+        self.transit(Some(InstanceState::Poisoned), InstanceState::Destroyed);
+    }
+    /// tear down the instance by the guest in background and drop it
+    fn destroy(&self, from: InstanceState, vm: WasmInstance) {
+        self.transit(Some(from), InstanceState::Destroyed);
+        let _ = tokio::spawn(async move {
+            let name = vm.vm_instance_name();
+            if let Err(err) = run_guest_hook(vm, GUEST_TEARDOWN).await {
+                tracing::warn!(
+                    "guest {} of instance {} failed: {}",
+                    GUEST_TEARDOWN,
+                    name,
+                    err
+                );
+            }
+        });
+    }
+    /// pool the instance created by scaling
+    fn warm(&self, instance_id: u64, vm: WasmInstance) {
         {
-            tracing::info!("new vm");
-            let config = ConfigBuilder::new(CommonConfigOptions::default())
-                .with_host_registration_config(HostRegistrationConfigOptions::default().wasi(true))
-                .build()
-                .expect("failed to create config");
-            let module = Module::from_file(
-                Some(&config),
-                file_dir
-                    .as_ref()
-                    .join(format!("apps/{}/app.wasm", instance_name)),
+            let mut pool = self.pool.lock();
+            if instance_id >= self.first_valid_id.load(Ordering::Relaxed)
+                && self.alive() <= self.max_instances.load(Ordering::Relaxed)
+            {
+                pool.push_front(vm);
+                self.transit(Some(InstanceState::Created), InstanceState::Idle);
+                return;
+            }
+        }
+        self.destroy(InstanceState::Created, vm);
+    }
+    /// take the idle instances beyond `keep`, the least recently used first
+    fn evict(&self, keep: u64) -> Vec<WasmInstance> {
+        let mut pool = self.pool.lock();
+        let mut evicted = vec![];
+        while pool.len() as u64 > keep {
+            evicted.extend(pool.pop_front());
+        }
+        evicted
+    }
+    /// app.wasm is updated, instances created before `first_valid_id` are not reused
+    fn invalidate(&self, first_valid_id: u64) {
+        self.first_valid_id.store(first_valid_id, Ordering::Relaxed);
+        for vm in self.evict(0) {
+            self.destroy(InstanceState::Idle, vm);
+        }
+    }
+    fn limit(&self, max_instances: u32) {
+        self.max_instances
+            .store(max_instances as u64, Ordering::Relaxed);
+    }
+    /// record the peak of the passed interval and destroy the idle instances not wanted,
+    /// returns how many instances should be created
+    fn scale(&self, policy: &PoolPolicy) -> u64 {
+        self.limit(policy.max_instances);
+        let using = self.using();
+        let target = {
+            let mut history = self.history.lock();
+            history.push_back(self.peak_using.swap(using, Ordering::Relaxed));
+            let window = (policy.idle_ttl.as_millis() / POOL_SCALE_INTERVAL.as_millis()).max(1);
+            while history.len() as u128 > window {
+                let _ = history.pop_front();
+            }
+            pool_target(&history, policy)
+        };
+        self.target.store(target, Ordering::Relaxed);
+        let idle = target.saturating_sub(using);
+        for vm in self.evict(idle) {
+            self.destroy(InstanceState::Idle, vm);
+        }
+        idle.saturating_sub(self.count(InstanceState::Idle))
+    }
+    fn occupancy(&self) -> (u64, u64, u64) {
+        (
+            self.count(InstanceState::Idle),
+            self.using(),
+            self.target.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// run the guest export `hook` if the instance has it, on a blocking thread like fn calls,
+/// the instance is dropped if the hook failed
+async fn run_guest_hook(mut vm: WasmInstance, hook: &'static str) -> Result<WasmInstance, String> {
+    let name = vm.vm_instance_name();
+    let exported = vm
+        .named_module(&name)
+        .map_or(false, |instance| instance.func(hook).is_ok());
+    if !exported {
+        return Ok(vm);
+    }
+    // cost is accumulated in the instance, the calls later add their own fuel
+    let fuel = DEFAULT_FUEL_PER_SEC.saturating_mul(GUEST_HOOK_TIMEOUT.as_secs());
+    if let Some(stat) = vm.statistics_mut() {
+        stat.set_cost_limit(stat.cost().saturating_add(fuel));
+    }
+    let runtime = tokio::runtime::Handle::current();
+    let (vm, res) = tokio::task::spawn_blocking(move || {
+        let res = runtime.block_on(async {
+            let async_state = AsyncState::new();
+            tokio::time::timeout(
+                GUEST_HOOK_TIMEOUT,
+                vm.run_func_async(&async_state, Some(&name), hook, []),
             )
-            .unwrap();
-            let import = wasm_host_funcs::new_import_obj();
-            let vm = VmBuilder::new()
-                .with_config(config)
-                // .with_wasi_context(WasiContext::default())
-                .build()
-                .unwrap_or_else(|err| panic!("failed to create vm: {:?}", err));
-            let vm = vm.register_import_module(import).unwrap();
-            let vm = vm
-                .register_module(
-                    Some(&format!(
-                        "{}{}",
-                        instance_name,
-                        self.next_instance_id.fetch_add(1, Ordering::Relaxed)
-                    )),
-                    module,
-                )
-                .unwrap();
-            return vm;
-        }
-    }
-    pub fn put(&self, value: WasmInstance) {
-        self.cache
-            .insert(self.next_instance_id.fetch_add(1, Ordering::Relaxed), value);
-        let _ = self.using.fetch_sub(1, Ordering::Relaxed);
-        self.getting.notify_waiters();
+            .await
+        });
+        (vm, res)
+    })
+    .await
+    .map_err(|err| err.to_string())?;
+    match res {
+        Ok(Ok(_)) => Ok(vm),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(format!("timeout after {:?}", GUEST_HOOK_TIMEOUT)),
+    }
+}
+
+/// interval of scaling the instance pools to the calls seen
+const POOL_SCALE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// instances the app should have, the peak of calls in flight within the idle ttl,
+/// plus the growth of the latest interval so that a rising load meets warm instances
+fn pool_target(history: &VecDeque<u64>, policy: &PoolPolicy) -> u64 {
+    let peak = history.iter().copied().max().unwrap_or(0);
+    let growth = match history.len() {
+        n if n >= 2 => history[n - 1].saturating_sub(history[n - 2]),
+        _ => 0,
+    };
+    (peak + growth).clamp(policy.min_warm as u64, policy.max_instances as u64)
+}
+
+/// instance of the app module, `instance_id` names the module registered in it
+fn new_instance(
+    app: &str,
+    instance_id: u64,
+    module: Module,
+    max_memory_pages: Option<u32>,
+) -> WasmEdgeResult<WasmInstance> {
+    let config = vm_config(max_memory_pages).build()?;
+    let import = wasm_host_funcs::new_import_obj();
+    let vm = VmBuilder::new()
+        .with_config(config)
+        .with_statistics(Statistics::new()?)
+        // .with_wasi_context(WasiContext::default())
+        .build()?;
+    let vm = vm.register_import_module(import)?;
+    vm.register_module(Some(&format!("{}{}", app, instance_id)), module)
+}
+
+/// config of the instances, the cost limit is set for each call as the fuel of the fn
+fn vm_config(max_memory_pages: Option<u32>) -> ConfigBuilder {
+    let mut runtime_config = RuntimeConfigOptions::default();
+    if let Some(pages) = max_memory_pages {
+        runtime_config = runtime_config.max_memory_pages(pages);
+    }
+    ConfigBuilder::new(CommonConfigOptions::default())
+        .with_host_registration_config(HostRegistrationConfigOptions::default().wasi(true))
+        .with_runtime_config(runtime_config)
+        .with_statistics_config(
+            StatisticsConfigOptions::default()
+                .count_instructions(true)
+                .measure_cost(true),
+        )
+}
+
+/// compiled module of app.wasm, shared by the instances of app
+#[derive(Clone)]
+struct AppModule {
+    module: Module,
+    /// modified time of app.wasm compiled
+    modified: SystemTime,
+}
+
+/// native artifact compiled from app.wasm next to it, compiled again if it's older than app.wasm
+#[cfg(feature = "aot")]
+fn aot_artifact(wasm: &Path, modified: SystemTime) -> WasmEdgeResult<PathBuf> {
+    let dir = wasm.parent().unwrap();
+    let artifact = dir.join("app.so");
+    let compiled = std::fs::metadata(&artifact)
+        .and_then(|meta| meta.modified())
+        .map_or(false, |time| time >= modified);
+    if compiled {
+        return Ok(artifact);
+    }
+    tracing::info!("aot compile {:?}", wasm);
+    let config = vm_config(None)
+        .with_compiler_config(
+            CompilerConfigOptions::default().out_format(CompilerOutputFormat::Native),
+        )
+        .build()?;
+    Compiler::new(Some(&config))?.compile_from_file(wasm, "app", dir)
+}
+
+/// load the module of app.wasm, from its aot artifact if `aot`
+fn load_module(
+    wasm: &Path,
+    modified: SystemTime,
+    aot: bool,
+) -> WasmEdgeResult<(Module, ModuleSource)> {
+    let config = vm_config(None).build()?;
+    #[cfg(feature = "aot")]
+    if aot {
+        match aot_artifact(wasm, modified) {
+            Ok(artifact) => {
+                return Ok((
+                    Module::from_file(Some(&config), artifact)?,
+                    ModuleSource::Aot,
+                ))
+            }
+            Err(err) => tracing::warn!("aot compile {:?} failed, load it as wasm: {}", wasm, err),
+        }
+    }
+    #[cfg(not(feature = "aot"))]
+    let _ = (modified, aot);
+    Ok((Module::from_file(Some(&config), wasm)?, ModuleSource::Wasm))
+}
+
+/// a running call of fn, counted in the concurrency of fn until dropped
+pub struct FnCallPermit(Arc<AtomicU32>);
+
+impl Drop for FnCallPermit {
+    fn drop(&mut self) {
+        let _ = self.0.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
 #[derive(LogicalModule)]
 pub struct InstanceManager {
     // cache: Mutex<LRUCache<Vm>>,
+    /// app and memory limit to the instances
     using_map: SkipMap<String, EachAppCache>,
+    /// app to its compiled module
+    modules: parking_lot::Mutex<HashMap<String, AppModule>>,
+    /// compile apps ahead of time
+    aot: bool,
+    /// instances created since the last metric report
+    cold_starts: parking_lot::Mutex<Vec<ColdStart>>,
+    /// app/fn to its running calls
+    fn_running: parking_lot::Mutex<HashMap<String, Arc<AtomicU32>>>,
     file_dir: PathBuf,
     /// instance addr 2 running function
     pub instance_running_function: parking_lot::RwLock<HashMap<String, FunctionCtx>>,
     pub next_instance_id: AtomicU64,
+    view: InstanceManagerView,
 }
 
 #[async_trait]
@@ -189,15 +562,41 @@ impl LogicalModule for InstanceManager {
     where
         Self: Sized,
     {
+        let aot = args.nodes_config.instance.aot;
+        if aot && cfg!(not(feature = "aot")) {
+            tracing::warn!("aot is configured but not built in, apps are loaded as wasm");
+        }
         Self {
             using_map: SkipMap::new(),
+            modules: parking_lot::Mutex::new(HashMap::new()),
+            aot,
+            cold_starts: parking_lot::Mutex::new(vec![]),
+            fn_running: parking_lot::Mutex::new(HashMap::new()),
             file_dir: args.nodes_config.file_dir.clone(),
             instance_running_function: parking_lot::RwLock::new(HashMap::new()),
             next_instance_id: AtomicU64::new(0),
+            view: InstanceManagerView::new(args.logical_modules_ref.clone()),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
-        Ok(vec![])
+        // other pools are created by the first call of the app
+        let prewarmed = self
+            .view
+            .appmeta_manager()
+            .meta
+            .read()
+            .await
+            .prewarmed_caches();
+        for (app, pages) in prewarmed {
+            self.prewarm(&app, pages);
+        }
+        let view = self.view.clone();
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(POOL_SCALE_INTERVAL).await;
+                view.instance_manager().scale_pools().await;
+            }
+        }))])
     }
 }
 
@@ -210,22 +609,294 @@ impl InstanceManager {
     //     Ok(())
     // }
 
-    pub async fn finish_using(&self, instance_name: &str, vm: WasmInstance) {
+    /// create the instance caches of app, they're warmed to the min warm instances by scaling
+    pub fn prewarm(&self, app: &str, pages: Vec<Option<u32>>) {
+        for max_memory_pages in pages {
+            let _ = self.app_cache(app, max_memory_pages);
+        }
+    }
+    fn app_cache(
+        &self,
+        app: &str,
+        max_memory_pages: Option<u32>,
+    ) -> crossbeam_skiplist::map::Entry<'_, String, EachAppCache> {
+        // instances can't be shared by fns with different memory limit
+        let key = match max_memory_pages {
+            Some(pages) => format!("{}:{}", app, pages),
+            None => app.to_owned(),
+        };
         self.using_map
-            .get_or_insert(instance_name.to_owned(), EachAppCache::new())
+            .get_or_insert(key, EachAppCache::new(max_memory_pages))
+    }
+    /// compiled module of app, compiled again when app.wasm is modified,
+    /// the source is cached if it's compiled before
+    async fn app_module(&self, app: &str) -> WSResult<(Module, ModuleSource)> {
+        let create_failed = |err: String| -> WSError {
+            WsFuncError::InstanceCreateFailed {
+                app: app.to_owned(),
+                err,
+            }
+            .into()
+        };
+        let wasm = self.file_dir.join(format!("apps/{}/app.wasm", app));
+        let modified = std::fs::metadata(&wasm)
+            .and_then(|meta| meta.modified())
+            .map_err(|err| create_failed(format!("read {:?}: {}", wasm, err)))?;
+        let old = self.modules.lock().get(app).cloned();
+        if let Some(cached) = old.as_ref().filter(|m| m.modified == modified) {
+            return Ok((cached.module.clone(), ModuleSource::Cached));
+        }
+        // compiling takes long, especially ahead of time
+        let aot = self.aot;
+        let (module, source) =
+            tokio::task::spawn_blocking(move || load_module(&wasm, modified, aot))
+                .await
+                .map_err(|err| create_failed(err.to_string()))?
+                .map_err(|err| create_failed(err.to_string()))?;
+        let _ = self.modules.lock().insert(
+            app.to_owned(),
+            AppModule {
+                module: module.clone(),
+                modified,
+            },
+        );
+        if old.is_some() {
+            tracing::info!("app {} updated, its old instances are dropped", app);
+            self.invalidate_app(app);
+        }
+        Ok((module, source))
+    }
+    /// app is switched to another version, instances of the old one are destroyed once idle
+    pub fn drain_app(&self, app: &str) {
+        let _ = self.modules.lock().remove(app);
+        self.invalidate_app(app);
+    }
+    fn invalidate_app(&self, app: &str) {
+        let first_valid_id = self.next_instance_id.load(Ordering::Relaxed);
+        for cache in self.using_map.iter() {
+            if app_of_cache_key(cache.key()) == app {
+                cache.value().invalidate(first_valid_id);
+            }
+        }
+    }
+    /// fails when the fn already runs `limits.max_concurrency` calls
+    pub fn acquire_fn_call(
+        &self,
+        app: &str,
+        func: &str,
+        limits: &FnLimits,
+    ) -> WSResult<FnCallPermit> {
+        let running = self
+            .fn_running
+            .lock()
+            .entry(format!("{}/{}", app, func))
+            .or_default()
+            .clone();
+        if running.fetch_add(1, Ordering::Relaxed) >= limits.max_concurrency {
+            let _ = running.fetch_sub(1, Ordering::Relaxed);
+            return Err(WsFuncError::ConcurrencyExceeded {
+                app: app.to_owned(),
+                func: func.to_owned(),
+                max: limits.max_concurrency,
+            }
+            .into());
+        }
+        Ok(FnCallPermit(running))
+    }
+    pub async fn finish_using(&self, app: &str, limits: &FnLimits, vm: WasmInstance) {
+        let instance_id = vm
+            .vm_instance_name()
+            .strip_prefix(app)
+            .and_then(|id| id.parse().ok());
+        let cache = self.app_cache(app, limits.max_memory_pages);
+        match instance_id {
+            Some(instance_id) => cache.value().put(instance_id, vm),
+            None => cache.value().destroy(InstanceState::Busy, vm),
+        }
+    }
+    /// the instance can't be reused, like trapped or interrupted in the middle of a fn
+    pub fn poison(&self, app: &str, limits: &FnLimits, vm: WasmInstance) {
+        self.app_cache(app, limits.max_memory_pages)
             .value()
-            .put(vm);
+            .poison(vm);
     }
-    pub async fn load_instance(&self, instance_name: &str) -> WasmInstance {
+    /// instance limited by the memory pages of `limits`, the fuel is set for each call,
+    /// waits at most `wait` for one to be released if max instances are busy
+    pub async fn load_instance(
+        &self,
+        app: &str,
+        limits: &FnLimits,
+        wait: Duration,
+    ) -> WSResult<WasmInstance> {
         // let lock = self
         //     .using_map
         //     .get_or_insert(instance_name.to_owned(), Mutex::new(()).into())
         //     .value()
         //     .clone();
-        self.using_map
-            .get_or_insert(instance_name.to_owned(), EachAppCache::new())
-            .value()
-            .get(&self.file_dir, instance_name)
+        let start = Instant::now();
+        let (module, source) = self.app_module(app).await?;
+        let max_instances = self
+            .view
+            .appmeta_manager()
+            .meta
+            .read()
             .await
+            .get_app_meta(app)
+            .map_or(DEFAULT_POOL_MAX_INSTANCES, |appmeta| {
+                appmeta.pool().max_instances
+            });
+        let cache = self.app_cache(app, limits.max_memory_pages);
+        // the pool may be created by this call, before it's scaled by the policy
+        cache.value().limit(max_instances);
+        let (vm, created) = cache
+            .value()
+            .get(
+                app,
+                self.next_instance_id.fetch_add(1, Ordering::Relaxed),
+                &module,
+                wait,
+            )
+            .await?;
+        if created {
+            self.cold_starts.lock().push(ColdStart {
+                app: app.to_owned(),
+                seconds: start.elapsed().as_secs_f32(),
+                module: source as i32,
+            });
+        }
+        Ok(vm)
+    }
+    /// instances created since last taken, reported to master as metric
+    pub fn take_cold_starts(&self) -> Vec<ColdStart> {
+        std::mem::take(&mut *self.cold_starts.lock())
+    }
+    /// instances of each app, reported to master as metric
+    pub fn pool_occupancy(&self) -> Vec<PoolOccupancy> {
+        let mut apps: HashMap<String, PoolOccupancy> = HashMap::new();
+        for cache in self.using_map.iter() {
+            let app = app_of_cache_key(cache.key()).to_owned();
+            let (idle, busy, target) = cache.value().occupancy();
+            let occupancy = apps.entry(app.clone()).or_insert_with(|| PoolOccupancy {
+                app,
+                ..Default::default()
+            });
+            occupancy.idle += idle;
+            occupancy.busy += busy;
+            occupancy.target += target;
+        }
+        apps.into_values().collect()
+    }
+    /// scale the instance pools to the calls seen and the pool policy of apps
+    async fn scale_pools(&self) {
+        let caches: Vec<_> = {
+            let metas = self.view.appmeta_manager().meta.read().await;
+            self.using_map
+                .iter()
+                .filter_map(|cache| {
+                    let appmeta = metas.get_app_meta(app_of_cache_key(cache.key()))?;
+                    Some((cache.key().clone(), appmeta.pool()))
+                })
+                .collect()
+        };
+        for (key, policy) in caches {
+            let Some(cache) = self.using_map.get(&key) else {
+                continue;
+            };
+            let create = cache.value().scale(&policy);
+            if create == 0 {
+                continue;
+            }
+            let app = app_of_cache_key(&key).to_owned();
+            let module = match self.app_module(&app).await {
+                Ok((module, _)) => module,
+                Err(err) => {
+                    tracing::warn!("warm instances of app {} failed: {}", app, err);
+                    continue;
+                }
+            };
+            for _ in 0..create {
+                if !cache.value().reserve() {
+                    break;
+                }
+                let instance_id = self.next_instance_id.fetch_add(1, Ordering::Relaxed);
+                match cache
+                    .value()
+                    .create(&app, instance_id, module.clone())
+                    .await
+                {
+                    Ok(vm) => cache.value().warm(instance_id, vm),
+                    Err(err) => {
+                        tracing::warn!("warm instance of app {} failed: {}", key, err);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// key of app cache is app or app:memory pages
+fn app_of_cache_key(key: &str) -> &str {
+    key.split_once(':').map_or(key, |(app, _)| app)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_app_of_cache_key() {
+        assert_eq!(app_of_cache_key("word_count"), "word_count");
+        assert_eq!(app_of_cache_key("word_count:16"), "word_count");
+    }
+
+    #[test]
+    fn test_instance_states() {
+        let cache = EachAppCache::new(None);
+        // two calls on new instances
+        cache.transit(None, InstanceState::Created);
+        cache.transit(None, InstanceState::Created);
+        assert_eq!((cache.using(), cache.alive()), (2, 2));
+        cache.transit(Some(InstanceState::Created), InstanceState::Busy);
+        cache.transit(Some(InstanceState::Created), InstanceState::Busy);
+        // one finished, the other trapped
+        cache.transit(Some(InstanceState::Busy), InstanceState::Idle);
+        cache.transit(Some(InstanceState::Busy), InstanceState::Poisoned);
+        cache.transit(Some(InstanceState::Poisoned), InstanceState::Destroyed);
+        assert_eq!((cache.using(), cache.alive()), (0, 1));
+        assert_eq!(cache.occupancy(), (1, 0, 0));
+        assert_eq!(cache.count(InstanceState::Destroyed), 1);
+    }
+
+    #[test]
+    fn test_reserve_within_max_instances() {
+        let cache = EachAppCache::new(None);
+        cache.limit(2);
+        assert!(cache.reserve());
+        assert!(cache.reserve());
+        assert!(!cache.reserve());
+        // released by a poisoned call
+        cache.transit(Some(InstanceState::Created), InstanceState::Busy);
+        cache.transit(Some(InstanceState::Busy), InstanceState::Poisoned);
+        cache.transit(Some(InstanceState::Poisoned), InstanceState::Destroyed);
+        assert!(cache.reserve());
+        assert_eq!(cache.alive(), 2);
+    }
+
+    #[test]
+    fn test_pool_target() {
+        let policy = PoolPolicy {
+            min_warm: 2,
+            max_instances: 10,
+            ..Default::default()
+        };
+        let target = |history: &[u64]| pool_target(&history.iter().copied().collect(), &policy);
+        assert_eq!(target(&[]), 2);
+        assert_eq!(target(&[0, 1]), 2);
+        // the peak is kept within the idle ttl
+        assert_eq!(target(&[5, 3, 1]), 5);
+        // rising calls get the growth ahead
+        assert_eq!(target(&[1, 3, 6]), 9);
+        assert_eq!(target(&[2, 8]), 10);
     }
 }
@@ -3,27 +3,36 @@ use std::time::Duration;
 use crate::{
     general::{
         kv_interface::{KvInterface, KvOptions},
+        m_appmeta_manager::AppMetaManager,
         network::{
             m_p2p::{P2PModule, RPCCaller},
-            proto::kv::{KvRequests, KvResponses},
+            proto::{
+                self,
+                kv::{KvPositionReq, KvRequests, KvResponses},
+            },
         },
     },
     logical_module_view_impl,
     result::WSResult,
-    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
 };
 use async_trait::async_trait;
 use ws_derive::LogicalModule;
 
+/// time of waiting for kv operations not bounded by the caller
+const KV_RPC_TIMEOUT: Duration = Duration::from_secs(60 * 30);
+
 logical_module_view_impl!(KvUserClientView);
 logical_module_view_impl!(KvUserClientView, p2p, P2PModule);
+logical_module_view_impl!(KvUserClientView, appmeta_manager, AppMetaManager);
 
 #[derive(LogicalModule)]
 pub struct KvUserClient {
     // testmap: SkipMap<Vec<u8>, Vec<u8>>,
     view: KvUserClientView,
     rpc_caller_kv: RPCCaller<KvRequests>,
+    rpc_caller_kv_position: RPCCaller<KvPositionReq>,
 }
 
 #[async_trait]
@@ -41,10 +50,12 @@ impl LogicalModule for KvUserClient {
             // testmap: SkipMap::new(),
             view: KvUserClientView::new(args.logical_modules_ref.clone()),
             rpc_caller_kv: RPCCaller::default(),
+            rpc_caller_kv_position: RPCCaller::default(),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
         self.rpc_caller_kv.regist(self.view.p2p());
+        self.rpc_caller_kv_position.regist(self.view.p2p());
 
         let all = vec![];
 
@@ -81,16 +92,38 @@ lazy_static::lazy_static! {
 
 #[async_trait]
 impl KvInterface for KvUserClient {
-    async fn call(&self, req: KvRequests, opt: KvOptions) -> WSResult<KvResponses> {
+    async fn call(&self, mut req: KvRequests, opt: KvOptions) -> WSResult<KvResponses> {
+        self.fill_default_ttl(&mut req).await;
+        let timeout = opt.timeout().unwrap_or(KV_RPC_TIMEOUT);
         if let Some(node_id) = opt.spec_node() {
-            self.rpc_caller_kv
-                .call(
-                    self.view.p2p(),
-                    node_id,
-                    req,
-                    Some(Duration::from_secs(60 * 30)),
-                )
-                .await
+            let (set_keys, ttls): (Vec<Vec<u8>>, Vec<u32>) = req
+                .requests
+                .iter()
+                .filter_map(|req| match req.op.as_ref().unwrap() {
+                    proto::kv::kv_request::Op::Set(set) => {
+                        set.kv.as_ref().map(|kv| (kv.key.clone(), set.ttl))
+                    }
+                    _ => None,
+                })
+                .unzip();
+            let resps = self
+                .rpc_caller_kv
+                .call(self.view.p2p(), node_id, req, Some(timeout))
+                .await?;
+            // the data is held by the spec node now, update the directory on master
+            if !set_keys.is_empty()
+                && !resps.aborted
+                && node_id != self.view.p2p().nodes_config.get_master_node()
+            {
+                let (_, error) = self
+                    .kv_position(set_keys, Some((node_id, ttls)))
+                    .await?;
+                // the written data is read from the spec node, the stale copy left is only a leak
+                if !error.is_empty() {
+                    tracing::warn!("kv set to node {}: {}", node_id, error);
+                }
+            }
+            Ok(resps)
         } else {
             // 1. dicide placement position
             // 2. send data to the position
@@ -99,7 +132,7 @@ impl KvInterface for KvUserClient {
                     self.view.p2p(),
                     self.view.p2p().nodes_config.get_master_node(),
                     req,
-                    Some(Duration::from_secs(60 * 30)),
+                    Some(timeout),
                 )
                 .await
         }
@@ -107,6 +140,53 @@ impl KvInterface for KvUserClient {
 }
 
 impl KvUserClient {
+    /// keys written without ttl use the default ttl of matched pattern in app.yaml
+    async fn fill_default_ttl(&self, req: &mut KvRequests) {
+        let metas = self.view.appmeta_manager().meta.read().await;
+        for op in req.requests.iter_mut().filter_map(|req| req.op.as_mut()) {
+            let (ttl, key) = match op {
+                proto::kv::kv_request::Op::Set(set) => match set.kv.as_ref() {
+                    Some(kv) => (&mut set.ttl, &kv.key),
+                    None => continue,
+                },
+                proto::kv::kv_request::Op::Cas(cas) => (&mut cas.ttl, &cas.key),
+                proto::kv::kv_request::Op::Add(add) => (&mut add.ttl, &add.key),
+                _ => continue,
+            };
+            if *ttl == 0 {
+                *ttl = metas.kv_ttl(&req.app, key).unwrap_or(0);
+            }
+        }
+    }
+    /// look up the nodes holding the keys on master,
+    /// if `migrate_to` is set, the keys with ttls are recorded as held by the node and stale data is dropped,
+    /// the failure of dropping is returned with the positions
+    pub async fn kv_position(
+        &self,
+        keys: Vec<Vec<u8>>,
+        migrate_to: Option<(NodeID, Vec<u32>)>,
+    ) -> WSResult<(Vec<Option<NodeID>>, String)> {
+        let (migrate_to, ttls) = migrate_to.unwrap_or_default();
+        let resp = self
+            .rpc_caller_kv_position
+            .call(
+                self.view.p2p(),
+                self.view.p2p().nodes_config.get_master_node(),
+                KvPositionReq {
+                    keys,
+                    migrate_to,
+                    ttls,
+                },
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
+        let nodes = resp
+            .nodes
+            .into_iter()
+            .map(|node| if node == 0 { None } else { Some(node) })
+            .collect();
+        Ok((nodes, resp.error))
+    }
     // pub async fn get_by_app_fn(&self, key_range: KeyRange) -> WSResult<Vec<KvPair>> {}
     // pub async fn set_by_app_fn(
     //     &self,
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use ws_derive::LogicalModule;
+
+use crate::{
+    general::{
+        m_kv_store_engine::{KvBatch, KvStoreEngine},
+        network::{
+            m_p2p::{P2PModule, RPCHandler, RPCResponsor},
+            msg_pack::KvResponsesExt,
+            proto::{
+                self,
+                kv::{KvRequests, KvResponses},
+            },
+        },
+    },
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
+    util::JoinHandleWrapper,
+};
+
+logical_module_view_impl!(WorkerKvView);
+logical_module_view_impl!(WorkerKvView, p2p, P2PModule);
+logical_module_view_impl!(WorkerKvView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(WorkerKvView, worker_kv, Option<WorkerKv>);
+
+/// kv shard held by worker, master records which node holds the key
+#[derive(LogicalModule)]
+pub struct WorkerKv {
+    rpc_handler: RPCHandler<proto::kv::KvRequests>,
+    view: WorkerKvView,
+}
+
+#[async_trait]
+impl LogicalModule for WorkerKv {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            rpc_handler: RPCHandler::default(),
+            view: WorkerKvView::new(args.logical_modules_ref.clone()),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let view = self.view.clone();
+        self.rpc_handler
+            .regist(self.view.p2p(), move |responsor, reqs| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    view.worker_kv().handle_kv_requests(reqs, responsor).await;
+                });
+                Ok(())
+            });
+
+        Ok(vec![])
+    }
+}
+
+impl WorkerKv {
+    /// data operations routed by master or by `KvOptions::with_spec_node`,
+    /// events are not triggered here
+    async fn handle_kv_requests(&self, reqs: KvRequests, responsor: RPCResponsor<KvRequests>) {
+        tracing::debug!(
+            "worker handle kv requests from node {}",
+            responsor.node_id()
+        );
+        let kv_store_engine = self.view.kv_store_engine();
+        let resps = if reqs.txn {
+            // the positions are reported to master by the writer after commit
+            kv_store_engine
+                .apply_kv_txn(&reqs.requests, &HashMap::new(), None, |_| {
+                    KvBatch::default()
+                })
+                .map_or_else(
+                    || KvResponses::new_aborted(&reqs.requests),
+                    |responses| KvResponses {
+                        responses,
+                        aborted: false,
+                        error: String::new(),
+                    },
+                )
+        } else {
+            KvResponses {
+                responses: reqs
+                    .requests
+                    .into_iter()
+                    .map(|req| kv_store_engine.apply_kv_request(req))
+                    .collect(),
+                aborted: false,
+                error: String::new(),
+            }
+        };
+        kv_store_engine.flush();
+
+        if let Err(err) = responsor.send_resp(resps).await {
+            tracing::error!("worker handle kv requests error:{}", err);
+        }
+    }
+}
@@ -4,6 +4,7 @@ pub mod m_http_handler;
 pub mod m_instance_manager;
 pub mod m_kv_user_client;
 pub mod m_worker;
+pub mod m_worker_kv;
 pub mod wasm;
 pub mod wasm_host_funcs;
 // use axum::{extract::Path, routing::get, Router};
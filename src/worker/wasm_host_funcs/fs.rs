@@ -1,4 +1,7 @@
-use super::{utils, utils::m_fs, HostFuncRegister};
+use super::{
+    utils::{self, m_fs, HostFuncErr, HostFuncResult},
+    HostFuncRegister,
+};
 
 #[cfg(target_os = "macos")]
 use wasmer::{imports, Function, FunctionType, Imports};
@@ -9,23 +12,43 @@ use wasmedge_sdk::{
     NeverType, WasmValue,
 };
 
+fn try_open_file(caller: &Caller, args: &[WasmValue]) -> HostFuncResult<()> {
+    let fname = utils::u8slice(caller, args[0].to_i32(), args[1].to_i32())?;
+    let fname = utils::utf8(fname, "file name")?;
+    let res = utils::mutref::<i32>(caller, args[2].to_i32())?;
+    match m_fs().open_file(fname) {
+        Ok(f) => {
+            *res = f;
+            Ok(())
+        }
+        Err(err) => {
+            *res = -1;
+            Err(HostFuncErr::Failed(format!(
+                "open file {} failed: {:?}",
+                fname, err
+            )))
+        }
+    }
+}
+
 // fname_ptr, fname_len, fd_ptr
 type OpenFileArgs = (i32, i32, i32);
 #[cfg_attr(target_os = "linux", host_function)]
 fn open_file(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
-    let fname = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32());
-    let res = utils::mutref::<i32>(&caller, args[2].to_i32());
-    if let Ok(f) = m_fs().open_file(std::str::from_utf8(fname).unwrap()) {
-        *res = f;
-    } else {
-        tracing::error!(
-            "function failed to open file {}",
-            std::str::from_utf8(fname).unwrap()
-        );
-        *res = -1;
-    }
+    let res = try_open_file(&caller, &args);
+    Ok(utils::host_func_ret(&caller, "open_file", res))
+}
 
-    Ok(vec![])
+fn try_read_file_at(caller: &Caller, args: &[WasmValue]) -> HostFuncResult<()> {
+    let fd = args[0].to_i32();
+    let data = utils::mutu8sclice(caller, args[1].to_i32(), args[2].to_i32())?;
+    let offset = args[3].to_i32();
+    let retlen = utils::mutref::<i32>(caller, args[4].to_i32())?;
+    *retlen = m_fs()
+        .read_file_at(fd, offset, data)
+        .map_err(|err| HostFuncErr::Failed(format!("read file {} failed: {:?}", fd, err)))?
+        as i32;
+    Ok(())
 }
 
 // fd, data, len, offset, retlen_ptr
@@ -38,19 +61,19 @@ async fn read_file_at_async<T>(
     args: Vec<WasmValue>,
     _ctx: *mut T,
 ) -> Result<Vec<WasmValue>, HostFuncError> {
-    if let Err(err) = tokio::task::spawn_blocking(move || {
-        let fd = args[0].to_i32();
-        let data = utils::mutu8sclice(&caller, args[1].to_i32(), args[2].to_i32()).unwrap();
-        let offset = args[3].to_i32();
-        let retlen = utils::mutref::<i32>(&caller, args[4].to_i32());
-        *retlen = m_fs().read_file_at(fd, offset, data).unwrap() as i32;
+    match tokio::task::spawn_blocking(move || {
+        let res = try_read_file_at(&caller, &args);
+        (caller, res)
     })
     .await
     {
-        tracing::error!("function read_file_at_async: {}", err);
+        Ok((caller, res)) => Ok(utils::host_func_ret(&caller, "read_file_at", res)),
+        Err(err) => {
+            tracing::error!("function read_file_at_async: {}", err);
+            let err = HostFuncErr::Failed(err.to_string());
+            Ok(vec![WasmValue::from_i32(err.code())])
+        }
     }
-
-    Ok(vec![])
 }
 #[cfg(target_os = "macos")]
 fn read_file_at(fd: i32, data_ptr: i32, data_len: i32, offset: i32, retlen_ptr: i32) {
@@ -65,13 +88,13 @@ pub(super) struct FsFuncsRegister;
 impl HostFuncRegister for FsFuncsRegister {
     fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
         builder
-            .with_async_func::<ReadFileArgs, (), NeverType>(
+            .with_async_func::<ReadFileArgs, i32, NeverType>(
                 "read_file_at",
                 read_file_at_async,
                 None,
             )
             .unwrap()
-            .with_func::<OpenFileArgs, (), NeverType>("open_file", open_file, None)
+            .with_func::<OpenFileArgs, i32, NeverType>("open_file", open_file, None)
             .unwrap()
     }
 }
@@ -1,16 +1,26 @@
-use super::{utils, utils::m_kv_user_client, HostFuncRegister};
-use crate::general::{
-    kv_interface::{KvInterface, KvOptions},
-    network::{
-        msg_pack::KvResponseExt,
-        proto::{
-            self,
-            kv::{KeyRange, KvPair, KvRequest, KvRequests, KvResponses},
+use super::{
+    utils::{self, m_kv_user_client, HostFuncErr, HostFuncResult},
+    HostFuncRegister,
+};
+use crate::{
+    general::{
+        kv_interface::{KvInterface, KvOptions},
+        m_appmeta_manager::KvEventKind,
+        network::{
+            msg_pack::{KvResponseExt, KvResponsesExt},
+            proto::{
+                self,
+                kv::{KeyRange, KvPair, KvRequest, KvRequests, KvResponse, KvResponses},
+            },
         },
     },
+    worker::m_executor::EventCtx,
 };
 use moka::sync::Cache;
-use std::{sync::atomic::AtomicI32, time::Duration};
+use std::{
+    sync::atomic::AtomicI32,
+    time::{Duration, Instant},
+};
 #[cfg(target_os = "macos")]
 use wasmer::{imports, Function, FunctionType, Imports};
 
@@ -106,7 +116,8 @@ fn kv_get_len(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, Ho
 
 lazy_static::lazy_static! {
 
-    static ref RECENT_KV_CACHE: Cache<i32, KvResponses>=Cache::builder()
+    // operation types and responses of one batch
+    static ref RECENT_KV_CACHE: Cache<i32, (Vec<usize>, KvResponses)>=Cache::builder()
         .time_to_live(Duration::from_secs(10))
         // This cache will hold up to 32MiB of values.
         .max_capacity(10240)
@@ -118,107 +129,323 @@ const SET_ID: usize = 1;
 const GET_ID: usize = 2;
 const LOCK_ID: usize = 3;
 const DELETE_ID: usize = 4;
+const SCAN_ID: usize = 5;
+const DELETE_RANGE_ID: usize = 6;
+const CAS_ID: usize = 7;
+const ADD_ID: usize = 8;
+const SET_TTL_ID: usize = 9;
+const GUARD_ID: usize = 10;
+const GET_REVISION_ID: usize = 11;
+const CAS_VERSION_ID: usize = 12;
+/// set in the operation count if the batch is a transaction
+const TXN_FLAG: i32 = 1 << 30;
 
-type KvBatchOpe = (i32, i32, i32);
-#[cfg_attr(target_os = "linux", async_host_function)]
-async fn kv_batch_ope<T>(
-    caller: Caller,
-    args: Vec<WasmValue>,
-    _ctx: *mut T,
-) -> Result<Vec<WasmValue>, HostFuncError> {
-    let opes_arg_ptr = args[0].to_i32();
-    let opes_arg_len = args[1].to_i32();
-    let opes_id = utils::mutref::<i32>(&caller, args[2].to_i32());
-    let args = utils::i32slice(&caller, opes_arg_ptr, opes_arg_len);
-    let func_ctx = unsafe { utils::current_app_fn_ctx(&caller).0.as_mut() };
+// kind of kv event firing the fn
+const KV_EVENT_SET: i32 = 1;
+const KV_EVENT_NEW: i32 = 2;
+const KV_EVENT_CHANGE: i32 = 3;
+const KV_EVENT_DELETE: i32 = 4;
 
-    // request and response mem position
-    let ope_cnt = args[0];
-    let mut requests: Vec<KvRequest> = vec![];
+/// scan results are passed to guest as `[key_len: u32 le][key][value_len: u32 le][value]...`
+fn encoded_kvs_len(kvs: &[KvPair]) -> usize {
+    kvs.iter().map(|kv| 8 + kv.key.len() + kv.value.len()).sum()
+}
+
+fn encode_kvs(kvs: &[KvPair], buf: &mut [u8]) {
+    let mut offset = 0;
+    for kv in kvs {
+        for part in [&kv.key, &kv.value] {
+            buf[offset..offset + 4].copy_from_slice(&(part.len() as u32).to_le_bytes());
+            offset += 4;
+            buf[offset..offset + part.len()].copy_from_slice(part);
+            offset += part.len();
+        }
+    }
+}
+
+/// args: start_ptr, start_len, end_ptr, end_len, prefix
+fn range_arg(caller: &Caller, args: &[i32]) -> HostFuncResult<KeyRange> {
+    Ok(KeyRange {
+        start: utils::u8slice(caller, args[0], args[1])?.to_owned(),
+        end: utils::u8slice(caller, args[2], args[3])?.to_owned(),
+        prefix: args[4] != 0,
+    })
+}
+
+/// u64 passed as low and high 32 bits
+fn u64_arg(low: i32, high: i32) -> u64 {
+    ((high as u32 as u64) << 32) | (low as u32 as u64)
+}
+
+/// count of i32 args taken by the operation, including the type
+fn ope_args_len(ope_type: usize) -> Option<usize> {
+    match ope_type {
+        SET_ID => Some(5),
+        SET_TTL_ID => Some(6),
+        GET_ID => Some(4),
+        LOCK_ID => Some(5),
+        DELETE_ID => Some(3),
+        SCAN_ID => Some(7),
+        DELETE_RANGE_ID => Some(6),
+        CAS_ID => Some(8),
+        ADD_ID => Some(6),
+        GUARD_ID => Some(6),
+        GET_REVISION_ID => Some(6),
+        CAS_VERSION_ID => Some(8),
+        _ => None,
+    }
+}
+
+/// operations in args, each is the type followed by its args
+fn split_opes(args: &[i32], ope_cnt: i32) -> HostFuncResult<Vec<(usize, &[i32])>> {
+    let mut opes = vec![];
     let mut cur_idx = 1;
-    // tracing::debug!("args:{:?}", args);
-    // Construct the requests
     for _ in 0..ope_cnt {
-        let ope_type = args[cur_idx];
-        match ope_type as usize {
-            // set
-            SET_ID => {
-                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
-                let value = utils::u8slice(&caller, args[cur_idx + 3], args[cur_idx + 4]);
-                requests.push(KvRequest {
-                    op: Some(proto::kv::kv_request::Op::Set(
-                        proto::kv::kv_request::KvPutRequest {
-                            kv: Some(KvPair {
-                                key: key.to_owned(),
-                                value: value.to_owned(),
-                            }),
-                        },
-                    )),
-                });
-                cur_idx += 5;
-            }
-            // get
-            GET_ID => {
-                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
-                // tracing::debug!("ptr:{} len:{} get key:{:?}", args[cur_idx + 1], args[cur_idx + 2],key);
-                requests.push(KvRequest {
-                    op: Some(proto::kv::kv_request::Op::Get(
-                        proto::kv::kv_request::KvGetRequest {
-                            range: Some(KeyRange {
-                                start: key.to_owned(),
-                                end: vec![],
-                            }),
-                        },
-                    )),
-                });
-                cur_idx += 4;
-            }
-            // lock
-            LOCK_ID => {
-                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
-                // // first bit
-                // let read_or_write = args[cur_idx + 3] & 1 == 1;
-                // <0 means get
-                let release_id = args[cur_idx + 3];
-                requests.push(KvRequest {
-                    op: Some(proto::kv::kv_request::Op::Lock(
-                        proto::kv::kv_request::KvLockRequest {
-                            read_or_write: false,
-                            release_id: if release_id < 0 {
-                                vec![]
-                            } else {
-                                vec![release_id as u32]
-                            },
-                            range: Some(KeyRange {
-                                start: key.to_owned(),
-                                end: vec![],
-                            }),
-                        },
-                    )),
-                });
-                cur_idx += 5;
-            }
-            DELETE_ID => {
-                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
-                requests.push(KvRequest {
-                    op: Some(proto::kv::kv_request::Op::Delete(
-                        proto::kv::kv_request::KvDeleteRequest {
-                            range: Some(KeyRange {
-                                start: key.to_owned(),
-                                end: vec![],
-                            }),
-                        },
-                    )),
-                });
-                cur_idx += 3;
-            }
-            _ => {
-                panic!("not implemented, reqs{:?},{:X}", requests, ope_type);
+        let ope_type = *args
+            .get(cur_idx)
+            .ok_or_else(|| HostFuncErr::Arg(format!("missing operation {}", opes.len())))?
+            as usize;
+        let ope = ope_args_len(ope_type)
+            .and_then(|len| args.get(cur_idx..cur_idx + len))
+            .ok_or_else(|| {
+                HostFuncErr::Arg(format!(
+                    "invalid operation {} of type {:X}",
+                    opes.len(),
+                    ope_type
+                ))
+            })?;
+        opes.push((ope_type, ope));
+        cur_idx += ope.len();
+    }
+    Ok(opes)
+}
+
+fn ope_request(caller: &Caller, ope_type: usize, ope: &[i32]) -> HostFuncResult<KvRequest> {
+    let op = match ope_type {
+        // set, with ttl in seconds
+        SET_ID | SET_TTL_ID => {
+            let key = utils::u8slice(caller, ope[1], ope[2])?;
+            let value = utils::u8slice(caller, ope[3], ope[4])?;
+            proto::kv::kv_request::Op::Set(proto::kv::kv_request::KvPutRequest {
+                kv: Some(KvPair {
+                    key: key.to_owned(),
+                    value: value.to_owned(),
+                    version: 0,
+                }),
+                ttl: if ope_type == SET_TTL_ID {
+                    ope[5] as u32
+                } else {
+                    0
+                },
+            })
+        }
+        // revision is passed as low and high 32 bits
+        GET_ID | GET_REVISION_ID => {
+            let key = utils::u8slice(caller, ope[1], ope[2])?;
+            // tracing::debug!("ptr:{} len:{} get key:{:?}", ope[1], ope[2],key);
+            proto::kv::kv_request::Op::Get(proto::kv::kv_request::KvGetRequest {
+                range: Some(KeyRange {
+                    start: key.to_owned(),
+                    end: vec![],
+                    prefix: false,
+                }),
+                revision: if ope_type == GET_REVISION_ID {
+                    u64_arg(ope[3], ope[4])
+                } else {
+                    0
+                },
+            })
+        }
+        LOCK_ID => {
+            let key = utils::u8slice(caller, ope[1], ope[2])?;
+            // // first bit
+            // let read_or_write = ope[3] & 1 == 1;
+            // <0 means get
+            let release_id = ope[3];
+            proto::kv::kv_request::Op::Lock(proto::kv::kv_request::KvLockRequest {
+                read_or_write: false,
+                release_id: if release_id < 0 {
+                    vec![]
+                } else {
+                    vec![release_id as u32]
+                },
+                range: Some(KeyRange {
+                    start: key.to_owned(),
+                    end: vec![],
+                    prefix: false,
+                }),
+            })
+        }
+        DELETE_ID => {
+            let key = utils::u8slice(caller, ope[1], ope[2])?;
+            proto::kv::kv_request::Op::Delete(proto::kv::kv_request::KvDeleteRequest {
+                range: Some(KeyRange {
+                    start: key.to_owned(),
+                    end: vec![],
+                    prefix: false,
+                }),
+            })
+        }
+        SCAN_ID => proto::kv::kv_request::Op::Get(proto::kv::kv_request::KvGetRequest {
+            range: Some(range_arg(caller, &ope[1..6])?),
+            revision: 0,
+        }),
+        DELETE_RANGE_ID => {
+            proto::kv::kv_request::Op::Delete(proto::kv::kv_request::KvDeleteRequest {
+                range: Some(range_arg(caller, &ope[1..6])?),
+            })
+        }
+        // expected_len < 0 means expect absent
+        CAS_ID => {
+            let key = utils::u8slice(caller, ope[1], ope[2])?;
+            let expect_absent = ope[4] < 0;
+            let expected = if expect_absent {
+                vec![]
+            } else {
+                utils::u8slice(caller, ope[3], ope[4])?.to_owned()
+            };
+            let new_value = utils::u8slice(caller, ope[5], ope[6])?;
+            proto::kv::kv_request::Op::Cas(proto::kv::kv_request::KvCasRequest {
+                key: key.to_owned(),
+                expected,
+                expect_absent,
+                new_value: new_value.to_owned(),
+                expected_version: 0,
+                ttl: 0,
+            })
+        }
+        // expected version is passed as low and high 32 bits, 0 means expect absent
+        CAS_VERSION_ID => {
+            let key = utils::u8slice(caller, ope[1], ope[2])?;
+            let expected_version = u64_arg(ope[3], ope[4]);
+            let new_value = utils::u8slice(caller, ope[5], ope[6])?;
+            proto::kv::kv_request::Op::Cas(proto::kv::kv_request::KvCasRequest {
+                key: key.to_owned(),
+                expected: vec![],
+                expect_absent: expected_version == 0,
+                new_value: new_value.to_owned(),
+                expected_version,
+                ttl: 0,
+            })
+        }
+        // delta is passed as low and high 32 bits
+        ADD_ID => {
+            let key = utils::u8slice(caller, ope[1], ope[2])?;
+            let delta = u64_arg(ope[3], ope[4]) as i64;
+            proto::kv::kv_request::Op::Add(proto::kv::kv_request::KvAddRequest {
+                key: key.to_owned(),
+                delta,
+                ttl: 0,
+            })
+        }
+        // expected_len < 0 means expect absent
+        GUARD_ID => {
+            let key = utils::u8slice(caller, ope[1], ope[2])?;
+            let expect_absent = ope[4] < 0;
+            let expected = if expect_absent {
+                vec![]
+            } else {
+                utils::u8slice(caller, ope[3], ope[4])?.to_owned()
+            };
+            proto::kv::kv_request::Op::Guard(proto::kv::kv_request::KvGuardRequest {
+                key: key.to_owned(),
+                expected,
+                expect_absent,
+            })
+        }
+        _ => {
+            return Err(HostFuncErr::Arg(format!(
+                "unknown operation {:X}",
+                ope_type
+            )))
+        }
+    };
+    Ok(KvRequest { op: Some(op) })
+}
+
+/// write back the lens and results of the operation to guest
+fn write_back_ope(
+    caller: &Caller,
+    ope_type: usize,
+    ope: &[i32],
+    resp: &KvResponse,
+) -> HostFuncResult<()> {
+    let unexpected = || HostFuncErr::Failed(format!("unexpected response {:?}", resp));
+    match ope_type {
+        // write back whether the guard passed
+        GUARD_ID => {
+            let (succ, _) = resp.atomic_res().ok_or_else(unexpected)?;
+            *utils::mutref::<i32>(caller, ope[5])? = succ as i32;
+        }
+        // write back len and version of the value, -1 and 0 if not exist
+        GET_ID | GET_REVISION_ID => {
+            let kvs = resp.common_kvs().ok_or_else(unexpected)?;
+            let res_ptr = if ope_type == GET_ID { ope[3] } else { ope[5] };
+            *utils::mutref::<[i64; 2]>(caller, res_ptr)? = kvs
+                .first()
+                .map_or([-1, 0], |kv| [kv.value.len() as i64, kv.version as i64]);
+        }
+        LOCK_ID => {
+            if let Some(lockid) = resp.lock_id() {
+                // lock id is allocated by the remote when call the lock
+                *utils::mutref::<u32>(caller, ope[4])? = lockid;
+            } else {
+                // unlock, no response
             }
         }
+        SCAN_ID => {
+            let kvs = resp.common_kvs().ok_or_else(unexpected)?;
+            *utils::mutref::<i32>(caller, ope[6])? = encoded_kvs_len(kvs) as i32;
+        }
+        // write back succ and len of current value
+        CAS_ID | CAS_VERSION_ID => {
+            let (succ, kvs) = resp.atomic_res().ok_or_else(unexpected)?;
+            *utils::mutref::<[i32; 2]>(caller, ope[7])? = [
+                succ as i32,
+                kvs.first().map_or(-1, |kv| kv.value.len() as i32),
+            ];
+        }
+        // write back succ and the new integer value
+        ADD_ID => {
+            let (succ, kvs) = resp.atomic_res().ok_or_else(unexpected)?;
+            let value = kvs
+                .first()
+                .and_then(|kv| std::str::from_utf8(&kv.value).ok())
+                .and_then(|v| v.parse::<i64>().ok())
+                .unwrap_or(0);
+            *utils::mutref::<[i64; 2]>(caller, ope[5])? = [succ as i64, value];
+        }
+        // set, delete, delete range
+        _ => {}
     }
+    Ok(())
+}
+
+async fn try_kv_batch_ope(caller: &Caller, args: &[WasmValue]) -> HostFuncResult<()> {
+    let opes_arg_ptr = args[0].to_i32();
+    let opes_arg_len = args[1].to_i32();
+    let opes_id = utils::mutref::<i32>(caller, args[2].to_i32())?;
+    let args = utils::i32slice(caller, opes_arg_ptr, opes_arg_len)?;
+    let func_ctx = unsafe { utils::current_app_fn_ctx(caller)?.0.as_mut() };
+
+    // request and response mem position
+    let head = *args
+        .first()
+        .ok_or_else(|| HostFuncErr::Arg("empty operations".to_owned()))?;
+    let txn = head & TXN_FLAG != 0;
+    let opes = split_opes(args, head & !TXN_FLAG)?;
+    // tracing::debug!("args:{:?}", args);
+    // Construct the requests
+    let requests = opes
+        .iter()
+        .map(|&(ope_type, ope)| ope_request(caller, ope_type, ope))
+        .collect::<HostFuncResult<Vec<_>>>()?;
     // tracing::debug!("requests:{:?}", requests);
-    match m_kv_user_client()
+    let mut opt = KvOptions::new();
+    if let Some(deadline) = func_ctx.deadline {
+        opt = opt.with_timeout(deadline.saturating_duration_since(Instant::now()));
+    }
+    let res = m_kv_user_client()
         .call(
             KvRequests {
                 requests,
@@ -228,94 +455,141 @@ async fn kv_batch_ope<T>(
                     .event_ctx
                     .take_prev_kv_opeid()
                     .map_or(-1, |v| v as i64),
+                txn,
+                workflow_id: func_ctx.workflow_id,
+                workflow_task: func_ctx.workflow_task,
             },
-            KvOptions::new(),
+            opt,
         )
         .await
-    {
-        Ok(res) => {
-            let id = NEXT_CACHE_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            // Write back the results to wasm runtime
-            let mut cur_idx = 1;
-            let mut resps = res.responses.iter();
-            // Construct the requests
-            for _ in 0..ope_cnt {
-                let ope_type = args[cur_idx];
-                match ope_type as usize {
-                    // set
-                    SET_ID => {
-                        let _ = resps.next().unwrap();
-                        cur_idx += 5;
-                    }
-                    // get
-                    GET_ID => {
-                        let kvs = resps.next().unwrap().common_kvs().unwrap();
-                        // get len
-                        *utils::mutref::<i32>(&caller, args[cur_idx + 3]) = if kvs.len() > 0 {
-                            kvs.get(0).unwrap().value.len() as i32
-                        } else {
-                            -1
-                        };
-                        cur_idx += 4;
-                    }
-                    // lock
-                    LOCK_ID => {
-                        if let Some(lockid) = resps.next().unwrap().lock_id() {
-                            // lock id is allocated by the remote when call the lock
-                            *utils::mutref::<u32>(&caller, args[cur_idx + 4]) = lockid;
-                        } else {
-                            // unlock, no response
-                        }
-                        cur_idx += 5;
-                    }
-                    DELETE_ID => {
-                        let _ = resps.next().unwrap();
-                        cur_idx += 3;
-                    }
-                    _ => {
-                        panic!("not implemented");
-                    }
-                }
+        .map_err(|err| HostFuncErr::Failed(format!("kv batch ope error:{}", err)))?;
+    if res.responses.len() != opes.len() {
+        return Err(HostFuncErr::Failed(format!(
+            "{} responses for {} operations",
+            res.responses.len(),
+            opes.len()
+        )));
+    }
+    // Write back the results to wasm runtime
+    // empty results of failed operations mustn't be taken as values
+    if let Some(err) = res.err() {
+        return Err(HostFuncErr::Failed(format!("kv batch ope failed:{}", err)));
+    }
+    for (&(ope_type, ope), resp) in opes.iter().zip(res.responses.iter()) {
+        write_back_ope(caller, ope_type, ope, resp)?;
+    }
+    let id = NEXT_CACHE_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let ope_types = opes.iter().map(|&(ope_type, _)| ope_type).collect();
+    RECENT_KV_CACHE.insert(id, (ope_types, res));
+    *opes_id = id;
+    Ok(())
+}
+
+type KvBatchOpe = (i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn kv_batch_ope<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let res = try_kv_batch_ope(&caller, &args).await;
+    Ok(utils::host_func_ret(&caller, "kv_batch_ope", res))
+}
+
+fn try_kv_batch_res(caller: &Caller, args: &[WasmValue]) -> HostFuncResult<()> {
+    let id = args[0].to_i32();
+    let Some((ope_types, res)) = RECENT_KV_CACHE.get(&id) else {
+        return Err(HostFuncErr::Failed(format!("kv batch {} expired", id)));
+    };
+    let args_ptr = args[1].to_i32();
+    let args_len = args[2].to_i32();
+    let args = utils::i32slice(caller, args_ptr, args_len)?;
+    if args.len() % 2 != 0 {
+        return Err(HostFuncErr::Arg(
+            "args should be pairs of operation index and buffer".to_owned(),
+        ));
+    }
+    for arg in args.chunks_exact(2) {
+        let (ope_idx, buf_ptr) = (arg[0], arg[1]);
+        let (Some(&ope_type), Some(res)) = (
+            ope_types.get(ope_idx as usize),
+            res.responses.get(ope_idx as usize),
+        ) else {
+            return Err(HostFuncErr::Arg(format!(
+                "invalid operation index {}",
+                ope_idx
+            )));
+        };
+        if ope_type == SCAN_ID {
+            let kvs: &[KvPair] = res.common_kvs().map_or(&[], |kvs| kvs);
+            let len = encoded_kvs_len(kvs);
+            encode_kvs(kvs, utils::mutu8sclice(caller, buf_ptr, len as i32)?);
+        } else if let Some((_, kvs)) = res.atomic_res() {
+            if let Some(kv) = kvs.first() {
+                utils::mutu8sclice(caller, buf_ptr, kv.value.len() as i32)?
+                    .copy_from_slice(&kv.value);
             }
-            RECENT_KV_CACHE.insert(id, res);
-            *opes_id = id;
-        }
-        Err(err) => {
-            tracing::error!("kv batch ope error:{}", err);
+        } else if let Some(kvs) = res.common_kvs() {
+            if let Some(kv) = kvs.first() {
+                utils::mutu8sclice(caller, buf_ptr, kv.value.len() as i32)?
+                    .copy_from_slice(&kv.value);
+            }
+        } else {
+            // lock or set, nothing to write back
         }
     }
-    Ok(vec![])
+    Ok(())
 }
 
 #[host_function]
 fn kv_batch_res(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
-    let id = args[0].to_i32();
+    let res = try_kv_batch_res(&caller, &args);
+    Ok(utils::host_func_ret(&caller, "kv_batch_res", res))
+}
 
-    if let Some(res) = RECENT_KV_CACHE.get(&id) {
-        let args_ptr = args[1].to_i32();
-        let args_len = args[2].to_i32();
-        let args = utils::i32slice(&caller, args_ptr, args_len);
-        let mut cur_idx = 0;
-        while cur_idx < args.len() {
-            let ope_idx = args[cur_idx];
-            if let Some(res) = res.responses.get(ope_idx as usize) {
-                if let Some(kvs) = res.common_kvs() {
-                    if let Some(kv) = kvs.get(0) {
-                        let slice =
-                            utils::mutu8sclice(&caller, args[cur_idx + 1], kv.value.len() as i32)
-                                .unwrap();
-                        slice.copy_from_slice(kvs.get(0).unwrap().value.as_slice());
-                    }
-                } else if let Some(_lock_id) = res.lock_id() {
-                    // do nothing
-                } else {
-                    panic!("not implemented");
-                }
-            }
-            cur_idx += 2;
-        }
-    }
-    Ok(vec![])
+fn try_kv_event_kind(caller: &Caller, args: &[WasmValue]) -> HostFuncResult<()> {
+    let kind = utils::mutref::<i32>(caller, args[0].to_i32())?;
+    let func_ctx = unsafe { utils::current_app_fn_ctx(caller)?.0.as_ref() };
+    *kind = match &func_ctx.event_ctx {
+        EventCtx::Kv { kind, .. } => match kind {
+            KvEventKind::Set => KV_EVENT_SET,
+            KvEventKind::New => KV_EVENT_NEW,
+            KvEventKind::Change => KV_EVENT_CHANGE,
+            KvEventKind::Delete => KV_EVENT_DELETE,
+        },
+        _ => 0,
+    };
+    Ok(())
+}
+
+fn try_kv_event_revision(caller: &Caller, args: &[WasmValue]) -> HostFuncResult<()> {
+    let revision = utils::mutref::<u64>(caller, args[0].to_i32())?;
+    let func_ctx = unsafe { utils::current_app_fn_ctx(caller)?.0.as_ref() };
+    *revision = match &func_ctx.event_ctx {
+        EventCtx::Kv { revision, .. } => *revision,
+        _ => 0,
+    };
+    Ok(())
+}
+
+// fn kv_event_revision(revision: &mut u64);
+/// version written by the set firing current fn, 0 for delete or not fired by kv event
+#[host_function]
+fn kv_event_revision(
+    caller: Caller,
+    args: Vec<WasmValue>,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let res = try_kv_event_revision(&caller, &args);
+    Ok(utils::host_func_ret(&caller, "kv_event_revision", res))
+}
+
+// fn kv_event_kind(kind: &mut i32);
+type KvEventKindArgs = i32;
+/// kind of kv event firing current fn, 0 if not fired by kv event
+#[host_function]
+fn kv_event_kind(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let res = try_kv_event_kind(&caller, &args);
+    Ok(utils::host_func_ret(&caller, "kv_event_kind", res))
 }
 
 pub(super) struct KvFuncsRegister;
@@ -323,9 +597,13 @@ pub(super) struct KvFuncsRegister;
 impl HostFuncRegister for KvFuncsRegister {
     fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
         builder
-            .with_async_func::<KvBatchOpe, (), NeverType>("kv_batch_ope", kv_batch_ope, None)
+            .with_async_func::<KvBatchOpe, i32, NeverType>("kv_batch_ope", kv_batch_ope, None)
             .unwrap()
-            .with_func::<KvBatchOpe, (), NeverType>("kv_batch_res", kv_batch_res, None)
+            .with_func::<KvBatchOpe, i32, NeverType>("kv_batch_res", kv_batch_res, None)
+            .unwrap()
+            .with_func::<KvEventKindArgs, i32, NeverType>("kv_event_kind", kv_event_kind, None)
+            .unwrap()
+            .with_func::<i32, i32, NeverType>("kv_event_revision", kv_event_revision, None)
             .unwrap()
         // .with_async_func::<KvGetLenArgs, (), NeverType>("kv_get_len", kv_get_len_async, None)
         // .unwrap()
@@ -333,3 +611,39 @@ impl HostFuncRegister for KvFuncsRegister {
         // .unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_opes() {
+        // set key, get key
+        let args = [2, SET_ID as i32, 8, 1, 16, 2, GET_ID as i32, 8, 1, 24];
+        let opes = split_opes(&args, 2).unwrap();
+        assert_eq!(opes.len(), 2);
+        assert_eq!(opes[0], (SET_ID, &args[1..6]));
+        assert_eq!(opes[1], (GET_ID, &args[6..10]));
+
+        // truncated get
+        assert!(matches!(
+            split_opes(&args[..8], 2),
+            Err(HostFuncErr::Arg(_))
+        ));
+        // more operations than args
+        assert!(matches!(split_opes(&args, 3), Err(HostFuncErr::Arg(_))));
+        // unknown operation type
+        assert!(matches!(
+            split_opes(&[1, 0x77, 0, 0], 1),
+            Err(HostFuncErr::Arg(_))
+        ));
+    }
+
+    #[test]
+    fn test_u64_arg() {
+        let revision: u64 = (7 << 32) | 0x8000_0001;
+        assert_eq!(u64_arg(revision as i32, (revision >> 32) as i32), revision);
+        let delta: i64 = -5;
+        assert_eq!(u64_arg(delta as i32, (delta >> 32) as i32) as i64, delta);
+    }
+}
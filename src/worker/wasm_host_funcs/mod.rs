@@ -13,10 +13,12 @@ mod utils {
 
     use std::ptr::NonNull;
 
-    use wasmedge_sdk::{Caller, CallingFrame, Instance, Memory};
+    use thiserror::Error;
+    use wasmedge_sdk::{Caller, CallingFrame, Instance, Memory, WasmValue};
 
     use crate::{
         general::m_os::OperatingSystem,
+        result::WsFuncError,
         sys::LogicalModulesRef,
         util::SendNonNull,
         worker::{
@@ -48,57 +50,136 @@ mod utils {
         }
     }
 
-    pub fn u8slice<'a>(caller: &impl WasmCtx, ptr: i32, len: i32) -> &'a [u8] {
-        // tracing::debug!("u8slice ptr: {}, len: {}", ptr, len);
-        let mem = caller
-            .memory(0)
-            .unwrap()
-            .data_pointer(ptr as u32, len as u32)
-            .unwrap();
-        let res = unsafe { std::slice::from_raw_parts(mem, len as usize) };
-        res
+    /// returned to guest when the host func succeeded
+    pub const HOST_OK: i32 = 0;
+
+    /// failure of host func, returned to guest as a negative code
+    #[derive(Debug, Error)]
+    pub enum HostFuncErr {
+        /// pointer or length out of guest memory, or the pointer is not aligned
+        #[error("invalid memory at {ptr} of len {len}")]
+        Memory { ptr: i32, len: i32 },
+        /// malformed arguments, like unknown kv operation or name not in utf8
+        #[error("invalid arg: {0}")]
+        Arg(String),
+        /// the operation failed on host, like the kv request, guest may handle it
+        #[error("{0}")]
+        Failed(String),
     }
 
-    pub fn i32slice<'a>(caller: &impl WasmCtx, ptr: i32, len: i32) -> &'a [i32] {
-        let mem = caller
+    impl HostFuncErr {
+        pub fn code(&self) -> i32 {
+            match self {
+                HostFuncErr::Memory { .. } => -1,
+                HostFuncErr::Arg(_) => -2,
+                HostFuncErr::Failed(_) => -3,
+            }
+        }
+        /// guest passed something invalid, the invocation is failed
+        fn is_guest_fault(&self) -> bool {
+            !matches!(self, HostFuncErr::Failed(_))
+        }
+    }
+
+    pub type HostFuncResult<T> = Result<T, HostFuncErr>;
+
+    /// the code returned to guest, guest faults are recorded in the running fn
+    pub fn host_func_ret(
+        caller: &impl WasmCtx,
+        host_func: &str,
+        res: HostFuncResult<()>,
+    ) -> Vec<WasmValue> {
+        let code = match res {
+            Ok(()) => HOST_OK,
+            Err(err) => {
+                tracing::warn!("host func {} failed: {}", host_func, err);
+                if err.is_guest_fault() {
+                    if let Ok(fn_ctx) = current_app_fn_ctx(caller) {
+                        let fn_ctx = unsafe { &mut *fn_ctx.0.as_ptr() };
+                        // keep the first one
+                        if fn_ctx.host_err.is_none() {
+                            fn_ctx.host_err = Some(WsFuncError::HostFuncFailed {
+                                app: fn_ctx.app.clone(),
+                                func: fn_ctx.func.clone(),
+                                host_func: host_func.to_owned(),
+                                msg: err.to_string(),
+                            });
+                        }
+                    }
+                }
+                err.code()
+            }
+        };
+        vec![WasmValue::from_i32(code)]
+    }
+
+    fn memory(caller: &impl WasmCtx, ptr: i32, len: i32) -> HostFuncResult<Memory> {
+        if ptr < 0 || len < 0 {
+            return Err(HostFuncErr::Memory { ptr, len });
+        }
+        caller
             .memory(0)
-            .unwrap()
+            .ok_or_else(|| HostFuncErr::Failed("guest has no memory".to_owned()))
+    }
+
+    /// `len` bytes at `ptr` of guest memory
+    pub fn u8slice<'a>(caller: &impl WasmCtx, ptr: i32, len: i32) -> HostFuncResult<&'a [u8]> {
+        // tracing::debug!("u8slice ptr: {}, len: {}", ptr, len);
+        let mem = memory(caller, ptr, len)?
             .data_pointer(ptr as u32, len as u32)
-            .unwrap();
-        unsafe { std::slice::from_raw_parts(mem as *const i32, len as usize) }
+            .map_err(|_| HostFuncErr::Memory { ptr, len })?;
+        Ok(unsafe { std::slice::from_raw_parts(mem, len as usize) })
     }
 
-    pub fn mutu8sclice<'a>(caller: &impl WasmCtx, ptr: i32, len: i32) -> Option<&'a mut [u8]> {
-        if let Ok(mem) = caller
-            .memory(0)
-            .unwrap()
-            .data_pointer_mut(ptr as u32, len as u32)
-        {
-            Some(unsafe { std::slice::from_raw_parts_mut(mem, len as usize) })
-        } else {
-            None
+    /// `len` i32s at `ptr` of guest memory
+    pub fn i32slice<'a>(caller: &impl WasmCtx, ptr: i32, len: i32) -> HostFuncResult<&'a [i32]> {
+        let bytes = len.checked_mul(4).ok_or(HostFuncErr::Memory { ptr, len })?;
+        if ptr % 4 != 0 {
+            return Err(HostFuncErr::Memory { ptr, len });
         }
+        let mem = memory(caller, ptr, bytes)?
+            .data_pointer(ptr as u32, bytes as u32)
+            .map_err(|_| HostFuncErr::Memory { ptr, len })?;
+        Ok(unsafe { std::slice::from_raw_parts(mem as *const i32, len as usize) })
     }
 
-    pub fn mutref<'a, T: Sized>(caller: &impl WasmCtx, ptr: i32) -> &'a mut T {
-        unsafe {
-            &mut *(caller
-                .memory(0)
-                .unwrap()
-                .data_pointer_mut(ptr as u32, std::mem::size_of::<T>() as u32)
-                .unwrap() as *mut T)
+    pub fn mutu8sclice<'a>(
+        caller: &impl WasmCtx,
+        ptr: i32,
+        len: i32,
+    ) -> HostFuncResult<&'a mut [u8]> {
+        let mem = memory(caller, ptr, len)?
+            .data_pointer_mut(ptr as u32, len as u32)
+            .map_err(|_| HostFuncErr::Memory { ptr, len })?;
+        Ok(unsafe { std::slice::from_raw_parts_mut(mem, len as usize) })
+    }
+
+    pub fn mutref<'a, T: Sized>(caller: &impl WasmCtx, ptr: i32) -> HostFuncResult<&'a mut T> {
+        let len = std::mem::size_of::<T>() as i32;
+        // guest memory starts at page boundary, so the offset decides the alignment
+        if ptr % std::mem::align_of::<T>() as i32 != 0 {
+            return Err(HostFuncErr::Memory { ptr, len });
         }
+        let mem = memory(caller, ptr, len)?
+            .data_pointer_mut(ptr as u32, len as u32)
+            .map_err(|_| HostFuncErr::Memory { ptr, len })?;
+        Ok(unsafe { &mut *(mem as *mut T) })
+    }
+
+    pub fn utf8<'a>(bytes: &'a [u8], what: &str) -> HostFuncResult<&'a str> {
+        std::str::from_utf8(bytes).map_err(|err| HostFuncErr::Arg(format!("{} {}", what, err)))
     }
 
-    pub fn current_app_fn_ctx(caller: &impl WasmCtx) -> SendNonNull<FunctionCtx> {
-        let app_fn = SendNonNull(NonNull::from(
-            m_instance_manager()
-                .instance_running_function
-                .read()
-                .get(&caller.instance().unwrap().name().unwrap())
-                .unwrap(),
-        ));
-        app_fn
+    pub fn current_app_fn_ctx(caller: &impl WasmCtx) -> HostFuncResult<SendNonNull<FunctionCtx>> {
+        let name = caller
+            .instance()
+            .and_then(|instance| instance.name())
+            .ok_or_else(|| HostFuncErr::Failed("caller has no instance name".to_owned()))?;
+        let running = m_instance_manager().instance_running_function.read();
+        let fn_ctx = running
+            .get(&name)
+            .ok_or_else(|| HostFuncErr::Failed(format!("no fn running on {}", name)))?;
+        Ok(SendNonNull(NonNull::from(fn_ctx)))
     }
 
     lazy_static::lazy_static! {
@@ -1,4 +1,8 @@
-use super::{utils, HostFuncRegister};
+use super::{
+    utils::{self, HostFuncResult},
+    HostFuncRegister,
+};
+use crate::worker::m_executor::FnResult;
 
 #[cfg(target_os = "macos")]
 use wasmer::{imports, Function, FunctionType, Imports};
@@ -8,15 +12,50 @@ use wasmedge_sdk::{
     error::HostFuncError, host_function, Caller, ImportObjectBuilder, NeverType, WasmValue,
 };
 
-// fname_ptr, fname_len, fd_ptr
+fn try_write_result(caller: &Caller, args: &[WasmValue]) -> HostFuncResult<()> {
+    let text = utils::u8slice(caller, args[0].to_i32(), args[1].to_i32())?;
+    let text = utils::utf8(text, "result")?.to_owned();
+    unsafe { utils::current_app_fn_ctx(caller)?.0.as_mut() }.res = Some(FnResult::Text(text));
+    Ok(())
+}
+
+// res_ptr, res_len
 type WriteResultArgs = (i32, i32);
 #[cfg_attr(target_os = "linux", host_function)]
 fn write_result(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
-    let fname = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32());
-    unsafe { utils::current_app_fn_ctx(&caller).0.as_mut() }.res =
-        Some(std::str::from_utf8(fname).unwrap().to_string());
+    let res = try_write_result(&caller, &args);
+    Ok(utils::host_func_ret(&caller, "write_result", res))
+}
 
-    Ok(vec![])
+fn try_write_http_response(caller: &Caller, args: &[WasmValue]) -> HostFuncResult<()> {
+    let headers = utils::u8slice(caller, args[1].to_i32(), args[2].to_i32())?;
+    let headers = String::from_utf8_lossy(headers)
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_owned(), value.trim().to_owned()))
+        })
+        .collect();
+    let body = utils::u8slice(caller, args[3].to_i32(), args[4].to_i32())?.to_owned();
+    unsafe { utils::current_app_fn_ctx(caller)?.0.as_mut() }.res = Some(FnResult::Http {
+        // invalid status is answered with 500
+        status: u16::try_from(args[0].to_i32()).unwrap_or(0),
+        headers,
+        body,
+    });
+    Ok(())
+}
+
+// status, headers_ptr, headers_len, body_ptr, body_len
+type WriteHttpResponseArgs = (i32, i32, i32, i32, i32);
+/// headers are lines of `name: value`
+#[cfg_attr(target_os = "linux", host_function)]
+fn write_http_response(
+    caller: Caller,
+    args: Vec<WasmValue>,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let res = try_write_http_response(&caller, &args);
+    Ok(utils::host_func_ret(&caller, "write_http_response", res))
 }
 
 pub(super) struct ResultFuncsRegister;
@@ -24,7 +63,13 @@ pub(super) struct ResultFuncsRegister;
 impl HostFuncRegister for ResultFuncsRegister {
     fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
         builder
-            .with_func::<WriteResultArgs, (), NeverType>("write_result", write_result, None)
+            .with_func::<WriteResultArgs, i32, NeverType>("write_result", write_result, None)
+            .unwrap()
+            .with_func::<WriteHttpResponseArgs, i32, NeverType>(
+                "write_http_response",
+                write_http_response,
+                None,
+            )
             .unwrap()
     }
 }
@@ -32,6 +32,49 @@ export class ServiceBasic {
     ){}
 }
 
+export class KvWatchEvent {
+    constructor(
+        public key:string,
+        public delete:boolean,
+        public version:number,
+        public revision:number,
+    ){}
+}
+
+export class DeadLetter {
+    constructor(
+        public id:string,
+        public app:string,
+        public func:string,
+        public trigger:string,
+        public key:string,
+        public attempts:number,
+        public nodes:number[],
+        public err:string,
+        public fail_time:number,
+    ){}
+}
+
+export class WorkflowTask {
+    constructor(
+        public task:number,
+        public parent:number,
+        public target:string,
+        public node:number,
+        public start_time:number,
+        public end_time:number,
+        public output:string,
+        public error:string,
+    ){}
+}
+
+export class AppVersion {
+    constructor(
+        public version:number,
+        public deploy_time:number,
+    ){}
+}
+
 
 export class AddServiceRespSucc {
     constructor(
@@ -192,6 +235,12 @@ export class RunServiceActionRespFail {
     ){}
 }
 
+export class RunServiceActionRespAsync {
+    constructor(
+        public invocation_id:string,
+    ){}
+}
+
 export class RunServiceActionResp{
     constructor(
         private kernel: any,
@@ -212,6 +261,13 @@ export class RunServiceActionResp{
         return undefined
     }
     
+    async():undefined| RunServiceActionRespAsync{
+        if(this.id==3){
+            return this.kernel
+        }
+        return undefined
+    }
+    
 }
 
 
@@ -231,3 +287,556 @@ export namespace apis {
 }
 
 
+
+
+export class WatchKvRespEvents {
+    constructor(
+        public events:KvWatchEvent[],
+        public revision:number,
+        public compacted:boolean,
+    ){}
+}
+
+export class WatchKvResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    events():undefined| WatchKvRespEvents{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class WatchKvReq {
+    constructor(
+        public key:string,
+        public pattern:boolean,
+        public from_revision:number,
+        public timeout_ms:number,
+    ){}
+}
+
+export namespace apis {
+    export async function watch_kv(req:WatchKvReq):Promise<WatchKvResp>{
+        let res:any = await axios.post("/api/watch_kv", req)
+        return new WatchKvResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class GetInvocationRespRunning {
+    constructor(
+        public invocation_id:string,
+        public target:string,
+        public start_time:number,
+    ){}
+}
+
+export class GetInvocationRespSucc {
+    constructor(
+        public invocation_id:string,
+        public target:string,
+        public start_time:number,
+        public end_time:number,
+        public status:number,
+        public output:string,
+    ){}
+}
+
+export class GetInvocationRespFail {
+    constructor(
+        public invocation_id:string,
+        public target:string,
+        public start_time:number,
+        public end_time:number,
+        public msg:string,
+    ){}
+}
+
+export class GetInvocationRespNotFound {
+    constructor(
+
+    ){}
+}
+
+export class GetInvocationResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    running():undefined| GetInvocationRespRunning{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    succ():undefined| GetInvocationRespSucc{
+        if(this.id==2){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    fail():undefined| GetInvocationRespFail{
+        if(this.id==3){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    not_found():undefined| GetInvocationRespNotFound{
+        if(this.id==4){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class GetInvocationReq {
+    constructor(
+        public invocation_id:string,
+    ){}
+}
+
+export namespace apis {
+    export async function get_invocation(req:GetInvocationReq):Promise<GetInvocationResp>{
+        let res:any = await axios.post("/api/get_invocation", req)
+        return new GetInvocationResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class CancelInvocationRespSucc {
+    constructor(
+
+    ){}
+}
+
+export class CancelInvocationRespFail {
+    constructor(
+        public msg:string,
+    ){}
+}
+
+export class CancelInvocationResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    succ():undefined| CancelInvocationRespSucc{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    fail():undefined| CancelInvocationRespFail{
+        if(this.id==2){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class CancelInvocationReq {
+    constructor(
+        public invocation_id:string,
+    ){}
+}
+
+export namespace apis {
+    export async function cancel_invocation(req:CancelInvocationReq):Promise<CancelInvocationResp>{
+        let res:any = await axios.post("/api/cancel_invocation", req)
+        return new CancelInvocationResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class ListDeadLettersRespLetters {
+    constructor(
+        public letters:DeadLetter[],
+    ){}
+}
+
+export class ListDeadLettersResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    letters():undefined| ListDeadLettersRespLetters{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class ListDeadLettersReq {
+    constructor(
+        public app:string,
+    ){}
+}
+
+export namespace apis {
+    export async function list_dead_letters(req:ListDeadLettersReq):Promise<ListDeadLettersResp>{
+        let res:any = await axios.post("/api/list_dead_letters", req)
+        return new ListDeadLettersResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class GetDeadLetterRespFound {
+    constructor(
+        public letter:DeadLetter,
+        public detail:string,
+    ){}
+}
+
+export class GetDeadLetterRespNotFound {
+    constructor(
+
+    ){}
+}
+
+export class GetDeadLetterResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    found():undefined| GetDeadLetterRespFound{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    not_found():undefined| GetDeadLetterRespNotFound{
+        if(this.id==2){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class GetDeadLetterReq {
+    constructor(
+        public id:string,
+    ){}
+}
+
+export namespace apis {
+    export async function get_dead_letter(req:GetDeadLetterReq):Promise<GetDeadLetterResp>{
+        let res:any = await axios.post("/api/get_dead_letter", req)
+        return new GetDeadLetterResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class ReplayDeadLetterRespSucc {
+    constructor(
+        public node:number,
+    ){}
+}
+
+export class ReplayDeadLetterRespFail {
+    constructor(
+        public msg:string,
+    ){}
+}
+
+export class ReplayDeadLetterResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    succ():undefined| ReplayDeadLetterRespSucc{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    fail():undefined| ReplayDeadLetterRespFail{
+        if(this.id==2){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class ReplayDeadLetterReq {
+    constructor(
+        public id:string,
+    ){}
+}
+
+export namespace apis {
+    export async function replay_dead_letter(req:ReplayDeadLetterReq):Promise<ReplayDeadLetterResp>{
+        let res:any = await axios.post("/api/replay_dead_letter", req)
+        return new ReplayDeadLetterResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class GetWorkflowRespRunning {
+    constructor(
+        public workflow_id:number,
+        public invocation_id:string,
+        public start_time:number,
+        public tasks:WorkflowTask[],
+    ){}
+}
+
+export class GetWorkflowRespDone {
+    constructor(
+        public workflow_id:number,
+        public invocation_id:string,
+        public succ:boolean,
+        public start_time:number,
+        public end_time:number,
+        public tasks:WorkflowTask[],
+    ){}
+}
+
+export class GetWorkflowRespNotFound {
+    constructor(
+
+    ){}
+}
+
+export class GetWorkflowResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    running():undefined| GetWorkflowRespRunning{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    done():undefined| GetWorkflowRespDone{
+        if(this.id==2){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    not_found():undefined| GetWorkflowRespNotFound{
+        if(this.id==3){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class GetWorkflowReq {
+    constructor(
+        public workflow_id:number,
+        public wait_ms:number,
+    ){}
+}
+
+export namespace apis {
+    export async function get_workflow(req:GetWorkflowReq):Promise<GetWorkflowResp>{
+        let res:any = await axios.post("/api/get_workflow", req)
+        return new GetWorkflowResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class DeployAppRespSucc {
+    constructor(
+        public version:number,
+    ){}
+}
+
+export class DeployAppRespFail {
+    constructor(
+        public msg:string,
+    ){}
+}
+
+export class DeployAppResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    succ():undefined| DeployAppRespSucc{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    fail():undefined| DeployAppRespFail{
+        if(this.id==2){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class DeployAppReq {
+    constructor(
+        public app:string,
+        public wasm:string,
+        public yaml:string,
+    ){}
+}
+
+export namespace apis {
+    export async function deploy_app(req:DeployAppReq):Promise<DeployAppResp>{
+        let res:any = await axios.post("/api/deploy_app", req)
+        return new DeployAppResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class RollbackAppRespSucc {
+    constructor(
+        public version:number,
+    ){}
+}
+
+export class RollbackAppRespFail {
+    constructor(
+        public msg:string,
+    ){}
+}
+
+export class RollbackAppResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    succ():undefined| RollbackAppRespSucc{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    fail():undefined| RollbackAppRespFail{
+        if(this.id==2){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class RollbackAppReq {
+    constructor(
+        public app:string,
+        public version:number,
+    ){}
+}
+
+export namespace apis {
+    export async function rollback_app(req:RollbackAppReq):Promise<RollbackAppResp>{
+        let res:any = await axios.post("/api/rollback_app", req)
+        return new RollbackAppResp(res.data.kernel,res.data.id)
+    }
+}
+
+
+
+
+export class ListAppVersionsRespVersions {
+    constructor(
+        public current:number,
+        public versions:AppVersion[],
+    ){}
+}
+
+export class ListAppVersionsRespNotFound {
+    constructor(
+
+    ){}
+}
+
+export class ListAppVersionsResp{
+    constructor(
+        private kernel: any,
+        private id: number
+    ) {}
+    
+    versions():undefined| ListAppVersionsRespVersions{
+        if(this.id==1){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+    not_found():undefined| ListAppVersionsRespNotFound{
+        if(this.id==2){
+            return this.kernel
+        }
+        return undefined
+    }
+    
+}
+
+
+export class ListAppVersionsReq {
+    constructor(
+        public app:string,
+    ){}
+}
+
+export namespace apis {
+    export async function list_app_versions(req:ListAppVersionsReq):Promise<ListAppVersionsResp>{
+        let res:any = await axios.post("/api/list_app_versions", req)
+        return new ListAppVersionsResp(res.data.kernel,res.data.id)
+    }
+}
+
+